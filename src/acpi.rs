@@ -0,0 +1,276 @@
+pub mod fadt;
+pub mod madt;
+pub mod rsdp;
+
+use self::{
+    fadt::{AddressSpace, Fadt},
+    rsdp::Rsdp,
+};
+use crate::{
+    boot, error, hlt_forever, interrupts,
+    memory::{self, BootInfoFrameAllocator},
+    ps2, warn,
+};
+use spin::Mutex;
+use x86_64::{instructions::port::Port, structures::paging::PhysFrame, PhysAddr};
+
+/// Size of the header shared by all ACPI system description tables.
+pub const HEADER_SIZE: usize = 36;
+/// Size of the largest RSDP, that of ACPI 2.0 and later.
+const RSDP_MAX_SIZE: usize = 36;
+
+/// Returns the signature of the table in `bytes`, if it is long enough to have one.
+pub fn signature(bytes: &[u8]) -> Option<[u8; 4]> {
+    bytes.get(..4)?.try_into().ok()
+}
+
+/// Returns whether all bytes of the table sum up to 0, as required for a valid table.
+pub fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Returns the bytes of the table at `addr`, using the length stored in its header.
+///
+/// # Safety
+/// `addr` must point to a mapped ACPI table that stays mapped and unmodified for the rest of
+/// the kernel's lifetime.
+pub unsafe fn table_bytes(addr: *const u8) -> &'static [u8] {
+    // the length follows the 4 byte signature
+    let length = u32::from_le_bytes(*(addr.add(4) as *const [u8; 4]));
+    core::slice::from_raw_parts(addr, length as usize)
+}
+
+/// Searches the BIOS memory for the RSDP: the first KiB of the extended BIOS data area and the
+/// BIOS ROM area from 0xe0000 to 0xfffff, at 16 byte boundaries. Structures with the RSDP
+/// signature that fail validation are skipped with a warning naming the reason.
+///
+/// Returns None if no valid RSDP is found or the physical memory is not mapped yet.
+pub fn find_rsdp() -> Option<Rsdp> {
+    find_rsdp_at().map(|(_, rsdp)| rsdp)
+}
+
+/// Like `find_rsdp`, but also returns the physical address of the RSDP.
+fn find_rsdp_at() -> Option<(u64, Rsdp)> {
+    // the real mode segment of the EBDA is stored at 0x40e
+    let ebda_segment = unsafe { *memory::phys_to_virt(PhysAddr::new(0x40e))?.as_ptr::<u16>() };
+    let ebda = u64::from(ebda_segment) << 4;
+
+    let candidates = (ebda..ebda + 1024)
+        .step_by(16)
+        .chain((0xe0000..0x100000).step_by(16));
+    for addr in candidates {
+        let virt = memory::phys_to_virt(PhysAddr::new(addr))?;
+        // the largest RSDP ends before the end of the scanned areas
+        let bytes = unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), RSDP_MAX_SIZE) };
+        if !bytes.starts_with(b"RSD PTR ") {
+            continue;
+        }
+        match Rsdp::parse(bytes) {
+            Ok(rsdp) => return Some((addr, rsdp)),
+            Err(err) => warn!("rejected ACPI RSDP at {:#x}: {}", addr, err),
+        }
+    }
+    None
+}
+
+/// Returns the bytes of the table at the physical address `addr`, if its header is mapped.
+fn table_at(addr: u64) -> Option<&'static [u8]> {
+    let virt = memory::phys_to_virt(PhysAddr::new(addr))?;
+    // the firmware tables stay mapped and are never modified
+    Some(unsafe { table_bytes(virt.as_ptr()) })
+}
+
+/// Returns the first table with `signature` listed in the root table of `rsdp`: the XSDT with
+/// 8 byte entries if present, else the RSDT with 4 byte entries. Tables with an invalid
+/// checksum are skipped with a warning.
+pub fn find_table(rsdp: &Rsdp, signature: [u8; 4]) -> Option<&'static [u8]> {
+    find_table_at(rsdp, signature).map(|(_, table)| table)
+}
+
+/// Like `find_table`, but also returns the physical address of the table.
+fn find_table_at(rsdp: &Rsdp, signature: [u8; 4]) -> Option<(u64, &'static [u8])> {
+    let (root_signature, entry_size) = match rsdp.xsdt_address {
+        Some(_) => (*b"XSDT", 8),
+        None => (*b"RSDT", 4),
+    };
+    let root = table_at(rsdp.root_table_address())?;
+    if self::signature(root) != Some(root_signature) || !checksum_valid(root) {
+        warn!(
+            "rejected ACPI root table at {:#x}",
+            rsdp.root_table_address()
+        );
+        return None;
+    }
+
+    let entries = root.get(HEADER_SIZE..)?.chunks_exact(entry_size);
+    for entry in entries {
+        let addr = match entry_size {
+            8 => read_u64(entry, 0)?,
+            _ => u64::from(read_u32(entry, 0)?),
+        };
+        let table = table_at(addr)?;
+        if self::signature(table) != Some(signature) {
+            continue;
+        }
+        if checksum_valid(table) {
+            return Some((addr, table));
+        }
+        warn!("rejected ACPI table at {:#x}: invalid checksum", addr);
+    }
+    None
+}
+
+/// Pins the frames of the `len` bytes at the physical address `addr`, so that `frame_allocator`
+/// never hands them out.
+fn pin_frames(frame_allocator: &mut BootInfoFrameAllocator, addr: u64, len: usize) {
+    let start = PhysFrame::containing_address(PhysAddr::new(addr));
+    let end = PhysFrame::containing_address(PhysAddr::new(addr + len.max(1) as u64 - 1));
+    for frame in PhysFrame::range_inclusive(start, end) {
+        frame_allocator.pin(frame);
+    }
+}
+
+/// Finds the FADT through the RSDP and the root table and registers it with `set_fadt`, so
+/// that `reboot` and `shutdown` use the registers of the firmware. The frames of the tables
+/// that are found are pinned in `frame_allocator`. Requires the physical memory to be mapped.
+///
+/// Returns the FADT, or None if there is no valid one.
+pub fn init(frame_allocator: &mut BootInfoFrameAllocator) -> Option<Fadt> {
+    boot::require(boot::Stage::Memory);
+    let (rsdp_addr, rsdp) = find_rsdp_at()?;
+    pin_frames(frame_allocator, rsdp_addr, RSDP_MAX_SIZE);
+    if let Some(root) = table_at(rsdp.root_table_address()) {
+        pin_frames(frame_allocator, rsdp.root_table_address(), root.len());
+    }
+    let (addr, table) = find_table_at(&rsdp, *b"FACP")?;
+    pin_frames(frame_allocator, addr, table.len());
+    match Fadt::parse(table) {
+        Ok(fadt) => {
+            set_fadt(fadt);
+            Some(fadt)
+        }
+        Err(err) => {
+            warn!("rejected ACPI FADT: {:?}", err);
+            None
+        }
+    }
+}
+
+/// The FADT of the system, once it has been found.
+static FADT: Mutex<Option<Fadt>> = Mutex::new(None);
+
+/// Registers the FADT of the system, which is used by `reboot` and `shutdown`. Called by
+/// `init`.
+pub fn set_fadt(fadt: Fadt) {
+    interrupts::without_interrupts(|| *FADT.lock() = Some(fadt));
+}
+
+/// Returns the registered FADT, see `set_fadt`.
+pub fn fadt() -> Option<Fadt> {
+    interrupts::without_interrupts(|| *FADT.lock())
+}
+
+/// Resets the system.
+///
+/// Writes the reset value to the reset register of the FADT if it advertises reset support.
+/// Otherwise, or if the system is still running afterwards, the reset line is pulsed through the
+/// 8042 PS/2 controller. Halts if both fail.
+pub fn reboot() -> ! {
+    match fadt() {
+        Some(fadt) if fadt.supports_reset() => {
+            let reg = fadt.reset_reg;
+            match reg.address_space {
+                AddressSpace::SystemIo => {
+                    let mut port: Port<u8> = Port::new(reg.address as u16);
+                    unsafe { port.write(fadt.reset_value) };
+                }
+                AddressSpace::SystemMemory => {
+                    if let Some(addr) = memory::phys_to_virt(PhysAddr::new(reg.address)) {
+                        unsafe { addr.as_mut_ptr::<u8>().write_volatile(fadt.reset_value) };
+                    }
+                }
+                space => warn!("unsupported ACPI reset register in {:?}", space),
+            }
+        }
+        _ => {}
+    }
+
+    // pulse the CPU reset line
+    let _ = ps2::command(0xfe);
+
+    error!("reboot failed, halting");
+    x86_64::instructions::interrupts::disable();
+    hlt_forever();
+}
+
+/// `SLP_TYP` of the S5 (soft off) sleep state. It is defined by the `\_S5` object of the DSDT,
+/// which would need an AML interpreter to read. qemu defines it as 0.
+const S5_SLEEP_TYPE: u16 = 0;
+/// Bit of the PM1 control register that enters the sleep state in `SLP_TYP`.
+const SLEEP_ENABLE: u16 = 1 << 13;
+/// PM1a control register of qemu's PIIX4 power management, used without a FADT.
+const QEMU_PM1A_CONTROL: u16 = 0x604;
+
+/// Powers the system off.
+///
+/// Enters the S5 sleep state through the PM1a control register of the FADT, or of qemu if no
+/// FADT is registered. Halts if the system is still running afterwards.
+pub fn shutdown() -> ! {
+    let control = match fadt() {
+        Some(fadt) if fadt.pm1a_control_block != 0 => fadt.pm1a_control_block as u16,
+        _ => QEMU_PM1A_CONTROL,
+    };
+    let mut port: Port<u16> = Port::new(control);
+    unsafe { port.write(S5_SLEEP_TYPE << 10 | SLEEP_ENABLE) };
+
+    // the test kernels can still end qemu through the debug exit device
+    #[cfg(test)]
+    crate::exit_qemu(crate::QemuExitCode::Success);
+
+    error!("shutdown failed, halting");
+    x86_64::instructions::interrupts::disable();
+    hlt_forever();
+}
+
+/// Reads the byte at `offset` of `bytes`.
+fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+/// Reads the little endian `u16` at `offset` of `bytes`.
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// Reads the little endian `u32` at `offset` of `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Reads the little endian `u64` at `offset` of `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// -- UNIT TESTS -- //
+
+/// Test the checksum and signature helpers.
+#[test_case]
+fn acpi_checksum_and_signature() {
+    let mut table = [0u8; HEADER_SIZE];
+    table[..4].copy_from_slice(b"APIC");
+    assert_eq!(signature(&table), Some(*b"APIC"));
+    assert!(!checksum_valid(&table));
+
+    let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    table[9] = 0u8.wrapping_sub(sum);
+    assert!(checksum_valid(&table));
+
+    assert_eq!(signature(b"AP"), None);
+}
@@ -0,0 +1,152 @@
+use super::{checksum_valid, read_u32, read_u64, read_u8, signature};
+
+// offsets of the FADT fields
+const PM1A_CONTROL_BLOCK: usize = 64;
+const FLAGS: usize = 112;
+const RESET_REG: usize = 116;
+const RESET_VALUE: usize = 128;
+
+/// FADT flag set if the reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+
+/// Size of a generic address structure.
+pub const GENERIC_ADDRESS_SIZE: usize = 12;
+
+/// The address space a generic address lies in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    /// Any other (embedded controller, SMBus, functional fixed hardware, ...) address space.
+    Other(u8),
+}
+
+impl AddressSpace {
+    fn from_id(id: u8) -> Self {
+        match id {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfiguration,
+            id => AddressSpace::Other(id),
+        }
+    }
+}
+
+/// An ACPI generic address structure describing the location of a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    /// Size of the register in bits.
+    pub bit_width: u8,
+    /// Offset of the register within the addressed location in bits.
+    pub bit_offset: u8,
+    /// Access size: 0 undefined, 1 byte, 2 word, 3 dword, 4 qword.
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Parses the 12 byte generic address structure at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        Some(GenericAddress {
+            address_space: AddressSpace::from_id(read_u8(bytes, 0)?),
+            bit_width: read_u8(bytes, 1)?,
+            bit_offset: read_u8(bytes, 2)?,
+            access_size: read_u8(bytes, 3)?,
+            address: read_u64(bytes, 4)?,
+        })
+    }
+}
+
+/// The fields of the Fixed ACPI Description Table used by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// I/O port of the PM1a control register, used to enter sleep states. 0 if there is none.
+    pub pm1a_control_block: u32,
+    pub flags: u32,
+    /// Register to write `reset_value` to for a reset.
+    pub reset_reg: GenericAddress,
+    pub reset_value: u8,
+}
+
+/// Error returned when a FADT could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadtError {
+    /// The table does not have the signature "FACP".
+    WrongSignature,
+    /// The bytes of the table do not sum up to 0.
+    InvalidChecksum,
+    /// The table is too short to contain the reset register (ACPI 1.0 tables).
+    Truncated,
+}
+
+impl Fadt {
+    /// Parses the FADT in `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, FadtError> {
+        if signature(bytes) != Some(*b"FACP") {
+            return Err(FadtError::WrongSignature);
+        }
+        let length = read_u32(bytes, 4).ok_or(FadtError::Truncated)? as usize;
+        let bytes = bytes.get(..length).ok_or(FadtError::Truncated)?;
+        if !checksum_valid(bytes) {
+            return Err(FadtError::InvalidChecksum);
+        }
+
+        Ok(Fadt {
+            pm1a_control_block: read_u32(bytes, PM1A_CONTROL_BLOCK).ok_or(FadtError::Truncated)?,
+            flags: read_u32(bytes, FLAGS).ok_or(FadtError::Truncated)?,
+            reset_reg: bytes
+                .get(RESET_REG..)
+                .and_then(GenericAddress::parse)
+                .ok_or(FadtError::Truncated)?,
+            reset_value: read_u8(bytes, RESET_VALUE).ok_or(FadtError::Truncated)?,
+        })
+    }
+
+    /// Returns whether the reset register may be used to reset the system.
+    pub fn supports_reset(&self) -> bool {
+        self.flags & RESET_REG_SUP != 0
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test extracting the reset register from a synthetic FADT.
+#[test_case]
+fn fadt_reset_register() {
+    let mut table = [0u8; 244];
+    table[..4].copy_from_slice(b"FACP");
+    table[4..8].copy_from_slice(&(table.len() as u32).to_le_bytes());
+    table[FLAGS..FLAGS + 4].copy_from_slice(&RESET_REG_SUP.to_le_bytes());
+    // byte wide register at I/O port 0xcf9
+    table[RESET_REG..RESET_REG + 4].copy_from_slice(&[1, 8, 0, 1]);
+    table[RESET_REG + 4..RESET_REG + 12].copy_from_slice(&0xcf9u64.to_le_bytes());
+    table[RESET_VALUE] = 0x06;
+    table[PM1A_CONTROL_BLOCK..PM1A_CONTROL_BLOCK + 4].copy_from_slice(&0x604u32.to_le_bytes());
+    let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    table[9] = 0u8.wrapping_sub(sum);
+
+    let fadt = Fadt::parse(&table).expect("parsing the FADT failed");
+    assert!(fadt.supports_reset());
+    assert_eq!(
+        fadt.reset_reg,
+        GenericAddress {
+            address_space: AddressSpace::SystemIo,
+            bit_width: 8,
+            bit_offset: 0,
+            access_size: 1,
+            address: 0xcf9,
+        }
+    );
+    assert_eq!(fadt.reset_value, 0x06);
+    assert_eq!(fadt.pm1a_control_block, 0x604);
+
+    // an ACPI 1.0 FADT ends before the reset register
+    let mut short = [0u8; 116];
+    short[..4].copy_from_slice(b"FACP");
+    short[4..8].copy_from_slice(&(short.len() as u32).to_le_bytes());
+    let sum = short.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    short[9] = 0u8.wrapping_sub(sum);
+    assert_eq!(Fadt::parse(&short), Err(FadtError::Truncated));
+}
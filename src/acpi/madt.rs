@@ -0,0 +1,132 @@
+use super::{checksum_valid, read_u16, read_u32, read_u64, read_u8, signature, HEADER_SIZE};
+use alloc::vec::Vec;
+
+/// Offset of the first interrupt controller structure, after the header, the local APIC address
+/// and the flags.
+const ENTRIES_OFFSET: usize = HEADER_SIZE + 8;
+
+// types of the interrupt controller structures
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// Local APIC flag set if the processor is usable.
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+/// Local APIC flag set if a disabled processor can be enabled at runtime.
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// An IO APIC described by the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    /// Physical address of the MMIO registers.
+    pub address: u32,
+    /// First global system interrupt handled by this IO APIC.
+    pub gsi_base: u32,
+}
+
+/// A legacy ISA interrupt that is connected to another global system interrupt than its IRQ
+/// number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    /// The ISA IRQ.
+    pub source: u8,
+    /// The global system interrupt the IRQ is connected to.
+    pub gsi: u32,
+    /// Polarity and trigger mode (MPS INTI flags).
+    pub flags: u16,
+}
+
+/// The interrupt controllers found in the MADT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MadtInfo {
+    /// Physical address of the local APIC of each processor.
+    pub local_apic_addr: u64,
+    /// Local APIC ids of the usable processors.
+    pub cpus: Vec<u8>,
+    pub ioapics: Vec<IoApic>,
+    pub overrides: Vec<InterruptSourceOverride>,
+}
+
+/// Error returned when a MADT could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtError {
+    /// The table does not have the signature "APIC".
+    WrongSignature,
+    /// The bytes of the table do not sum up to 0.
+    InvalidChecksum,
+    /// The table or one of its structures is shorter than its type requires.
+    Truncated,
+}
+
+/// Parses the MADT in `bytes`.
+///
+/// Interrupt controller structures of unknown types are skipped.
+pub fn parse(bytes: &[u8]) -> Result<MadtInfo, MadtError> {
+    if signature(bytes) != Some(*b"APIC") {
+        return Err(MadtError::WrongSignature);
+    }
+    let length = read_u32(bytes, 4).ok_or(MadtError::Truncated)? as usize;
+    let bytes = bytes.get(..length).ok_or(MadtError::Truncated)?;
+    if !checksum_valid(bytes) {
+        return Err(MadtError::InvalidChecksum);
+    }
+
+    let mut info = MadtInfo {
+        local_apic_addr: u64::from(read_u32(bytes, HEADER_SIZE).ok_or(MadtError::Truncated)?),
+        cpus: Vec::new(),
+        ioapics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut offset = ENTRIES_OFFSET;
+    while offset < bytes.len() {
+        // every structure starts with its type and length
+        let kind = bytes[offset];
+        let entry_length = usize::from(read_u8(bytes, offset + 1).ok_or(MadtError::Truncated)?);
+        if entry_length < 2 {
+            return Err(MadtError::Truncated);
+        }
+        let entry = bytes
+            .get(offset..offset + entry_length)
+            .ok_or(MadtError::Truncated)?;
+
+        match kind {
+            LOCAL_APIC => {
+                let flags = read_u32(entry, 4).ok_or(MadtError::Truncated)?;
+                if flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0 {
+                    info.cpus
+                        .push(read_u8(entry, 3).ok_or(MadtError::Truncated)?);
+                }
+            }
+            IO_APIC => info.ioapics.push(IoApic {
+                id: read_u8(entry, 2).ok_or(MadtError::Truncated)?,
+                address: read_u32(entry, 4).ok_or(MadtError::Truncated)?,
+                gsi_base: read_u32(entry, 8).ok_or(MadtError::Truncated)?,
+            }),
+            INTERRUPT_SOURCE_OVERRIDE => info.overrides.push(InterruptSourceOverride {
+                bus: read_u8(entry, 2).ok_or(MadtError::Truncated)?,
+                source: read_u8(entry, 3).ok_or(MadtError::Truncated)?,
+                gsi: read_u32(entry, 4).ok_or(MadtError::Truncated)?,
+                flags: read_u16(entry, 8).ok_or(MadtError::Truncated)?,
+            }),
+            LOCAL_APIC_ADDRESS_OVERRIDE => {
+                info.local_apic_addr = read_u64(entry, 4).ok_or(MadtError::Truncated)?;
+            }
+            _ => {}
+        }
+        offset += entry_length;
+    }
+
+    Ok(info)
+}
+
+/// Parses the MADT at `addr`.
+///
+/// # Safety
+/// `addr` must point to a mapped ACPI table, see `acpi::table_bytes`.
+pub unsafe fn parse_at(addr: *const u8) -> Result<MadtInfo, MadtError> {
+    parse(super::table_bytes(addr))
+}
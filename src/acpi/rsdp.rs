@@ -0,0 +1,179 @@
+use super::{checksum_valid, read_u32, read_u64, read_u8};
+use core::fmt;
+
+/// Size of the ACPI 1.0 RSDP, which the checksum covers.
+const V1_SIZE: usize = 20;
+/// Size of the ACPI 2.0+ RSDP with the XSDT address.
+const V2_SIZE: usize = 36;
+
+// offsets of the RSDP fields
+const REVISION: usize = 15;
+const RSDT_ADDRESS: usize = 16;
+const LENGTH: usize = 20;
+const XSDT_ADDRESS: usize = 24;
+
+/// Physical addresses are at most 52 bits wide.
+const MAX_PHYS_ADDR: u64 = 1 << 52;
+
+/// The Root System Description Pointer, which locates the RSDT or XSDT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub rsdt_address: u32,
+    /// Only present from ACPI 2.0 on.
+    pub xsdt_address: Option<u64>,
+}
+
+/// Reason a RSDP was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsdpError {
+    /// The structure does not start with "RSD PTR ".
+    WrongSignature,
+    /// The bytes are fewer than the revision requires.
+    Truncated,
+    /// The first 20 bytes or, from ACPI 2.0 on, the whole structure do not sum up to 0.
+    InvalidChecksum,
+    /// Revision 1 is not used by any ACPI version.
+    UnsupportedRevision(u8),
+    /// The length field of an ACPI 2.0+ RSDP is shorter than the structure.
+    InvalidLength(u32),
+    /// The table address is 0 or not a physical address.
+    ImplausibleAddress(u64),
+}
+
+impl fmt::Display for RsdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RsdpError::WrongSignature => write!(f, "wrong signature"),
+            RsdpError::Truncated => write!(f, "truncated structure"),
+            RsdpError::InvalidChecksum => write!(f, "invalid checksum"),
+            RsdpError::UnsupportedRevision(revision) => {
+                write!(f, "unsupported revision {}", revision)
+            }
+            RsdpError::InvalidLength(length) => write!(f, "invalid length {}", length),
+            RsdpError::ImplausibleAddress(addr) => {
+                write!(f, "implausible table address {:#x}", addr)
+            }
+        }
+    }
+}
+
+impl Rsdp {
+    /// Parses and validates the RSDP in `bytes`. Besides the checksums the revision, the
+    /// length and the table addresses are checked, so that a malformed structure with a
+    /// matching checksum is not trusted either.
+    pub fn parse(bytes: &[u8]) -> Result<Self, RsdpError> {
+        if bytes.get(..8) != Some(b"RSD PTR ") {
+            return Err(RsdpError::WrongSignature);
+        }
+        let v1 = bytes.get(..V1_SIZE).ok_or(RsdpError::Truncated)?;
+        if !checksum_valid(v1) {
+            return Err(RsdpError::InvalidChecksum);
+        }
+
+        let revision = read_u8(bytes, REVISION).ok_or(RsdpError::Truncated)?;
+        let rsdt_address = read_u32(bytes, RSDT_ADDRESS).ok_or(RsdpError::Truncated)?;
+        let xsdt_address = match revision {
+            0 => None,
+            1 => return Err(RsdpError::UnsupportedRevision(revision)),
+            _ => {
+                let length = read_u32(bytes, LENGTH).ok_or(RsdpError::Truncated)?;
+                if (length as usize) < V2_SIZE {
+                    return Err(RsdpError::InvalidLength(length));
+                }
+                let bytes = bytes.get(..length as usize).ok_or(RsdpError::Truncated)?;
+                if !checksum_valid(bytes) {
+                    return Err(RsdpError::InvalidChecksum);
+                }
+                Some(read_u64(bytes, XSDT_ADDRESS).ok_or(RsdpError::Truncated)?)
+            }
+        };
+
+        // the XSDT replaces the RSDT, which may be left empty then
+        match xsdt_address {
+            Some(addr) if addr == 0 || addr >= MAX_PHYS_ADDR => {
+                return Err(RsdpError::ImplausibleAddress(addr))
+            }
+            None if rsdt_address == 0 => {
+                return Err(RsdpError::ImplausibleAddress(rsdt_address.into()))
+            }
+            _ => {}
+        }
+
+        Ok(Rsdp {
+            revision,
+            rsdt_address,
+            xsdt_address,
+        })
+    }
+
+    /// Returns the physical address of the root table, the XSDT if present and else the RSDT.
+    pub fn root_table_address(&self) -> u64 {
+        self.xsdt_address
+            .unwrap_or_else(|| u64::from(self.rsdt_address))
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Returns an ACPI 2.0 RSDP with valid checksums pointing at `xsdt`.
+#[cfg(test)]
+fn rsdp_v2(xsdt: u64) -> [u8; V2_SIZE] {
+    let mut rsdp = [0u8; V2_SIZE];
+    rsdp[..8].copy_from_slice(b"RSD PTR ");
+    rsdp[REVISION] = 2;
+    rsdp[RSDT_ADDRESS..RSDT_ADDRESS + 4].copy_from_slice(&0x7fe_0000u32.to_le_bytes());
+    rsdp[LENGTH..LENGTH + 4].copy_from_slice(&(V2_SIZE as u32).to_le_bytes());
+    rsdp[XSDT_ADDRESS..XSDT_ADDRESS + 8].copy_from_slice(&xsdt.to_le_bytes());
+    fix_checksums(&mut rsdp);
+    rsdp
+}
+
+/// Sets the checksum byte of the ACPI 1.0 part (offset 8) and the extended one (offset 32).
+#[cfg(test)]
+fn fix_checksums(rsdp: &mut [u8; V2_SIZE]) {
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    rsdp[8] = 0;
+    rsdp[8] = 0u8.wrapping_sub(sum(&rsdp[..V1_SIZE]));
+    rsdp[32] = 0;
+    rsdp[32] = 0u8.wrapping_sub(sum(&rsdp[..]));
+}
+
+/// Test parsing a well-formed ACPI 2.0 RSDP.
+#[test_case]
+fn rsdp_valid() {
+    let rsdp = Rsdp::parse(&rsdp_v2(0x7fe_1000)).expect("parsing the RSDP failed");
+    assert_eq!(rsdp.revision, 2);
+    assert_eq!(rsdp.rsdt_address, 0x7fe_0000);
+    assert_eq!(rsdp.root_table_address(), 0x7fe_1000);
+}
+
+/// Test that malformed structures with valid checksums are rejected with the reason.
+#[test_case]
+fn rsdp_malformed_rejected() {
+    assert_eq!(
+        Rsdp::parse(&rsdp_v2(0)),
+        Err(RsdpError::ImplausibleAddress(0))
+    );
+    assert_eq!(
+        Rsdp::parse(&rsdp_v2(u64::MAX)),
+        Err(RsdpError::ImplausibleAddress(u64::MAX))
+    );
+
+    let mut bad_revision = rsdp_v2(0x7fe_1000);
+    bad_revision[REVISION] = 1;
+    fix_checksums(&mut bad_revision);
+    assert_eq!(
+        Rsdp::parse(&bad_revision),
+        Err(RsdpError::UnsupportedRevision(1))
+    );
+
+    let mut zero_length = rsdp_v2(0x7fe_1000);
+    zero_length[LENGTH..LENGTH + 4].copy_from_slice(&0u32.to_le_bytes());
+    fix_checksums(&mut zero_length);
+    assert_eq!(Rsdp::parse(&zero_length), Err(RsdpError::InvalidLength(0)));
+
+    let mut corrupted = rsdp_v2(0x7fe_1000);
+    corrupted[XSDT_ADDRESS] ^= 1;
+    assert_eq!(Rsdp::parse(&corrupted), Err(RsdpError::InvalidChecksum));
+}
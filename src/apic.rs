@@ -0,0 +1,328 @@
+use crate::{
+    boot,
+    idt::{self, InterruptIndex, PIC_1_OFFSET},
+    interrupts, memory, pit, time, vectors,
+};
+use core::{
+    arch::x86_64::__cpuid,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use x86_64::{
+    instructions::port::Port,
+    registers::model_specific::Msr,
+    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB},
+    PhysAddr,
+};
+
+/// The model specific register holding the physical base address of the local APIC.
+const APIC_BASE_MSR: u32 = 0x1b;
+/// Bit of the APIC base MSR that globally enables the local APIC.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// Bits of the APIC base MSR holding the page aligned base address.
+const APIC_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// offsets of the local APIC registers
+const EOI: usize = 0xb0;
+const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const INITIAL_COUNT: usize = 0x380;
+const CURRENT_COUNT: usize = 0x390;
+const DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+/// Bit of the spurious interrupt vector register that software enables the local APIC.
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// LVT flag that masks the interrupt.
+const LVT_MASKED: u32 = 1 << 16;
+/// LVT delivery mode passing the interrupt on as an NMI.
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+/// LVT delivery mode taking the vector from an external 8259 PIC.
+const LVT_DELIVERY_EXTINT: u32 = 0b111 << 8;
+/// LVT timer flag selecting periodic instead of one-shot mode.
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+// fields of the interrupt command register
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Vector of the spurious interrupts raised by the local APIC.
+pub const SPURIOUS_VECTOR: u8 = crate::vectors::APIC_SPURIOUS;
+
+/// Divider of the timer clock used by `calibrate`.
+pub const CALIBRATION_DIVIDE: u8 = 16;
+
+// PIT channel 2, whose gate and output are controlled through the keyboard controller port B
+const PIT_CHANNEL2_DATA: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PORT_B: u16 = 0x61;
+/// Longest interval channel 2 can time in one go, the count must fit into 16 bits.
+const PIT_MAX_MS: u32 = 50;
+/// IRQ of the PIT timer interrupt, which the local APIC timer replaces.
+const PIT_IRQ: u8 = InterruptIndex::Timer as u8 - PIC_1_OFFSET;
+/// TSC cycles `pit_wait` waits for channel 2 to reach its terminal count. `PIT_MAX_MS` take a
+/// quarter of that at 5 GHz.
+const PIT_WAIT_TIMEOUT_CYCLES: u64 = 1_000_000_000;
+/// Interval `start_tick_timer` calibrates the timer over.
+const TICK_CALIBRATION_MS: u32 = 10;
+
+/// Set once a core asked all other cores to halt.
+static HALT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Address of the local APIC registers, 0 while the APIC is not enabled. The registers are
+/// identity mapped, so this is both the physical and the virtual address.
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the processor has a local APIC (CPUID leaf 1, bit 9 of edx).
+pub fn has_local_apic() -> bool {
+    unsafe { __cpuid(0x01) }.edx & (1 << 9) != 0
+}
+
+/// Returns whether the local APIC is enabled and delivers interrupts.
+pub fn is_enabled() -> bool {
+    BASE.load(Ordering::Acquire) != 0
+}
+
+/// Enables the local APIC and makes it the active interrupt controller.
+///
+/// There is no I/O APIC routing, so the legacy IRQs keep coming from the 8259 PIC, which is
+/// passed through LINT0 (virtual wire mode). Their lines stay as they are, see
+/// `interrupts::mask_irq`.
+///
+/// Returns false and leaves the PIC in charge if the processor has no local APIC.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> bool {
+    // spurious interrupts need a handler
+    boot::require(boot::Stage::Idt);
+    boot::require(boot::Stage::Memory);
+    if !has_local_apic() {
+        return false;
+    }
+    if is_enabled() {
+        return true;
+    }
+
+    let mut msr = Msr::new(APIC_BASE_MSR);
+    let base = unsafe { msr.read() };
+    let addr = base & APIC_BASE_ADDR_MASK;
+    match unsafe { memory::id_map_uncached(PhysAddr::new(addr), mapper, frame_allocator) } {
+        Ok(()) => {}
+        Err(MapToError::PageAlreadyMapped(frame)) if frame.start_address().as_u64() == addr => {}
+        Err(err) => panic!("mapping the local APIC registers failed: {:?}", err),
+    }
+
+    let _guard = interrupts::guard();
+    unsafe { msr.write(base | APIC_BASE_ENABLE) };
+    BASE.store(addr, Ordering::Release);
+    write(
+        SPURIOUS_INTERRUPT_VECTOR,
+        SVR_ENABLE | u32::from(SPURIOUS_VECTOR),
+    );
+    // the PIC delivers through LINT0, NMIs from the chipset come in on LINT1
+    write(LVT_LINT0, LVT_DELIVERY_EXTINT);
+    write(LVT_LINT1, LVT_DELIVERY_NMI);
+    interrupts::set_controller(interrupts::InterruptController::Apic);
+    true
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+pub fn send_eoi() {
+    write(EOI, 0);
+}
+
+/// Calibrates the local APIC timer and lets it drive the kernel ticks in place of the PIT, see
+/// `start_timer`.
+///
+/// Returns false and leaves the PIT in charge if the calibration failed.
+///
+/// Panics if the local APIC is not enabled.
+pub fn start_tick_timer() -> bool {
+    let cycles = match calibrate(TICK_CALIBRATION_MS) {
+        Some(cycles) => u64::from(cycles),
+        None => return false,
+    };
+    let tick_micros = time::ticks_to_duration(1).as_micros() as u64;
+    let initial_count = cycles * tick_micros / (u64::from(TICK_CALIBRATION_MS) * 1000);
+    start_timer(
+        vectors::APIC_TIMER,
+        initial_count as u32,
+        CALIBRATION_DIVIDE,
+    );
+    true
+}
+
+/// Starts the local APIC timer in periodic mode. It raises the interrupt `vector` every
+/// `initial_count` cycles of the bus clock divided by `divide` (1, 2, 4, ..., 128). The handler
+/// for `vector` advances the kernel ticks like the PIT timer interrupt, so `initial_count` should
+/// match the length of a PIT tick, see `calibrate`. The PIT timer interrupt is masked until the
+/// timer is stopped again.
+///
+/// Panics if the local APIC is not enabled or `divide` is not a power of two up to 128.
+pub fn start_timer(vector: u8, initial_count: u32, divide: u8) {
+    let divide_config = divide_configuration(divide);
+    idt::set_handler(vector, idt::apic_timer_handler);
+
+    let _guard = interrupts::guard();
+    interrupts::mask_irq(PIT_IRQ);
+    write(DIVIDE_CONFIGURATION, divide_config);
+    write(LVT_TIMER, LVT_TIMER_PERIODIC | u32::from(vector));
+    // writing the initial count starts the timer
+    write(INITIAL_COUNT, initial_count);
+}
+
+/// Stops the local APIC timer and hands the kernel ticks back to the PIT.
+pub fn stop_timer() {
+    let _guard = interrupts::guard();
+    halt_timer();
+    interrupts::unmask_irq(PIT_IRQ);
+}
+
+/// Returns the number of local APIC timer cycles, with a divide value of `CALIBRATION_DIVIDE`,
+/// that elapse in `pit_ms` milliseconds. The interval is timed with channel 2 of the PIT, which
+/// does not raise interrupts.
+///
+/// Returns None if channel 2 did not reach its terminal count in time.
+///
+/// Panics if the local APIC is not enabled.
+pub fn calibrate(pit_ms: u32) -> Option<u32> {
+    let _guard = interrupts::guard();
+    start_timer_masked();
+
+    let mut remaining = pit_ms;
+    while remaining > 0 {
+        let ms = remaining.min(PIT_MAX_MS);
+        if !pit_wait(ms) {
+            halt_timer();
+            return None;
+        }
+        remaining -= ms;
+    }
+
+    let elapsed = u32::MAX - read(CURRENT_COUNT);
+    halt_timer();
+    Some(elapsed)
+}
+
+/// Stops the local APIC timer.
+fn halt_timer() {
+    write(LVT_TIMER, LVT_MASKED);
+    write(INITIAL_COUNT, 0);
+}
+
+/// Returns the value of the divide configuration register for the timer clock divider `divide`.
+fn divide_configuration(divide: u8) -> u32 {
+    // the divide value is encoded in bits 0, 1 and 3
+    match divide {
+        1 => 0b1011,
+        2 => 0b0000,
+        4 => 0b0001,
+        8 => 0b0010,
+        16 => 0b0011,
+        32 => 0b1000,
+        64 => 0b1001,
+        128 => 0b1010,
+        _ => panic!("invalid APIC timer divide value {}", divide),
+    }
+}
+
+/// Starts the timer as a masked one-shot timer counting down from `u32::MAX`.
+fn start_timer_masked() {
+    write(
+        DIVIDE_CONFIGURATION,
+        divide_configuration(CALIBRATION_DIVIDE),
+    );
+    write(LVT_TIMER, LVT_MASKED);
+    write(INITIAL_COUNT, u32::MAX);
+}
+
+/// Busy waits `ms` (at most `PIT_MAX_MS`) milliseconds using channel 2 of the PIT.
+///
+/// Returns false if the channel did not reach its terminal count within
+/// `PIT_WAIT_TIMEOUT_CYCLES`.
+fn pit_wait(ms: u32) -> bool {
+    let mut port_b: Port<u8> = Port::new(PORT_B);
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut data: Port<u8> = Port::new(PIT_CHANNEL2_DATA);
+
+    let count = (pit::BASE_FREQUENCY * u64::from(ms) / 1000) as u16;
+    unsafe {
+        // disable the speaker and the gate of channel 2
+        let b = port_b.read() & !0b11;
+        port_b.write(b);
+        // channel 2, low and high byte, mode 0 (interrupt on terminal count), binary
+        command.write(0b1011_0000);
+        let [low, high] = count.to_le_bytes();
+        data.write(low);
+        data.write(high);
+        // raising the gate starts counting
+        port_b.write(b | 0b01);
+        // bit 5 reflects the output of channel 2, which goes high at the terminal count
+        let stopwatch = time::Stopwatch::start();
+        let mut done = false;
+        while !done && stopwatch.elapsed_cycles() <= PIT_WAIT_TIMEOUT_CYCLES {
+            done = port_b.read() & 0x20 != 0;
+            core::hint::spin_loop();
+        }
+        port_b.write(b);
+        done
+    }
+}
+
+/// Returns a pointer to the local APIC register at `offset`.
+fn register(offset: usize) -> *mut u32 {
+    let base = BASE.load(Ordering::Acquire);
+    assert_ne!(base, 0, "the local APIC is not enabled");
+    (base as usize + offset) as *mut u32
+}
+
+/// Reads the local APIC register at `offset`.
+fn read(offset: usize) -> u32 {
+    unsafe { register(offset).read_volatile() }
+}
+
+/// Writes `value` to the local APIC register at `offset`.
+fn write(offset: usize, value: u32) {
+    unsafe { register(offset).write_volatile(value) }
+}
+
+/// Stops all other cores by sending them an NMI, which cannot be masked. Their NMI handler
+/// sees the request, see `halt_requested`, and halts for good. Called on panic so that no core
+/// keeps running on a broken kernel.
+///
+/// Does nothing if the local APIC is not enabled, in which case only this core runs. The kernel
+/// does not start the application processors yet, so for now no core acts on the NMI.
+pub fn halt_other_cores() {
+    HALT_REQUESTED.store(true, Ordering::SeqCst);
+    if !is_enabled() {
+        return;
+    }
+
+    let _guard = interrupts::guard();
+    // the destination is given by the shorthand
+    write(INTERRUPT_COMMAND_HIGH, 0);
+    write(
+        INTERRUPT_COMMAND_LOW,
+        ICR_DELIVERY_NMI | ICR_LEVEL_ASSERT | ICR_ALL_EXCLUDING_SELF,
+    );
+    while read(INTERRUPT_COMMAND_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns whether `halt_other_cores` was called. An NMI received afterwards is a request to
+/// halt.
+pub fn halt_requested() -> bool {
+    HALT_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Returns whether the local APIC is software enabled.
+pub fn is_software_enabled() -> bool {
+    is_enabled() && read(SPURIOUS_INTERRUPT_VECTOR) & SVR_ENABLE != 0
+}
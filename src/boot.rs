@@ -0,0 +1,249 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+/// A step of the kernel initialization. Stages that depend on another stage check that it has
+/// completed with `require`, so that reordering the initialization fails loudly instead of
+/// breaking subtly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Stage {
+    /// The IDT is loaded.
+    Idt,
+    /// The GDT and TSS are loaded.
+    Gdt,
+    /// The interrupt controller is initialized.
+    Pic,
+    /// External interrupts are enabled.
+    Interrupts,
+    /// The wall clock is synchronized with the RTC.
+    Clock,
+    /// The page table of the kernel is accessible.
+    Memory,
+    /// The kernel heap is mapped and initialized.
+    Heap,
+}
+
+/// Bit `n` is set when the stage with discriminant `n` has completed.
+static COMPLETED: AtomicU32 = AtomicU32::new(0);
+
+impl Stage {
+    fn bit(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// Marks `stage` as completed.
+pub fn complete(stage: Stage) {
+    COMPLETED.fetch_or(stage.bit(), Ordering::SeqCst);
+}
+
+/// Returns whether `stage` has completed.
+pub fn is_complete(stage: Stage) -> bool {
+    COMPLETED.load(Ordering::SeqCst) & stage.bit() != 0
+}
+
+/// Panics with a message naming `stage` if it has not completed yet. Called at the start of
+/// every stage for each of its prerequisites.
+#[track_caller]
+pub fn require(stage: Stage) {
+    if !is_complete(stage) {
+        panic!(
+            "boot order violated: init stage {:?} is required but has not run yet",
+            stage
+        );
+    }
+}
+
+/// Maximum number of options kept by `BootArgs`. Further options are ignored.
+pub const MAX_BOOT_OPTIONS: usize = 16;
+
+/// The options of a kernel command line, see `parse_cmdline`. Options are either boolean flags
+/// like `quiet` or `key=value` pairs. Values containing spaces can be quoted: `key="a b"`.
+#[derive(Debug, Clone, Copy)]
+pub struct BootArgs<'a> {
+    options: [(&'a str, Option<&'a str>); MAX_BOOT_OPTIONS],
+    len: usize,
+}
+
+impl<'a> BootArgs<'a> {
+    /// Returns the arguments of an empty command line.
+    pub const fn empty() -> Self {
+        BootArgs {
+            options: [("", None); MAX_BOOT_OPTIONS],
+            len: 0,
+        }
+    }
+
+    /// Returns the options in command line order as key and value, if any.
+    pub fn options(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> + '_ {
+        self.options[..self.len].iter().copied()
+    }
+
+    /// Returns the value of the option `key`. If the option is repeated, the last value counts.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.options()
+            .filter(|(option, _)| *option == key)
+            .last()
+            .and_then(|(_, value)| value)
+    }
+
+    /// Returns whether the flag `key` is set. A flag is set if it is present, unless its last
+    /// occurrence has the value `0`, `false` or `no`.
+    pub fn flag(&self, key: &str) -> bool {
+        match self.options().filter(|(option, _)| *option == key).last() {
+            Some((_, Some(value))) => !matches!(value, "0" | "false" | "no"),
+            Some((_, None)) => true,
+            None => false,
+        }
+    }
+
+    /// Whether the verbose boot messages are suppressed.
+    pub fn quiet(&self) -> bool {
+        self.flag("quiet")
+    }
+
+    /// Whether the output to the screen is mirrored to the serial port.
+    pub fn serial_console(&self) -> bool {
+        self.flag("serial_console")
+    }
+
+    /// The requested log level, if given as a number.
+    pub fn loglevel(&self) -> Option<u8> {
+        self.get("loglevel")?.parse().ok()
+    }
+}
+
+/// Parses the options of the command line `cmdline`, see `BootArgs`. An unterminated quote
+/// extends to the end of the command line.
+pub fn parse_cmdline(cmdline: &str) -> BootArgs<'_> {
+    let mut args = BootArgs::empty();
+    let mut rest = cmdline.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let (value, remaining) = match value.strip_prefix('"') {
+                    Some(quoted) => match quoted.find('"') {
+                        Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                        None => (quoted, ""),
+                    },
+                    None => {
+                        let end = value
+                            .find(|c: char| c.is_ascii_whitespace())
+                            .unwrap_or(value.len());
+                        value.split_at(end)
+                    }
+                };
+                rest = remaining;
+                Some(value)
+            }
+            None => None,
+        };
+
+        if !key.is_empty() && args.len < MAX_BOOT_OPTIONS {
+            args.options[args.len] = (key, value);
+            args.len += 1;
+        }
+        rest = rest.trim_start();
+    }
+    args
+}
+
+/// The kernel command line and its parsed options, see `set_cmdline`.
+static CMDLINE: Mutex<(&str, BootArgs<'static>)> = Mutex::new(("", BootArgs::empty()));
+
+/// Sets the kernel command line and applies the options that take effect right away, e.g.
+/// `serial_console` and `loglevel`. Should be called first thing during boot.
+pub fn set_cmdline(cmdline: &'static str) {
+    let args = parse_cmdline(cmdline);
+    *CMDLINE.lock() = (cmdline, args);
+    crate::vga_buffer::set_serial_mirror(args.serial_console());
+    if let Some(level) = args.loglevel() {
+        crate::log::set_level(crate::log::LogLevel::from_number(level));
+    }
+}
+
+/// Returns the kernel command line, empty if none has been set.
+pub fn cmdline() -> &'static str {
+    CMDLINE.lock().0
+}
+
+/// Returns the options of the kernel command line.
+pub fn args() -> BootArgs<'static> {
+    CMDLINE.lock().1
+}
+
+/// Prints to the VGA text buffer like `print!`, unless the `quiet` boot option is set.
+#[macro_export]
+macro_rules! status_print {
+    ($($arg:tt)*) => {
+        if !$crate::boot::args().quiet() {
+            $crate::print!($($arg)*);
+        }
+    };
+}
+
+/// Prints to the VGA text buffer like `println!`, unless the `quiet` boot option is set.
+#[macro_export]
+macro_rules! status_println {
+    ($($arg:tt)*) => {
+        if !$crate::boot::args().quiet() {
+            $crate::println!($($arg)*);
+        }
+    };
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the stages run by `init()` are marked as completed.
+#[test_case]
+fn boot_stages_completed_by_init() {
+    for stage in [
+        Stage::Idt,
+        Stage::Gdt,
+        Stage::Pic,
+        Stage::Interrupts,
+        Stage::Clock,
+    ] {
+        assert!(is_complete(stage));
+        require(stage);
+    }
+    // the unit tests run without a heap
+    assert!(!is_complete(Stage::Heap));
+}
+
+/// Test the command line parser with flags, values, quoting and repeated keys.
+#[test_case]
+fn boot_parse_cmdline() {
+    let args = parse_cmdline(
+        r#"  quiet loglevel=3 title="tRust kernel" loglevel=5 serial_console=no x= "#,
+    );
+    let options = [
+        ("quiet", None),
+        ("loglevel", Some("3")),
+        ("title", Some("tRust kernel")),
+        ("loglevel", Some("5")),
+        ("serial_console", Some("no")),
+        ("x", Some("")),
+    ];
+    assert!(args.options().eq(options.iter().copied()));
+
+    assert!(args.quiet());
+    // the last occurrence counts
+    assert_eq!(args.loglevel(), Some(5));
+    assert_eq!(args.get("title"), Some("tRust kernel"));
+    assert!(!args.serial_console());
+    assert!(!args.flag("missing"));
+    assert_eq!(args.get("quiet"), None);
+
+    // an unterminated quote extends to the end
+    let args = parse_cmdline(r#"title="a b loglevel=x"#);
+    assert_eq!(args.get("title"), Some("a b loglevel=x"));
+    assert_eq!(args.loglevel(), None);
+    assert_eq!(parse_cmdline("").options().count(), 0);
+}
@@ -0,0 +1,125 @@
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of distinct counter names that can be registered.
+const MAX_COUNTERS: usize = 32;
+
+/// Name pointers of the registered counters. A slot is claimed by swapping in the pointer of its
+/// name, so that registering never takes a lock and is safe in any interrupt context, NMIs
+/// included. The index of a name is the index of its value in `VALUES`.
+static NAMES: [AtomicPtr<u8>; MAX_COUNTERS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_COUNTERS];
+/// Name lengths of the registered counters plus one. 0 while the slot is free or its claim has
+/// not been published yet.
+static LENGTHS: [AtomicUsize; MAX_COUNTERS] = [const { AtomicUsize::new(0) }; MAX_COUNTERS];
+/// Values of the registered counters.
+static VALUES: [AtomicU64; MAX_COUNTERS] = [const { AtomicU64::new(0) }; MAX_COUNTERS];
+
+/// Returns the name of the counter in slot `i`, or None if the slot has no published name.
+fn name(i: usize) -> Option<&'static str> {
+    let len = LENGTHS[i].load(Ordering::Acquire);
+    if len == 0 {
+        return None;
+    }
+    let ptr = NAMES[i].load(Ordering::Relaxed);
+    // SAFETY: pointer and length were taken from a `&'static str` before being published
+    Some(unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len - 1)) })
+}
+
+/// Returns the slot of the counter `name`. Registers the counter when `register` is set and the
+/// name is not known yet.
+///
+/// Returns None when the counter does not exist (and was not registered) or the registry is full.
+/// A claim that is not yet published is skipped instead of waited for, as the claiming code may be
+/// the one that got interrupted. The same name can therefore end up in two slots; readers add them
+/// up.
+fn slot(name: &'static str, register: bool) -> Option<usize> {
+    for i in 0..MAX_COUNTERS {
+        // slots are claimed in order, so a free slot ends the registered names
+        if NAMES[i].load(Ordering::Acquire).is_null() {
+            if !register {
+                return None;
+            }
+            let claimed = NAMES[i].compare_exchange(
+                ptr::null_mut(),
+                name.as_ptr() as *mut u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if claimed.is_ok() {
+                LENGTHS[i].store(name.len() + 1, Ordering::Release);
+                return Some(i);
+            }
+        }
+        if self::name(i) == Some(name) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Increments the counter `name` by one, registering it on first use.
+///
+/// This neither allocates nor locks and is therefore safe to call from interrupt handlers.
+/// Increments of new counters are dropped once all slots are in use.
+pub fn inc(name: &'static str) {
+    add(name, 1);
+}
+
+/// Adds `value` to the counter `name`, registering it on first use.
+pub fn add(name: &'static str, value: u64) {
+    if let Some(i) = slot(name, true) {
+        VALUES[i].fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+/// Returns the current value of the counter `name`. Unknown counters read as 0.
+pub fn get(name: &'static str) -> u64 {
+    (0..MAX_COUNTERS)
+        .filter(|&i| self::name(i) == Some(name))
+        .map(|i| VALUES[i].load(Ordering::Relaxed))
+        .sum()
+}
+
+/// Calls `f` with the name and current value of every registered counter.
+pub fn for_each(mut f: impl FnMut(&'static str, u64)) {
+    for i in 0..MAX_COUNTERS {
+        if let Some(name) = name(i) {
+            // a name registered twice is reported once, at its first slot
+            if !(0..i).any(|j| self::name(j) == Some(name)) {
+                f(name, get(name));
+            }
+        }
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that an unknown counter reads as 0 and is not registered by reading it.
+#[test_case]
+fn counter_unknown_is_zero() {
+    assert_eq!(get("test_unknown_counter"), 0);
+    assert_eq!(slot("test_unknown_counter", false), None);
+}
+
+/// Test incrementing a counter the way an interrupt handler would.
+#[test_case]
+fn counter_inc_from_irq_path() {
+    let before = get("test_irq_counter");
+    crate::interrupts::without_interrupts(|| {
+        inc("test_irq_counter");
+        inc("test_irq_counter");
+    });
+    assert_eq!(get("test_irq_counter"), before + 2);
+}
+
+/// Test that incrementing a counter repeatedly claims a single slot.
+#[test_case]
+fn counter_registered_once() {
+    inc("test_once_counter");
+    inc("test_once_counter");
+    let slots = (0..MAX_COUNTERS)
+        .filter(|&i| name(i) == Some("test_once_counter"))
+        .count();
+    assert_eq!(slots, 1);
+}
@@ -0,0 +1,335 @@
+use crate::{bitmask, println, util::FixedString};
+use core::{
+    arch::x86_64::{__cpuid, __cpuid_count},
+    fmt,
+};
+
+/// Returns the highest basic CPUID leaf supported by the processor.
+fn max_leaf() -> u32 {
+    unsafe { __cpuid(0) }.eax
+}
+
+/// Returns the CPU vendor string, e.g. "GenuineIntel" or "AuthenticAMD".
+pub fn vendor() -> FixedString<12> {
+    let result = unsafe { __cpuid(0) };
+    // the vendor string is stored in ebx, edx, ecx in that order
+    let mut bytes = [0; 12];
+    bytes[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    bytes[4..8].copy_from_slice(&result.edx.to_le_bytes());
+    bytes[8..12].copy_from_slice(&result.ecx.to_le_bytes());
+
+    let mut vendor = FixedString::new();
+    vendor.push_str(core::str::from_utf8(&bytes).unwrap_or("unknown"));
+    vendor
+}
+
+/// Returns the highest extended CPUID leaf supported by the processor, or 0 if there are none.
+fn max_extended_leaf() -> u32 {
+    let max = unsafe { __cpuid(0x8000_0000) }.eax;
+    // processors without extended leaves return data of a basic leaf instead
+    if max & 0x8000_0000 != 0 {
+        max
+    } else {
+        0
+    }
+}
+
+/// Returns the processor brand string, e.g. "QEMU Virtual CPU version 2.5+", or "unknown" if
+/// the processor does not report one.
+pub fn brand() -> FixedString<48> {
+    let mut brand = FixedString::new();
+    if max_extended_leaf() < 0x8000_0004 {
+        brand.push_str("unknown");
+        return brand;
+    }
+
+    // the 48 bytes are spread over eax, ebx, ecx, edx of three leaves
+    let mut bytes = [0; 48];
+    for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+        let result = unsafe { __cpuid(leaf) };
+        for (j, register) in [result.eax, result.ebx, result.ecx, result.edx]
+            .iter()
+            .enumerate()
+        {
+            let offset = i * 16 + j * 4;
+            bytes[offset..offset + 4].copy_from_slice(&register.to_le_bytes());
+        }
+    }
+
+    // the string is NUL terminated and some vendors pad it with spaces
+    let trimmed = core::str::from_utf8(&bytes)
+        .map(|string| string.trim_matches(|c| c == ' ' || c == '\0'))
+        .unwrap_or("");
+    brand.push_str(if trimmed.is_empty() {
+        "unknown"
+    } else {
+        trimmed
+    });
+    brand
+}
+
+/// A set of CPU features as advertised by CPUID, see `cpu_features`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeatures(u32);
+
+impl CpuFeatures {
+    pub const SSE: CpuFeatures = CpuFeatures(1 << 0);
+    pub const SSE2: CpuFeatures = CpuFeatures(1 << 1);
+    pub const SSE3: CpuFeatures = CpuFeatures(1 << 2);
+    pub const SSSE3: CpuFeatures = CpuFeatures(1 << 3);
+    pub const SSE4_1: CpuFeatures = CpuFeatures(1 << 4);
+    pub const SSE4_2: CpuFeatures = CpuFeatures(1 << 5);
+    pub const AVX: CpuFeatures = CpuFeatures(1 << 6);
+    pub const AVX2: CpuFeatures = CpuFeatures(1 << 7);
+    pub const FMA: CpuFeatures = CpuFeatures(1 << 8);
+    pub const APIC: CpuFeatures = CpuFeatures(1 << 9);
+    pub const X2APIC: CpuFeatures = CpuFeatures(1 << 10);
+    pub const RDRAND: CpuFeatures = CpuFeatures(1 << 11);
+    pub const RDSEED: CpuFeatures = CpuFeatures(1 << 12);
+    pub const FSGSBASE: CpuFeatures = CpuFeatures(1 << 13);
+    pub const NX: CpuFeatures = CpuFeatures(1 << 14);
+
+    /// All features with their names, in the order they are printed.
+    const NAMES: [(CpuFeatures, &'static str); 15] = [
+        (Self::SSE, "sse"),
+        (Self::SSE2, "sse2"),
+        (Self::SSE3, "sse3"),
+        (Self::SSSE3, "ssse3"),
+        (Self::SSE4_1, "sse4.1"),
+        (Self::SSE4_2, "sse4.2"),
+        (Self::AVX, "avx"),
+        (Self::AVX2, "avx2"),
+        (Self::FMA, "fma"),
+        (Self::APIC, "apic"),
+        (Self::X2APIC, "x2apic"),
+        (Self::RDRAND, "rdrand"),
+        (Self::RDSEED, "rdseed"),
+        (Self::FSGSBASE, "fsgsbase"),
+        (Self::NX, "nx"),
+    ];
+
+    /// Returns the empty set.
+    pub const fn empty() -> Self {
+        CpuFeatures(0)
+    }
+
+    /// Returns the raw bits of the set.
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether all features of `other` are in the set.
+    pub const fn contains(&self, other: CpuFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds the features of `other` to the set if `condition` holds.
+    fn set_if(&mut self, other: CpuFeatures, condition: bool) {
+        if condition {
+            self.0 |= other.0;
+        }
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    /// Writes the names of the features in the set, separated by spaces.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Self::NAMES
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name);
+        if let Some(first) = names.next() {
+            write!(f, "{}", first)?;
+        }
+        for name in names {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the features advertised in leaf 0x01, leaf 0x07 and the extended leaf 0x8000_0001.
+/// Leaves the processor does not support report no features.
+pub fn cpu_features() -> CpuFeatures {
+    let mut features = CpuFeatures::empty();
+
+    let leaf_1 = unsafe { __cpuid(0x01) };
+    features.set_if(CpuFeatures::SSE, leaf_1.edx & bitmask!(u32; 25) != 0);
+    features.set_if(CpuFeatures::SSE2, leaf_1.edx & bitmask!(u32; 26) != 0);
+    features.set_if(CpuFeatures::APIC, leaf_1.edx & bitmask!(u32; 9) != 0);
+    features.set_if(CpuFeatures::SSE3, leaf_1.ecx & bitmask!(u32; 0) != 0);
+    features.set_if(CpuFeatures::SSSE3, leaf_1.ecx & bitmask!(u32; 9) != 0);
+    features.set_if(CpuFeatures::FMA, leaf_1.ecx & bitmask!(u32; 12) != 0);
+    features.set_if(CpuFeatures::SSE4_1, leaf_1.ecx & bitmask!(u32; 19) != 0);
+    features.set_if(CpuFeatures::SSE4_2, leaf_1.ecx & bitmask!(u32; 20) != 0);
+    features.set_if(CpuFeatures::X2APIC, leaf_1.ecx & bitmask!(u32; 21) != 0);
+    features.set_if(CpuFeatures::AVX, leaf_1.ecx & bitmask!(u32; 28) != 0);
+    features.set_if(CpuFeatures::RDRAND, leaf_1.ecx & bitmask!(u32; 30) != 0);
+
+    if max_leaf() >= 0x07 {
+        let leaf_7 = unsafe { __cpuid_count(0x07, 0) };
+        features.set_if(CpuFeatures::FSGSBASE, leaf_7.ebx & bitmask!(u32; 0) != 0);
+        features.set_if(CpuFeatures::AVX2, leaf_7.ebx & bitmask!(u32; 5) != 0);
+        features.set_if(CpuFeatures::RDSEED, leaf_7.ebx & bitmask!(u32; 18) != 0);
+    }
+
+    if max_extended_leaf() >= 0x8000_0001 {
+        let extended = unsafe { __cpuid(0x8000_0001) };
+        features.set_if(CpuFeatures::NX, extended.edx & bitmask!(u32; 20) != 0);
+    }
+    features
+}
+
+/// The number of cores and hardware threads of the processor package the kernel runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Topology {
+    /// Hardware threads (logical processors) per core.
+    pub threads_per_core: u32,
+    /// Cores per package.
+    pub cores_per_package: u32,
+}
+
+impl Topology {
+    /// Reads the topology from CPUID. The extended topology leaf 0x0B is used when available,
+    /// otherwise the counts are derived from the legacy leaves 0x01 and 0x04.
+    pub fn read() -> Self {
+        let max_leaf = max_leaf();
+        if max_leaf >= 0x0b {
+            if let Some(topology) = Self::from_leaf_0b() {
+                return topology;
+            }
+        }
+        Self::from_legacy_leaves(max_leaf)
+    }
+
+    /// Parses the extended topology enumeration leaf. Returns None when the leaf reports no
+    /// levels, which is the case on processors that do not actually implement it.
+    fn from_leaf_0b() -> Option<Self> {
+        let mut threads_per_core = None;
+        let mut logical_per_package = None;
+
+        // there are only a handful of levels, the bound guards against broken implementations
+        for subleaf in 0..16 {
+            let result = unsafe { __cpuid_count(0x0b, subleaf) };
+            // bits 15:8 of ecx hold the level type, 0 marks the end of the list
+            let level_type = (result.ecx & bitmask!(u32; 15..8)) >> 8;
+            // bits 15:0 of ebx hold the number of logical processors at this level and below
+            let logical = result.ebx & bitmask!(u32; 15..0);
+            match level_type {
+                0 => break,
+                1 => threads_per_core = Some(logical),
+                2 => logical_per_package = Some(logical),
+                _ => {}
+            }
+        }
+
+        let threads_per_core = threads_per_core.filter(|&n| n > 0).unwrap_or(1);
+        let logical_per_package = logical_per_package.filter(|&n| n > 0)?;
+        Some(Topology {
+            threads_per_core,
+            cores_per_package: (logical_per_package / threads_per_core).max(1),
+        })
+    }
+
+    /// Derives the topology from leaf 0x01 (logical processors per package) and leaf 0x04
+    /// (cores per package). These report the addressable IDs rather than the actual counts and
+    /// may overestimate.
+    fn from_legacy_leaves(max_leaf: u32) -> Self {
+        let leaf_1 = unsafe { __cpuid(0x01) };
+        // bit 28 of edx (HTT) marks that bits 23:16 of ebx hold the logical processor count
+        let logical = if leaf_1.edx & bitmask!(u32; 28) != 0 {
+            ((leaf_1.ebx & bitmask!(u32; 23..16)) >> 16).max(1)
+        } else {
+            1
+        };
+        let cores = if max_leaf >= 0x04 {
+            // bits 31:26 of eax hold the number of cores minus one
+            (unsafe { __cpuid_count(0x04, 0) }.eax >> 26) + 1
+        } else {
+            1
+        };
+
+        Topology {
+            threads_per_core: (logical / cores).max(1),
+            cores_per_package: cores,
+        }
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} cores x {} threads",
+            self.cores_per_package, self.threads_per_core
+        )
+    }
+}
+
+/// Prints the vendor, brand and topology of the processor.
+pub fn print_cpu_info() {
+    println!(
+        "CPU: {} {} ({})",
+        vendor().as_str(),
+        brand().as_str(),
+        Topology::read()
+    );
+    println!("CPU features: {}", cpu_features());
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the vendor string is one of the vendors qemu reports.
+#[test_case]
+fn cpu_vendor_string() {
+    let vendor = vendor();
+    assert!(["GenuineIntel", "AuthenticAMD"].contains(&vendor.as_str()));
+}
+
+/// Test that qemu's CPU reports a brand string.
+#[test_case]
+fn cpu_brand_string() {
+    let brand = brand();
+    assert!(!brand.is_empty());
+    assert_ne!(brand.as_str(), "unknown");
+    assert!(!brand.ends_with(' '));
+    assert!(!brand.ends_with('\0'));
+}
+
+/// Test that the default qemu CPU reports the APIC and the features required by x86_64.
+#[test_case]
+fn cpu_features_of_qemu() {
+    let features = cpu_features();
+    assert!(features.contains(CpuFeatures::APIC));
+    assert!(features.contains(CpuFeatures::SSE));
+    assert!(features.contains(CpuFeatures::SSE2));
+    assert!(features.contains(CpuFeatures::NX));
+}
+
+/// Test the containment check and the printed feature list.
+#[test_case]
+fn cpu_features_display() {
+    let mut features = CpuFeatures::empty();
+    features.set_if(CpuFeatures::NX, true);
+    features.set_if(CpuFeatures::SSE, true);
+    features.set_if(CpuFeatures::AVX, false);
+    assert!(features.contains(CpuFeatures::SSE));
+    assert!(!features.contains(CpuFeatures::AVX));
+    assert_eq!(CpuFeatures::empty().bits(), 0);
+
+    let mut printed = FixedString::<32>::new();
+    fmt::write(&mut printed, format_args!("{}", features)).unwrap();
+    assert_eq!(printed.as_str(), "sse nx");
+}
+
+/// Test that the topology matches the `-smp 4,cores=2,threads=2` of the test configuration.
+#[test_case]
+fn cpu_topology_matches_qemu_config() {
+    assert_eq!(
+        Topology::read(),
+        Topology {
+            threads_per_core: 2,
+            cores_per_package: 2,
+        }
+    );
+}
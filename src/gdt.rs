@@ -1,4 +1,5 @@
-use crate::{print, println};
+use crate::{boot, println, status_print, status_println};
+use core::fmt;
 use lazy_static::lazy_static;
 use x86_64::{
     registers::segmentation::Segment,
@@ -6,11 +7,14 @@ use x86_64::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
         tss::TaskStateSegment,
     },
-    VirtAddr,
+    PrivilegeLevel, VirtAddr,
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of the kernel stack used for interrupts and system calls from ring 3.
+const PRIVILEGE_STACK_SIZE: usize = 4096 * 5;
+
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
@@ -22,13 +26,29 @@ lazy_static! {
             let start = VirtAddr::from_ptr(unsafe { &STACK });
             start + STACK_SIZE // return the top of the stack, as x86 stack grows downwards
         };
+        // the CPU switches to RSP0 when an interrupt arrives in ring 3. The GDT is loaded
+        // before the heap exists, so the stack is static as well.
+        tss.privilege_stack_table[0] = {
+            static mut STACK: [u8; PRIVILEGE_STACK_SIZE] = [0; PRIVILEGE_STACK_SIZE];
+
+            #[allow(static_mut_refs)]
+            let start = VirtAddr::from_ptr(unsafe { &STACK });
+            start + PRIVILEGE_STACK_SIZE
+        };
         tss
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// Segment selectors of the descriptors in the GDT.
+#[derive(Debug)]
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
+    /// User data segment, requested with RPL 3.
+    pub user_data_selector: SegmentSelector,
+    /// User code segment, requested with RPL 3. It follows the user data segment, as `sysret`
+    /// expects.
+    pub user_code_selector: SegmentSelector,
 }
 
 lazy_static! {
@@ -36,11 +56,16 @@ lazy_static! {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
         let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        // user segments are added with RPL 3
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
         (
             gdt,
             Selectors {
                 code_selector,
                 tss_selector,
+                user_data_selector,
+                user_code_selector,
             },
         )
     };
@@ -52,10 +77,208 @@ pub fn init() {
     use x86_64::instructions::tables::load_tss;
 
     GDT.0.load();
-    print!("Loaded GDT. Trying to set selectors... ");
+    status_print!("Loaded GDT. Trying to set selectors... ");
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
-    println!("[ok]")
+    boot::complete(boot::Stage::Gdt);
+    status_println!("[ok]");
+}
+
+/// Returns the selectors of the kernel's GDT.
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Returns the top of the stack the CPU switches to on interrupts from ring 3.
+pub fn privilege_stack_top() -> VirtAddr {
+    TSS.privilege_stack_table[0]
+}
+
+/// A descriptor of the loaded GDT, decoded from its raw representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorInfo {
+    /// Index of the descriptor in the GDT, as used in segment selectors.
+    pub index: u16,
+    /// Base address of the segment. Ignored by the CPU for code and data segments in long mode.
+    pub base: u64,
+    /// Limit of the segment in bytes (granularity already applied).
+    pub limit: u32,
+    /// The 4 bit type field.
+    pub kind: u8,
+    /// Set for code and data segments, clear for system segments like the TSS.
+    pub user_segment: bool,
+    /// Descriptor privilege level.
+    pub dpl: u8,
+    /// Whether the segment is present.
+    pub present: bool,
+    /// Whether this is a 64-bit code segment.
+    pub long_mode: bool,
+}
+
+impl DescriptorInfo {
+    /// Whether this is a code segment.
+    pub fn is_code(&self) -> bool {
+        self.user_segment && self.kind & 0x8 != 0
+    }
+
+    /// Whether this is a 64-bit TSS, either available or busy.
+    pub fn is_tss(&self) -> bool {
+        !self.user_segment && (self.kind == 0x9 || self.kind == 0xb)
+    }
+}
+
+impl fmt::Display for DescriptorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.is_code() {
+            "code"
+        } else if self.user_segment {
+            "data"
+        } else if self.is_tss() {
+            "tss"
+        } else {
+            "system"
+        };
+        write!(
+            f,
+            "#{} {:6} base={:#x} limit={:#x} type={:#x} dpl={} present={} long={}",
+            self.index,
+            kind,
+            self.base,
+            self.limit,
+            self.kind,
+            self.dpl,
+            self.present,
+            self.long_mode
+        )
+    }
+}
+
+/// Iterator over the descriptors of the loaded GDT. The null descriptor is skipped.
+pub struct Descriptors {
+    table: *const u64,
+    len: usize,
+    next: usize,
+}
+
+impl Iterator for Descriptors {
+    type Item = DescriptorInfo;
+
+    fn next(&mut self) -> Option<DescriptorInfo> {
+        // skip empty entries, including the null descriptor
+        let (index, low) = loop {
+            if self.next >= self.len {
+                return None;
+            }
+            let index = self.next;
+            self.next += 1;
+            let low = unsafe { self.table.add(index).read() };
+            if low != 0 {
+                break (index, low);
+            }
+        };
+
+        let access = (low >> 40) as u8;
+        let flags = (low >> 52) as u8 & 0xf;
+        let user_segment = access & 0x10 != 0;
+
+        let mut limit = (low & 0xffff) as u32 | ((low >> 32) as u32 & 0xf_0000);
+        if flags & 0x8 != 0 {
+            // granularity bit: the limit is in 4 KiB units
+            limit = (limit << 12) | 0xfff;
+        }
+        let mut base = ((low >> 16) & 0xff_ffff) | ((low >> 32) & 0xff00_0000);
+        if !user_segment && self.next < self.len {
+            // system descriptors take two entries, the second holds the upper half of the base
+            let high = unsafe { self.table.add(self.next).read() };
+            base |= (high & 0xffff_ffff) << 32;
+            self.next += 1;
+        }
+
+        Some(DescriptorInfo {
+            index: index as u16,
+            base,
+            limit,
+            kind: access & 0xf,
+            user_segment,
+            dpl: (access >> 5) & 0x3,
+            present: access & 0x80 != 0,
+            long_mode: flags & 0x2 != 0,
+        })
+    }
+}
+
+/// Returns an iterator over the descriptors of the currently loaded GDT, read through the GDTR.
+pub fn descriptors() -> Descriptors {
+    let gdtr = x86_64::instructions::tables::sgdt();
+    Descriptors {
+        table: gdtr.base.as_ptr(),
+        len: (usize::from(gdtr.limit) + 1) / 8,
+        next: 0,
+    }
+}
+
+/// Prints every descriptor of the loaded GDT.
+pub fn dump() {
+    println!("GDT:");
+    for descriptor in descriptors() {
+        println!("  {}", descriptor);
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the kernel code segment and the TSS appear in the loaded GDT.
+#[test_case]
+fn gdt_dump_contains_code_and_tss() {
+    let code = descriptors()
+        .find(|d| d.index == GDT.1.code_selector.index())
+        .expect("kernel code segment missing");
+    assert!(code.is_code());
+    assert!(code.long_mode);
+    assert!(code.present);
+    assert_eq!(code.dpl, 0);
+
+    let tss = descriptors()
+        .find(|d| d.index == GDT.1.tss_selector.index())
+        .expect("TSS descriptor missing");
+    // loading the TSS marks it busy
+    assert!(tss.is_tss());
+    assert_eq!(tss.kind, 0xb);
+    assert!(tss.present);
+    assert_eq!(tss.base, &*TSS as *const TaskStateSegment as u64);
+    assert_eq!(
+        tss.limit as usize,
+        core::mem::size_of::<TaskStateSegment>() - 1
+    );
+}
+
+/// Test that the user segments have RPL 3 and follow the TSS, data before code.
+#[test_case]
+fn gdt_user_selectors() {
+    let selectors = selectors();
+    assert_eq!(selectors.user_data_selector.rpl(), PrivilegeLevel::Ring3);
+    assert_eq!(selectors.user_code_selector.rpl(), PrivilegeLevel::Ring3);
+    assert_eq!(selectors.code_selector.rpl(), PrivilegeLevel::Ring0);
+    // the TSS takes the two entries after the kernel code segment
+    assert_eq!(selectors.code_selector.index(), 1);
+    assert_eq!(selectors.tss_selector.index(), 2);
+    assert_eq!(selectors.user_data_selector.index(), 4);
+    assert_eq!(selectors.user_code_selector.index(), 5);
+
+    let code = descriptors()
+        .find(|d| d.index == 5)
+        .expect("user code segment missing");
+    assert!(code.is_code());
+    assert!(code.long_mode);
+    assert_eq!(code.dpl, 3);
+    let data = descriptors()
+        .find(|d| d.index == 4)
+        .expect("user data segment missing");
+    assert!(!data.is_code());
+    assert!(data.user_segment);
+    assert_eq!(data.dpl, 3);
+
+    assert_ne!(privilege_stack_top().as_u64(), 0);
 }
@@ -1,11 +1,19 @@
 pub mod bump;
+pub mod fixed;
 pub mod list;
 
 use self::list::ListAllocator;
-use core::{alloc::GlobalAlloc, ptr::null_mut};
+use crate::{boot, counters, error, memory::BootInfoFrameAllocator};
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    ptr::null_mut,
+};
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageSize,
+        PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
@@ -27,10 +35,20 @@ impl<T> Locked<T> {
     }
 }
 
-/// Aligns the given address upwards to the alignment `align` given `align` is a power of 2.
+/// Aligns the given address upwards to the alignment `align`.
+///
+/// Panics if `align` is not a power of 2.
 fn align_up(addr: usize, align: usize) -> usize {
-    let offset = (addr as *const u8).align_offset(align);
-    addr + offset
+    assert!(align.is_power_of_two(), "`align` must be a power of 2");
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Aligns the given address downwards to the alignment `align`.
+///
+/// Panics if `align` is not a power of 2.
+fn align_down(addr: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "`align` must be a power of 2");
+    addr & !(align - 1)
 }
 
 /// A dummy heap allocator that always returns a null pointer signaling a
@@ -47,40 +65,359 @@ unsafe impl GlobalAlloc for DummyAllocator {
     }
 }
 
+/// A snapshot of the heap usage of an allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Total number of free bytes.
+    pub free_bytes: usize,
+    /// Size of the largest free memory region.
+    pub largest_free_block: usize,
+    /// Number of free memory regions.
+    pub node_count: usize,
+    /// Number of currently active allocations.
+    pub allocations: usize,
+    /// Histogram of the number of free list nodes inspected per allocation. Bucket 0 counts
+    /// searches of an empty list, bucket `i` counts searches that inspected `2^(i-1)` up to
+    /// `2^i - 1` nodes. The last bucket also holds all longer searches.
+    #[cfg(debug_assertions)]
+    pub traversals: [usize; TRAVERSAL_BUCKETS],
+}
+
+/// Number of buckets of the free list traversal histogram.
+#[cfg(debug_assertions)]
+pub const TRAVERSAL_BUCKETS: usize = 8;
+
+/// The reason an allocation could not be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomCause {
+    /// Enough memory is free in total but no single free region is large enough.
+    Fragmented,
+    /// Not enough memory is free.
+    Exhausted,
+}
+
+impl HeapStats {
+    /// Classifies why an allocation of `size` bytes could not be served.
+    pub fn oom_cause(&self, size: usize) -> OomCause {
+        if self.free_bytes >= size {
+            OomCause::Fragmented
+        } else {
+            OomCause::Exhausted
+        }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes free in {} regions (largest {} bytes), {} active allocations",
+            self.free_bytes, self.node_count, self.largest_free_block, self.allocations
+        )
+    }
+}
+
 #[global_allocator]
 static ALLOCATOR: Locked<ListAllocator> = Locked::new(ListAllocator::empty());
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
-/// Maps the heap pages to physical memory.
-pub fn init(
+/// Returns the current usage statistics of the kernel heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// Returns a snapshot of the free regions of the kernel heap as (start address, size) tuples.
+///
+/// The heap must not be locked while the snapshot vector is allocated, so the number of
+/// regions is read first and the snapshot is retried if the list grew in between.
+pub fn free_blocks() -> Vec<(usize, usize)> {
+    loop {
+        // allocating the vector changes the free list, leave some room for that
+        let count = ALLOCATOR.lock().free_blocks().count() + 2;
+        let mut blocks = Vec::with_capacity(count);
+
+        let allocator = ALLOCATOR.lock();
+        if allocator.free_blocks().count() <= blocks.capacity() {
+            blocks.extend(allocator.free_blocks());
+            return blocks;
+        }
+    }
+}
+
+/// Called when a heap allocation fails. Reports the heap state so that fragmentation can be
+/// told apart from true exhaustion.
+///
+/// The failed layout and the heap usage are printed to the screen and the serial port before
+/// panicking, so that they are visible even if the panic is reported elsewhere.
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    let stats = stats();
+    let cause = stats.oom_cause(layout.size());
+    error!(
+        "failed to allocate {} bytes aligned to {} ({:?})\nheap: {}",
+        layout.size(),
+        layout.align(),
+        cause,
+        stats
+    );
+    panic!(
+        "allocation error: {:?}\nheap: {}\ncause: {:?}",
+        layout, stats, cause
+    );
+}
+
+/// Smallest heap size the kernel can run with. With less free physical memory heap
+/// initialization fails.
+pub const MIN_HEAP_SIZE: usize = HEAP_SIZE / 4;
+
+/// Error returned when the heap could not be initialized.
+#[derive(Debug)]
+pub enum HeapInitError {
+    /// Physical memory ran out before `MIN_HEAP_SIZE` bytes of the heap were mapped.
+    InsufficientMemory { needed: usize, available: usize },
+    /// Mapping a heap page failed for another reason than frame exhaustion.
+    Mapping(MapToError<Size4KiB>),
+}
+
+impl fmt::Display for HeapInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapInitError::InsufficientMemory { needed, available } => write!(
+                f,
+                "insufficient memory for heap (need {} bytes, have {} bytes)",
+                needed, available
+            ),
+            HeapInitError::Mapping(err) => write!(f, "mapping the heap failed: {:?}", err),
+        }
+    }
+}
+
+/// Maps the pages of `size` bytes starting at `start` to newly allocated frames. Stops at the
+/// first error. Returns the number of bytes mapped and the error, if any.
+fn map_pages(
+    start: usize,
+    size: usize,
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
+) -> (usize, Option<MapToError<Size4KiB>>) {
+    crate::assert_page_aligned!(start as u64);
+
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
+        let start = VirtAddr::new(start as u64);
+        let end = start + size - 1u64;
+        let start_page = Page::containing_address(start);
+        let end_page = Page::containing_address(end);
 
-        Page::range_inclusive(heap_start_page, heap_end_page)
+        Page::range_inclusive(start_page, end_page)
     };
 
     // map every page to a frame
+    let mut mapped = 0;
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+        let result = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| unsafe { mapper.map_to(page, frame, flags, frame_allocator) });
+
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(err) => return (mapped, Some(err)),
         }
+        mapped += page.size() as usize;
+    }
+    (mapped, None)
+}
+
+/// Maps the heap pages to physical memory. The heap starts out with the `initial_size` of the
+/// heap policy.
+///
+/// When physical memory runs out before the whole heap is mapped, the heap is shrunk to the
+/// mapped part as long as that is at least `MIN_HEAP_SIZE` bytes (or the initial size, if that
+/// is smaller). Returns the size of the heap in bytes.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<usize, HeapInitError> {
+    boot::require(boot::Stage::Memory);
+
+    let initial_size = policy().initial_size;
+    let (heap_size, err) = map_pages(HEAP_START, initial_size, mapper, frame_allocator);
+    match err {
+        // out of frames (for a page or a page table), continue with what is mapped
+        None | Some(MapToError::FrameAllocationFailed) => {}
+        Some(err) => return Err(HeapInitError::Mapping(err)),
+    }
+
+    let needed = MIN_HEAP_SIZE.min(initial_size);
+    if heap_size < needed {
+        return Err(HeapInitError::InsufficientMemory {
+            needed,
+            available: heap_size,
+        });
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START, heap_size);
+    }
+    boot::complete(boot::Stage::Heap);
+
+    Ok(heap_size)
+}
+
+/// Limits for the size of the kernel heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapPolicy {
+    /// Size the heap is mapped with on initialization.
+    pub initial_size: usize,
+    /// Size the heap never grows beyond.
+    pub max_size: usize,
+    /// Number of bytes the heap grows by at once.
+    pub growth_chunk: usize,
+}
+
+impl HeapPolicy {
+    /// The policy used unless another one is set: start with `HEAP_SIZE` and grow in 64 KiB
+    /// steps up to 1 MiB.
+    pub const DEFAULT: HeapPolicy = HeapPolicy {
+        initial_size: HEAP_SIZE,
+        max_size: 1024 * 1024,
+        growth_chunk: 64 * 1024,
+    };
+}
+
+static POLICY: spin::Mutex<HeapPolicy> = spin::Mutex::new(HeapPolicy::DEFAULT);
+
+/// Returns the current heap policy.
+pub fn policy() -> HeapPolicy {
+    *POLICY.lock()
+}
+
+/// Sets the heap policy. The initial size only has an effect when set before `init`.
+///
+/// Panics if the sizes are not positive or the initial size exceeds the maximum size.
+pub fn set_policy(policy: HeapPolicy) {
+    assert!(policy.initial_size > 0 && policy.growth_chunk > 0);
+    assert!(
+        policy.initial_size <= policy.max_size,
+        "initial heap size exceeds the maximum heap size"
+    );
+    *POLICY.lock() = policy;
+}
+
+/// Error returned when the heap could not grow.
+#[derive(Debug)]
+pub enum HeapGrowError {
+    /// The heap already has the maximum size allowed by the heap policy.
+    LimitReached,
+    /// Mapping the first page of the new part failed.
+    Mapping(MapToError<Size4KiB>),
+}
+
+/// Returns the current size of the heap in bytes.
+pub fn size() -> usize {
+    ALLOCATOR.lock().heap_end().saturating_sub(HEAP_START)
+}
+
+/// Grows the heap by the `growth_chunk` of the heap policy, or less if that would exceed its
+/// `max_size`. Returns the new size of the heap.
+///
+/// If physical memory runs out, the heap grows by the part that could be mapped.
+pub fn grow(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<usize, HeapGrowError> {
+    grow_by(0, mapper, frame_allocator)
+}
+
+/// Grows the heap by at least `min_size` bytes, rounded up to whole `growth_chunk`s of the heap
+/// policy, or by less than that if it would exceed its `max_size`. Returns the new size of the
+/// heap.
+///
+/// Fails with `LimitReached` without growing if not even `min_size` bytes fit below the
+/// `max_size`. If physical memory runs out, the heap grows by the part that could be mapped.
+fn grow_by(
+    min_size: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<usize, HeapGrowError> {
+    let policy = policy();
+    let heap_end = ALLOCATOR.lock().heap_end();
+    let size = heap_end - HEAP_START;
+
+    let chunks = min_size.div_ceil(policy.growth_chunk).max(1);
+    let wanted = chunks.saturating_mul(policy.growth_chunk);
+    // only grow by whole pages
+    let extra = align_down(
+        wanted.min(policy.max_size.saturating_sub(size)),
+        Size4KiB::SIZE as usize,
+    );
+    if extra == 0 || extra < min_size {
+        return Err(HeapGrowError::LimitReached);
     }
 
-    Ok(())
+    let (mapped, err) = map_pages(heap_end, extra, mapper, frame_allocator);
+    if mapped == 0 {
+        return Err(HeapGrowError::Mapping(
+            err.unwrap_or(MapToError::FrameAllocationFailed),
+        ));
+    }
+
+    unsafe { ALLOCATOR.lock().extend(mapped) };
+    Ok(size + mapped)
+}
+
+/// Page table and frame allocator used to grow the heap when an allocation runs out of memory.
+static GROWTH_MEMORY: spin::Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> =
+    spin::Mutex::new(None);
+
+/// Lets failing allocations grow the heap by as many `growth_chunk`s as they need until the heap
+/// policy's `max_size` is reached. Takes over the page table and frame allocator, which are
+/// locked on every growth.
+pub fn enable_growth(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    boot::require(boot::Stage::Heap);
+    *GROWTH_MEMORY.lock() = Some((mapper, frame_allocator));
+    ALLOCATOR.lock().set_oom_handler(grow_on_oom);
+}
+
+/// Out of memory handler of the kernel heap. Grows the heap so that `layout` fits at its end.
+/// Returns whether the heap grew.
+fn grow_on_oom(layout: Layout) -> bool {
+    let min_size = ALLOCATOR.lock().extension_for(layout);
+    // the memory is busy when an allocation fails while growing, give up on that one
+    let mut memory = match GROWTH_MEMORY.try_lock() {
+        Some(memory) => memory,
+        None => return false,
+    };
+    match memory.as_mut() {
+        Some((mapper, frame_allocator)) if grow_by(min_size, mapper, frame_allocator).is_ok() => {
+            counters::inc("heap_grow");
+            true
+        }
+        _ => false,
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test aligning addresses that are already aligned, not aligned, and aligned to 1.
+#[test_case]
+fn heap_align_up_and_down() {
+    // any address is aligned to 1
+    assert_eq!(align_up(0x1235, 1), 0x1235);
+    assert_eq!(align_down(0x1235, 1), 0x1235);
+
+    // not aligned
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+    assert_eq!(align_down(0x1fff, 0x1000), 0x1000);
+    assert_eq!(align_up(9, 8), 16);
+    assert_eq!(align_down(9, 8), 8);
+
+    // already aligned
+    assert_eq!(align_up(0x2000, 0x1000), 0x2000);
+    assert_eq!(align_down(0x2000, 0x1000), 0x2000);
+    assert_eq!(align_up(0, 16), 0);
+    assert_eq!(align_down(0, 16), 0);
 }
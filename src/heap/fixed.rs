@@ -0,0 +1,281 @@
+use super::{list::ListAllocator, Locked};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr,
+};
+
+/// The block sizes to use. Each size must be a power of 2 as it is also used as the block
+/// alignment.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// A free block, stored in the block itself.
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// An allocator serving small allocations from lists of free blocks of fixed sizes. Larger
+/// allocations and those with an alignment above the largest block size are served by a
+/// `ListAllocator`, which also provides the memory for new blocks.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback: ListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    /// Creates an empty FixedSizeBlockAllocator.
+    pub const fn empty() -> Self {
+        FixedSizeBlockAllocator {
+            list_heads: [const { None }; BLOCK_SIZES.len()],
+            fallback: ListAllocator::empty(),
+        }
+    }
+
+    /// Initializes the FixedSizeBlockAllocator with the given heap bounds.
+    ///
+    /// # Safety
+    /// This method is unsafe as the caller must ensure that the given
+    /// memory range is usable. Also, this method must be called no more
+    /// than once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback.init(heap_start, heap_size);
+    }
+
+    /// Returns the fallback allocator. Freed blocks stay in the block lists and are counted
+    /// as allocated by it.
+    pub fn fallback(&self) -> &ListAllocator {
+        &self.fallback
+    }
+
+    /// Returns the number of free blocks in the list of each block size, in the order of the
+    /// block sizes.
+    pub fn free_block_counts(&self) -> [usize; BLOCK_SIZES.len()] {
+        let mut counts = [0; BLOCK_SIZES.len()];
+        for (count, head) in counts.iter_mut().zip(self.list_heads.iter()) {
+            let mut cur = head.as_deref();
+            while let Some(node) = cur {
+                *count += 1;
+                cur = node.next.as_deref();
+            }
+        }
+        counts
+    }
+}
+
+/// Returns the index of the smallest block size that fits `layout`, or None if the allocation
+/// has to be served by the fallback allocator.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    // no free block of this size, allocate a new one aligned to its size
+                    let block_size = BLOCK_SIZES[index];
+                    let layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    allocator.fallback.allocate(layout)
+                }
+            },
+            None => allocator.fallback.allocate(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => {
+                // every block can hold a list node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => allocator.fallback.deallocate(ptr, layout),
+        }
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Size of the arena the tests allocate from.
+#[cfg(test)]
+const ARENA_SIZE: usize = 32 * 1024;
+
+#[cfg(test)]
+#[repr(C, align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+
+/// Runs `f` with a new allocator on the test arena. The tests run one after another, so they
+/// share the arena.
+#[cfg(test)]
+fn with_test_allocator(f: impl FnOnce(&Locked<FixedSizeBlockAllocator>)) {
+    static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::empty());
+    unsafe {
+        let arena_start = ptr::addr_of_mut!(ARENA) as usize;
+        allocator.lock().init(arena_start, ARENA_SIZE);
+    }
+    f(&allocator);
+}
+
+/// Test two small allocations like `Box::new(123)` and `Box::new(321)`.
+#[test_case]
+fn fixed_size_block_box_alloc() {
+    with_test_allocator(|allocator| {
+        let layout = Layout::new::<i32>();
+        let v1 = unsafe { allocator.alloc(layout) } as *mut i32;
+        let v2 = unsafe { allocator.alloc(layout) } as *mut i32;
+        assert!(!v1.is_null() && !v2.is_null());
+        assert_ne!(v1, v2);
+        unsafe {
+            v1.write(123);
+            v2.write(321);
+            assert_eq!(*v1, 123);
+            assert_eq!(*v2, 321);
+            allocator.dealloc(v1 as *mut u8, layout);
+            allocator.dealloc(v2 as *mut u8, layout);
+        }
+    });
+}
+
+/// Test a vector of 1000 numbers growing like `Vec::push`, from blocks to the fallback
+/// allocator.
+#[test_case]
+fn fixed_size_block_vec_alloc() {
+    with_test_allocator(|allocator| {
+        let n = 1000;
+        let mut layout = Layout::array::<u64>(4).unwrap();
+        let mut vec = unsafe { allocator.alloc(layout) } as *mut u64;
+        for i in 0..n {
+            if i * 8 == layout.size() {
+                let new_size = layout.size() * 2;
+                vec = unsafe { allocator.realloc(vec as *mut u8, layout, new_size) } as *mut u64;
+                layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+            }
+            assert!(!vec.is_null());
+            unsafe { vec.add(i).write(i as u64 + 1) };
+        }
+        let sum: u64 = (0..n).map(|i| unsafe { *vec.add(i) }).sum();
+        assert_eq!(sum, n as u64 * (n as u64 + 1) / 2);
+        unsafe { allocator.dealloc(vec as *mut u8, layout) };
+    });
+}
+
+/// Test that small allocations are served from blocks and reused after being freed.
+#[test_case]
+fn fixed_size_block_reuse_after_free() {
+    with_test_allocator(|allocator| {
+        // like a `Box<u64>`
+        let layout = Layout::new::<u64>();
+        let first = unsafe { allocator.alloc(layout) };
+        assert!(!first.is_null());
+        assert_eq!(first as usize % 8, 0);
+        unsafe { (first as *mut u64).write(123) };
+        unsafe { allocator.dealloc(first, layout) };
+        assert_eq!(allocator.lock().free_block_counts()[0], 1);
+
+        // the freed block is handed out again
+        for i in 0..ARENA_SIZE {
+            let block = unsafe { allocator.alloc(layout) };
+            assert_eq!(block, first);
+            unsafe {
+                (block as *mut usize).write(i);
+                assert_eq!(*(block as *mut usize), i);
+                allocator.dealloc(block, layout);
+            }
+        }
+        assert_eq!(allocator.lock().fallback().stats().allocations, 1);
+    });
+}
+
+/// Test that a long lived block is not handed out while many short lived ones come and go.
+#[test_case]
+fn fixed_size_block_prolonged_use() {
+    with_test_allocator(|allocator| {
+        let layout = Layout::new::<u64>();
+        let prolonged = unsafe { allocator.alloc(layout) };
+        unsafe { (prolonged as *mut u64).write(123) };
+        for i in 0..ARENA_SIZE {
+            let block = unsafe { allocator.alloc(layout) };
+            assert_ne!(block, prolonged);
+            unsafe {
+                (block as *mut usize).write(i);
+                allocator.dealloc(block, layout);
+            }
+        }
+        assert_eq!(unsafe { *(prolonged as *mut u64) }, 123);
+    });
+}
+
+/// Test that blocks are aligned to their size and large or strongly aligned requests use the
+/// fallback allocator.
+#[test_case]
+fn fixed_size_block_sizes_and_fallback() {
+    with_test_allocator(|allocator| {
+        // a growing vector: every capacity is served by the next block size
+        for &size in BLOCK_SIZES {
+            let layout = Layout::from_size_align(size / 2 + 1, 1).unwrap();
+            let block = unsafe { allocator.alloc(layout) };
+            assert!(!block.is_null());
+            assert_eq!(block as usize % size, 0);
+            unsafe { allocator.dealloc(block, layout) };
+        }
+        assert_eq!(allocator.lock().free_block_counts(), [1; BLOCK_SIZES.len()]);
+
+        // too large for a block, goes back to the fallback allocator on free
+        let large = Layout::from_size_align(4096, 8).unwrap();
+        let free_before = allocator.lock().fallback().stats().free_bytes;
+        let block = unsafe { allocator.alloc(large) };
+        assert!(!block.is_null());
+        assert_eq!(
+            allocator.lock().fallback().stats().free_bytes,
+            free_before - 4096
+        );
+        unsafe { allocator.dealloc(block, large) };
+        assert_eq!(allocator.lock().fallback().stats().free_bytes, free_before);
+
+        // an alignment above the largest block size
+        let aligned = Layout::from_size_align(8, 4096).unwrap();
+        let block = unsafe { allocator.alloc(aligned) };
+        assert!(!block.is_null());
+        assert_eq!(block as usize % 4096, 0);
+        unsafe { allocator.dealloc(block, aligned) };
+        assert_eq!(allocator.lock().free_block_counts(), [1; BLOCK_SIZES.len()]);
+    });
+}
+
+/// Test 10000 cycles of small allocations and frees of mixed sizes without exhausting the
+/// arena.
+#[test_case]
+fn fixed_size_block_many_cycles() {
+    with_test_allocator(|allocator| {
+        let layouts = [
+            Layout::from_size_align(8, 8).unwrap(),
+            Layout::from_size_align(24, 8).unwrap(),
+            Layout::from_size_align(100, 4).unwrap(),
+        ];
+        for i in 0..10_000 {
+            let layout = layouts[i % layouts.len()];
+            let block = unsafe { allocator.alloc(layout) };
+            assert!(!block.is_null());
+            unsafe { allocator.dealloc(block, layout) };
+        }
+
+        // one block of each used size was ever taken from the fallback allocator
+        assert_eq!(allocator.lock().fallback().stats().allocations, 3);
+    });
+}
@@ -1,4 +1,6 @@
-use super::Locked;
+#[cfg(debug_assertions)]
+use super::TRAVERSAL_BUCKETS;
+use super::{HeapStats, Locked};
 use crate::heap::align_up;
 use core::{
     alloc::{GlobalAlloc, Layout},
@@ -29,6 +31,15 @@ impl ListNode {
 
 pub struct ListAllocator {
     head: ListNode,
+    // counter for the active allocations
+    allocations: usize,
+    // end address of the managed memory
+    heap_end: usize,
+    // called without the lock held when no free region fits an allocation
+    oom_handler: Option<fn(Layout) -> bool>,
+    // number of nodes inspected per allocation, only recorded in debug builds
+    #[cfg(debug_assertions)]
+    traversals: [usize; TRAVERSAL_BUCKETS],
 }
 
 impl ListAllocator {
@@ -36,6 +47,11 @@ impl ListAllocator {
     pub const fn empty() -> Self {
         ListAllocator {
             head: ListNode::new(0),
+            allocations: 0,
+            heap_end: 0,
+            oom_handler: None,
+            #[cfg(debug_assertions)]
+            traversals: [0; TRAVERSAL_BUCKETS],
         }
     }
 
@@ -47,6 +63,96 @@ impl ListAllocator {
     /// than once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.add_free_mem_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
+    }
+
+    /// Sets the function called when an allocation finds no large enough free region.
+    ///
+    /// The handler is called without the allocator locked and may `extend` it. The allocation
+    /// is retried as long as the handler returns true, so it must eventually return false.
+    pub fn set_oom_handler(&mut self, handler: fn(Layout) -> bool) {
+        self.oom_handler = Some(handler);
+    }
+
+    /// Returns the end address of the memory managed by the allocator.
+    pub fn heap_end(&self) -> usize {
+        self.heap_end
+    }
+
+    /// Extends the managed memory by `extra_size` bytes directly after the current end. A free
+    /// region at the current end is enlarged instead of adding a separate one, so that the new
+    /// memory can serve allocations together with it.
+    ///
+    /// # Safety
+    /// This method is unsafe as the caller must ensure that the memory after the current end
+    /// is usable.
+    pub unsafe fn extend(&mut self, extra_size: usize) {
+        let heap_end = self.heap_end;
+        self.heap_end += extra_size;
+
+        let mut cur = self.head.next.as_deref_mut();
+        while let Some(region) = cur {
+            if region.end_addr() == heap_end {
+                region.size += extra_size;
+                return;
+            }
+            cur = region.next.as_deref_mut();
+        }
+        self.add_free_mem_region(heap_end, extra_size);
+    }
+
+    /// Returns the number of bytes the managed memory must be extended by so that an
+    /// allocation of `layout` fits at its end, taking a free region at the end into account.
+    pub fn extension_for(&self, layout: Layout) -> usize {
+        let (size, align) = ListAllocator::size_align(layout);
+        let trailing_free = self
+            .free_blocks()
+            .find(|(start, size)| start + size == self.heap_end)
+            .map_or(0, |(_, size)| size);
+        // the alignment may skip up to `align` bytes, and a rest too small for a ListNode
+        // fails the allocation
+        (size + align + mem::size_of::<ListNode>()).saturating_sub(trailing_free)
+    }
+
+    /// Returns usage statistics computed from the current free list.
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            free_bytes: 0,
+            largest_free_block: 0,
+            node_count: 0,
+            allocations: self.allocations,
+            #[cfg(debug_assertions)]
+            traversals: self.traversals,
+        };
+
+        let mut cur = &self.head.next;
+        while let Some(region) = cur {
+            stats.free_bytes += region.size;
+            stats.largest_free_block = stats.largest_free_block.max(region.size);
+            stats.node_count += 1;
+            cur = &region.next;
+        }
+
+        stats
+    }
+
+    /// Returns an iterator over the free memory regions as (start address, size) tuples, in free
+    /// list order.
+    pub fn free_blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut cur = self.head.next.as_deref();
+        core::iter::from_fn(move || {
+            let region = cur?;
+            cur = region.next.as_deref();
+            Some((region.start_addr(), region.size))
+        })
+    }
+
+    /// Records that an allocation inspected `nodes` free list nodes.
+    #[cfg(debug_assertions)]
+    fn record_traversal(&mut self, nodes: usize) {
+        // bucket by the number of significant bits, i.e. 0, 1, 2-3, 4-7, ...
+        let bucket = (usize::BITS - nodes.leading_zeros()) as usize;
+        self.traversals[bucket.min(TRAVERSAL_BUCKETS - 1)] += 1;
     }
 
     /// Adds the given memory region to the front of the list
@@ -70,10 +176,28 @@ impl ListAllocator {
         &mut self,
         size: usize,
         align: usize,
+    ) -> Option<(&'static mut ListNode, usize)> {
+        let mut inspected = 0;
+        let found = Self::take_free_mem_region(&mut self.head, size, align, &mut inspected);
+
+        #[cfg(debug_assertions)]
+        self.record_traversal(inspected);
+
+        found
+    }
+
+    /// Searches the list after `head` for a region fitting `size` and `align` and removes it.
+    /// `inspected` is incremented for every node looked at.
+    fn take_free_mem_region(
+        head: &mut ListNode,
+        size: usize,
+        align: usize,
+        inspected: &mut usize,
     ) -> Option<(&'static mut ListNode, usize)> {
         // traverse the list starting from the head node
-        let mut cur = &mut self.head;
+        let mut cur = head;
         while let Some(ref mut region) = cur.next {
+            *inspected += 1;
             if let Some(alloc_start) = Self::alloc_from_region(region, size, align) {
                 // region is okay to be allocated
                 let next = region.next.take();
@@ -124,33 +248,218 @@ impl ListAllocator {
         let size = layout.size().max(mem::size_of::<ListNode>());
         (size, layout.align())
     }
-}
 
-unsafe impl GlobalAlloc for Locked<ListAllocator> {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+    /// Allocates memory for `layout` from the free list without calling the out of memory
+    /// handler.
+    ///
+    /// Returns a null pointer when no free region is large enough.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
         // re-align so that a ListNode may be stored
         let (size, align) = ListAllocator::size_align(layout);
-        let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_free_mem_region(size, align) {
+        if let Some((region, alloc_start)) = self.find_free_mem_region(size, align) {
             let alloc_end = match alloc_start.checked_add(size) {
                 Some(end) => end,
                 None => return ptr::null_mut(), // overflow means out of memory
             };
             let excess_size = region.end_addr() - alloc_end;
             if excess_size > 0 {
-                allocator.add_free_mem_region(alloc_end, excess_size);
+                unsafe { self.add_free_mem_region(alloc_end, excess_size) };
             }
+            self.allocations += 1;
             alloc_start as *mut u8
         } else {
             ptr::null_mut()
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Returns the memory at `ptr` to the free list.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` of this allocator for the same `layout` and
+    /// must not be used afterwards.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
         // re-align so that a ListNode may be stored
         let (size, _align) = ListAllocator::size_align(layout);
-        // deallocate
-        self.lock().add_free_mem_region(ptr as usize, size);
+        self.add_free_mem_region(ptr as usize, size);
+        self.allocations -= 1;
     }
 }
+
+unsafe impl GlobalAlloc for Locked<ListAllocator> {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        loop {
+            let mut allocator = self.lock();
+            let ptr = allocator.allocate(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+
+            // out of memory, let the handler make room and retry
+            let handler = allocator.oom_handler;
+            drop(allocator);
+            match handler {
+                Some(handler) if handler(layout) => {}
+                _ => return ptr::null_mut(),
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout);
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Size of the arena the tests allocate from.
+#[cfg(test)]
+const ARENA_SIZE: usize = 4096;
+/// Size of the blocks `fill_arena` allocates.
+#[cfg(test)]
+const BLOCK_SIZE: usize = 256;
+
+#[cfg(test)]
+#[repr(C, align(16))]
+struct Arena([u8; ARENA_SIZE]);
+
+/// Runs `f` with a new allocator managing the first `heap_size` bytes of the test arena and the
+/// start address of the arena. The tests run one after another, so they share the arena.
+#[cfg(test)]
+fn with_test_allocator(heap_size: usize, f: impl FnOnce(&Locked<ListAllocator>, usize)) {
+    static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+    assert!(heap_size <= ARENA_SIZE);
+    let allocator = Locked::new(ListAllocator::empty());
+    let arena_start = ptr::addr_of_mut!(ARENA) as usize;
+    unsafe { allocator.lock().init(arena_start, heap_size) };
+    f(&allocator, arena_start);
+}
+
+/// Returns the layout of the blocks allocated by `fill_arena`.
+#[cfg(test)]
+fn block_layout() -> Layout {
+    Layout::from_size_align(BLOCK_SIZE, 8).unwrap()
+}
+
+/// Fills the whole arena with blocks of `BLOCK_SIZE` bytes.
+#[cfg(test)]
+fn fill_arena(allocator: &Locked<ListAllocator>) -> [*mut u8; ARENA_SIZE / BLOCK_SIZE] {
+    let mut blocks = [ptr::null_mut(); ARENA_SIZE / BLOCK_SIZE];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(block_layout()) };
+        assert!(!block.is_null());
+    }
+    blocks
+}
+
+/// Frees every other block of `blocks`: half of the arena is free but only in small regions.
+#[cfg(test)]
+fn free_every_other(allocator: &Locked<ListAllocator>, blocks: &[*mut u8]) {
+    for block in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(*block, block_layout()) };
+    }
+}
+
+/// Test that the heap statistics tell a fragmented heap apart from an exhausted one.
+#[test_case]
+fn list_allocator_oom_cause() {
+    use super::OomCause;
+
+    with_test_allocator(ARENA_SIZE, |allocator, _| {
+        let blocks = fill_arena(allocator);
+        assert_eq!(allocator.lock().stats().free_bytes, 0);
+
+        free_every_other(allocator, &blocks);
+        let big = Layout::from_size_align(2 * BLOCK_SIZE, 8).unwrap();
+        assert!(unsafe { allocator.alloc(big) }.is_null());
+
+        let stats = allocator.lock().stats();
+        assert_eq!(stats.free_bytes, ARENA_SIZE / 2);
+        assert_eq!(stats.largest_free_block, BLOCK_SIZE);
+        assert_eq!(stats.node_count, blocks.len() / 2);
+        assert_eq!(stats.allocations, blocks.len() / 2);
+        assert_eq!(stats.oom_cause(big.size()), OomCause::Fragmented);
+        assert_eq!(stats.oom_cause(ARENA_SIZE), OomCause::Exhausted);
+    });
+}
+
+/// Test that searching a fragmented free list shows up in the traversal histogram.
+#[cfg(debug_assertions)]
+#[test_case]
+fn list_allocator_traversal_histogram() {
+    with_test_allocator(ARENA_SIZE, |allocator, _| {
+        // on an unfragmented heap the remainder is always at the front of the list
+        let blocks = fill_arena(allocator);
+        let unfragmented = allocator.lock().stats().traversals;
+        assert_eq!(unfragmented[1], blocks.len());
+        assert_eq!(unfragmented.iter().sum::<usize>(), blocks.len());
+
+        // search for a region none of the small free ones can serve
+        free_every_other(allocator, &blocks);
+        let big = Layout::from_size_align(2 * BLOCK_SIZE, 8).unwrap();
+        assert!(unsafe { allocator.alloc(big) }.is_null());
+
+        let fragmented = allocator.lock().stats().traversals;
+        // all 8 free regions were inspected
+        assert_eq!(fragmented[4], unfragmented[4] + 1);
+        assert_eq!(fragmented.iter().sum::<usize>(), blocks.len() + 1);
+    });
+}
+
+/// Test that the free regions are reported with their start and size.
+#[test_case]
+fn list_allocator_free_blocks() {
+    with_test_allocator(ARENA_SIZE, |allocator, arena_start| {
+        assert!(allocator
+            .lock()
+            .free_blocks()
+            .eq([(arena_start, ARENA_SIZE)]));
+
+        let layout = block_layout();
+        let mut blocks = [ptr::null_mut(); 4];
+        for block in blocks.iter_mut() {
+            *block = unsafe { allocator.alloc(layout) };
+            assert!(!block.is_null());
+        }
+        // free the first and the third block, they are not merged with their neighbours
+        unsafe {
+            allocator.dealloc(blocks[0], layout);
+            allocator.dealloc(blocks[2], layout);
+        }
+
+        let allocator = allocator.lock();
+        assert_eq!(allocator.free_blocks().count(), 3);
+        let mut free = [(0, 0); 3];
+        for (slot, block) in free.iter_mut().zip(allocator.free_blocks()) {
+            *slot = block;
+        }
+        // freed blocks are added to the front of the list
+        assert_eq!(free[0], (blocks[2] as usize, BLOCK_SIZE));
+        assert_eq!(free[1], (blocks[0] as usize, BLOCK_SIZE));
+        assert_eq!(
+            free[2],
+            (arena_start + 4 * BLOCK_SIZE, ARENA_SIZE - 4 * BLOCK_SIZE)
+        );
+    });
+}
+
+/// Test that extending the heap enlarges the free region at its end, so that an allocation
+/// larger than the extension fits.
+#[test_case]
+fn list_allocator_extend_merges() {
+    with_test_allocator(ARENA_SIZE / 2, |allocator, arena_start| {
+        let half = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        assert!(unsafe { allocator.alloc(half) }.is_null());
+        let needed = allocator.lock().extension_for(half);
+        assert!(needed > 0 && needed <= ARENA_SIZE / 2);
+
+        unsafe { allocator.lock().extend(ARENA_SIZE / 2) };
+        assert!(allocator
+            .lock()
+            .free_blocks()
+            .eq([(arena_start, ARENA_SIZE)]));
+        assert_eq!(allocator.lock().extension_for(half), 0);
+        assert!(!unsafe { allocator.alloc(half) }.is_null());
+    });
+}
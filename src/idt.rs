@@ -1,17 +1,31 @@
-use crate::{gdt, hlt_forever, println};
-#[allow(unused_imports)]
-use core::arch::asm;
+use crate::{
+    boot, counters, gdt, hlt_forever,
+    interrupts::{self, without_interrupts},
+    println, serial_println, time, vectors,
+};
+use core::{
+    arch::asm,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{
+    structures::idt::{
+        HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+        PageFaultHandlerFunc,
+    },
+    PrivilegeLevel, VirtAddr,
+};
 
 lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
+    // the IDT is behind a lock so that handlers can be swapped at runtime
+    static ref IDT: spin::Mutex<InterruptDescriptorTable> = spin::Mutex::new({
         let mut idt = InterruptDescriptorTable::new();
         // Exceptions
         idt.divide_error.set_handler_fn(div_by_zero_handler);
         idt.debug.set_handler_fn(debug_handler);
-        // TODO: Non-maskable Interrupt
+        idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.overflow.set_handler_fn(overflow_handler);
         // TODO: Bound Range Exceeded
@@ -22,16 +36,16 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        // TODO: Invalid TSS
-        // TODO: Segment Not Present
-        // TODO: Stack-Segment Fault
+        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
         // TODO: General Protection Fault
         idt.page_fault.set_handler_fn(page_fault_handler);
-        // TODO: x87 Floating-Point Exception
-        // TODO: Alignment Check
-        // TODO: Machine Check
-        // TODO: SIMD Floating-Point Exception <-- low priority as SIMD is not enabled for kernel
-        // TODO: Virtualization Exception
+        idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+        idt.alignment_check.set_handler_fn(alignment_check_handler);
+        idt.machine_check.set_handler_fn(machine_check_handler);
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+        idt.virtualization.set_handler_fn(virtualization_handler);
         // TODO: Control Protection Exception
         // TODO: Hypervisor Injection Exception
         // TODO: VMM Communication Exception
@@ -43,13 +57,145 @@ lazy_static! {
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         // PS/2 Keyboard interrupt handler
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        // COM1 receive interrupt handler
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+        // PS/2 mouse interrupt handler
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+
+        // Local APIC spurious interrupts
+        idt[usize::from(crate::apic::SPURIOUS_VECTOR)].set_handler_fn(apic_spurious_handler);
+
+        // Software interrupts
+
+        // system calls, which ring 3 may raise
+        unsafe {
+            idt[usize::from(vectors::SYSCALL)]
+                .set_handler_addr(crate::usermode::syscall_entry())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
+        idt[usize::from(TRACE_POINT_VECTOR)].set_handler_fn(trace_point_handler);
 
         idt
-    };
+    });
 }
 
 pub fn init_idt() {
-    IDT.load();
+    // SAFETY: the table lives in a static and is never moved, so it stays valid after the lock
+    // is released. It is only modified through the lock with interrupts disabled.
+    unsafe { IDT.lock().load_unsafe() };
+    boot::complete(boot::Stage::Idt);
+}
+
+/// Installs `handler` for the interrupt `vector`, runs `f` and restores the previous handler
+/// afterwards. This allows tests to intercept exceptions, e.g. to check that they are raised.
+///
+/// Panics if `vector` is an exception that pushes an error code or does not return.
+pub fn with_handler<R>(vector: u8, handler: HandlerFunc, f: impl FnOnce() -> R) -> R {
+    let index = usize::from(vector);
+    let previous = without_interrupts(|| {
+        let mut idt = IDT.lock();
+        let previous = idt[index];
+        idt[index].set_handler_fn(handler);
+        previous
+    });
+
+    let result = f();
+
+    without_interrupts(|| IDT.lock()[index] = previous);
+    result
+}
+
+/// Installs `handler` for the interrupt `vector` permanently.
+pub fn set_handler(vector: u8, handler: HandlerFunc) {
+    without_interrupts(|| {
+        IDT.lock()[usize::from(vector)].set_handler_fn(handler);
+    });
+}
+
+/// Like `with_handler` for the page fault exception, which takes an error code.
+pub fn with_page_fault_handler<R>(handler: PageFaultHandlerFunc, f: impl FnOnce() -> R) -> R {
+    let previous = without_interrupts(|| {
+        let mut idt = IDT.lock();
+        let previous = idt.page_fault;
+        idt.page_fault.set_handler_fn(handler);
+        previous
+    });
+
+    let result = f();
+
+    without_interrupts(|| IDT.lock().page_fault = previous);
+    result
+}
+
+/// Raises the software interrupt `vector`, which runs its handler as if the interrupt occurred.
+///
+/// Unlike `x86_64::software_interrupt!` the vector does not have to be a constant: the macro is
+/// expanded once per vector and the matching one is selected at runtime.
+///
+/// # Safety
+/// The handler of `vector` must be safe to invoke from the calling context. In particular,
+/// exceptions that push an error code must not be raised this way, as their handler would
+/// treat part of the stack as the error code.
+pub unsafe fn trigger_software_interrupt(vector: u8) {
+    // `int` with the vector `high << 4 | low`
+    macro_rules! int_row {
+        ($high:literal) => {
+            match vector & 0xf {
+                0x0 => x86_64::software_interrupt!($high << 4 | 0x0),
+                0x1 => x86_64::software_interrupt!($high << 4 | 0x1),
+                0x2 => x86_64::software_interrupt!($high << 4 | 0x2),
+                0x3 => x86_64::software_interrupt!($high << 4 | 0x3),
+                0x4 => x86_64::software_interrupt!($high << 4 | 0x4),
+                0x5 => x86_64::software_interrupt!($high << 4 | 0x5),
+                0x6 => x86_64::software_interrupt!($high << 4 | 0x6),
+                0x7 => x86_64::software_interrupt!($high << 4 | 0x7),
+                0x8 => x86_64::software_interrupt!($high << 4 | 0x8),
+                0x9 => x86_64::software_interrupt!($high << 4 | 0x9),
+                0xa => x86_64::software_interrupt!($high << 4 | 0xa),
+                0xb => x86_64::software_interrupt!($high << 4 | 0xb),
+                0xc => x86_64::software_interrupt!($high << 4 | 0xc),
+                0xd => x86_64::software_interrupt!($high << 4 | 0xd),
+                0xe => x86_64::software_interrupt!($high << 4 | 0xe),
+                _ => x86_64::software_interrupt!($high << 4 | 0xf),
+            }
+        };
+    }
+
+    match vector >> 4 {
+        0x0 => int_row!(0x0),
+        0x1 => int_row!(0x1),
+        0x2 => int_row!(0x2),
+        0x3 => int_row!(0x3),
+        0x4 => int_row!(0x4),
+        0x5 => int_row!(0x5),
+        0x6 => int_row!(0x6),
+        0x7 => int_row!(0x7),
+        0x8 => int_row!(0x8),
+        0x9 => int_row!(0x9),
+        0xa => int_row!(0xa),
+        0xb => int_row!(0xb),
+        0xc => int_row!(0xc),
+        0xd => int_row!(0xd),
+        0xe => int_row!(0xe),
+        _ => int_row!(0xf),
+    }
+}
+
+#[test_case]
+fn test_trigger_software_interrupt() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+    extern "x86-interrupt" fn counting_handler(_stack_frame: InterruptStackFrame) {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // the vector is only known at runtime
+    let vector = core::hint::black_box(3);
+    with_handler(vector, counting_handler, || unsafe {
+        trigger_software_interrupt(vector)
+    });
+    assert_eq!(HITS.load(Ordering::SeqCst), 1);
 }
 
 /// Exception handler for a division by zero exception.
@@ -62,7 +208,7 @@ fn test_div_by_zero_exception() {
     // invoke a division by zero exception by invoking a 0x0 software interrupt.
     // This action is inheritly unsafe.
     unsafe {
-        x86_64::software_interrupt!(0x0);
+        trigger_software_interrupt(0x0);
     }
 }
 
@@ -76,7 +222,7 @@ fn test_debug_exception() {
     // invoke a debug exception by invoking a 0x1 software interrupt.
     // This action is inheritly unsafe.
     unsafe {
-        x86_64::software_interrupt!(0x1);
+        trigger_software_interrupt(0x1);
     }
 }
 
@@ -91,6 +237,26 @@ fn test_breakpoint_execption() {
     x86_64::instructions::interrupts::int3();
 }
 
+#[test_case]
+fn test_with_handler_restores_default() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+    extern "x86-interrupt" fn counting_handler(_stack_frame: InterruptStackFrame) {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let default = without_interrupts(|| IDT.lock().breakpoint);
+
+    with_handler(3, counting_handler, x86_64::instructions::interrupts::int3);
+    assert_eq!(HITS.load(Ordering::SeqCst), 1);
+    assert!(without_interrupts(|| IDT.lock().breakpoint) == default);
+
+    // the default handler is used again
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(HITS.load(Ordering::SeqCst), 1);
+}
+
 /// Exception handler for an overflow exception.
 extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
     println!("CPU EXCEPTION: OVERFLOW\n{:#?}", stack_frame);
@@ -101,8 +267,20 @@ fn test_overflow_exception() {
     // invoke an overflow exception by invoking a 0x4 software interrupt.
     // This action is inheritly unsafe.
     unsafe {
-        x86_64::software_interrupt!(0x4);
+        trigger_software_interrupt(0x4);
+    }
+}
+
+/// Exception handler for a non-maskable interrupt. Another core sends one on panic to halt this
+/// core, see `apic::halt_other_cores`.
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    // checked before anything else: the core may have been stopped in the middle of any code
+    if crate::apic::halt_requested() {
+        x86_64::instructions::interrupts::disable();
+        hlt_forever();
     }
+    counters::inc("nmi");
+    println!("CPU EXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}", stack_frame);
 }
 
 /// Exception handler for a double fault exception.
@@ -113,27 +291,378 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("CPU EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+/// Exception handler for an invalid TSS exception. The error code is the selector of the
+/// invalid segment.
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    println!("CPU EXCEPTION: INVALID TSS");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+
+    // returning would run into the fault again
+    hlt_forever();
+}
+
+/// Exception handler for a segment not present exception. The error code is the selector of
+/// the segment.
+extern "x86-interrupt" fn segment_not_present_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    println!("CPU EXCEPTION: SEGMENT NOT PRESENT");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+
+    // returning would run into the fault again
+    hlt_forever();
+}
+
+/// Exception handler for a stack-segment fault. The error code is the selector of the stack
+/// segment, or 0 for a limit violation.
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    println!("CPU EXCEPTION: STACK-SEGMENT FAULT");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+
+    // returning would run into the fault again
+    hlt_forever();
+}
+
+/// Exception handler for an x87 floating-point exception.
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    println!("CPU EXCEPTION: x87 FLOATING-POINT\n{:#?}", stack_frame);
+}
+
+#[test_case]
+fn test_x87_floating_point_exception() {
+    // invoke an x87 floating-point exception by invoking a 0x10 software interrupt.
+    // This action is inheritly unsafe.
+    unsafe {
+        trigger_software_interrupt(0x10);
+    }
+}
+
+/// Exception handler for an alignment check exception. It is only raised in user mode with
+/// alignment checking enabled.
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    println!("CPU EXCEPTION: ALIGNMENT CHECK");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+
+    // returning would run into the fault again
+    hlt_forever();
+}
+
+/// Exception handler for a machine check exception. The processor state may be corrupted, so
+/// the kernel cannot continue.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    panic!("CPU EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+}
+
+/// Exception handler for a SIMD floating-point exception.
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    println!("CPU EXCEPTION: SIMD FLOATING-POINT\n{:#?}", stack_frame);
+}
+
+#[test_case]
+fn test_simd_floating_point_exception() {
+    // invoke a SIMD floating-point exception by invoking a 0x13 software interrupt.
+    // This action is inheritly unsafe.
+    unsafe {
+        trigger_software_interrupt(0x13);
+    }
+}
+
+/// Exception handler for a virtualization exception.
+extern "x86-interrupt" fn virtualization_handler(stack_frame: InterruptStackFrame) {
+    println!("CPU EXCEPTION: VIRTUALIZATION\n{:#?}", stack_frame);
+}
+
+#[test_case]
+fn test_virtualization_exception() {
+    // invoke a virtualization exception by invoking a 0x14 software interrupt.
+    // This action is inheritly unsafe.
+    unsafe {
+        trigger_software_interrupt(0x14);
+    }
+}
+
 /// Exception handler for a page fault exception.
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2; // CR2 is populated with the accessed address at page fault
 
+    counters::inc("page_fault");
+
+    if recover_probe_fault(&mut stack_frame, Cr2::read(), error_code) {
+        return;
+    }
+
     println!("CPU EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("{}", PageFaultDescription::new(Cr2::read(), error_code));
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
 
     hlt_forever();
 }
 
+/// Human readable description of a page fault, e.g. "write to read-only page at 0x1000 from
+/// kernel mode".
+#[derive(Debug, Clone, Copy)]
+pub struct PageFaultDescription {
+    address: VirtAddr,
+    error_code: PageFaultErrorCode,
+}
+
+impl PageFaultDescription {
+    pub fn new(address: VirtAddr, error_code: PageFaultErrorCode) -> Self {
+        PageFaultDescription {
+            address,
+            error_code,
+        }
+    }
+}
+
+impl fmt::Display for PageFaultDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.error_code;
+        let protection = code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+
+        let (access, page) = if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            ("instruction fetch from", "non-executable page")
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            ("write to", "read-only page")
+        } else {
+            ("read from", "protected page")
+        };
+        let page = if protection { page } else { "non-present page" };
+        let mode = if code.contains(PageFaultErrorCode::USER_MODE) {
+            "user"
+        } else {
+            "kernel"
+        };
+
+        write!(
+            f,
+            "{} {} at {:#x} from {} mode",
+            access,
+            page,
+            self.address.as_u64(),
+            mode
+        )?;
+        if code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            write!(f, " (reserved bit set in page table)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Details of a page fault that was recovered from by `probe_read` or `probe_write`.
+#[derive(Debug, Clone, Copy)]
+pub struct RecoveredFault {
+    /// The accessed address.
+    pub address: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+    /// Address of the faulting instruction.
+    pub instruction_pointer: VirtAddr,
+}
+
+impl RecoveredFault {
+    /// Returns a human readable description of the fault.
+    pub fn describe(&self) -> PageFaultDescription {
+        PageFaultDescription::new(self.address, self.error_code)
+    }
+}
+
+/// Machine code of `mov rax, [rdi]`, the read performed by `probe_read`.
+const PROBE_INSTRUCTION: [u8; 3] = [0x48, 0x8b, 0x07];
+/// Machine code of `mov [rdi], rax`, the write performed by `probe_write`.
+const PROBE_WRITE_INSTRUCTION: [u8; 3] = [0x48, 0x89, 0x07];
+
+// the fault region [start, end), empty when start == end
+static FAULT_REGION_START: AtomicU64 = AtomicU64::new(0);
+static FAULT_REGION_END: AtomicU64 = AtomicU64::new(0);
+static RECOVERED_FAULT: spin::Mutex<Option<RecoveredFault>> = spin::Mutex::new(None);
+
+/// Registers the region of `size` bytes at `start` in which page faults caused by `probe_read`
+/// or `probe_write` are recovered from instead of halting the kernel. Intended for tests of the fault path.
+pub fn set_fault_region(start: VirtAddr, size: u64) {
+    FAULT_REGION_START.store(start.as_u64(), Ordering::SeqCst);
+    FAULT_REGION_END.store(start.as_u64() + size, Ordering::SeqCst);
+}
+
+/// Removes the fault region, so that all page faults halt the kernel again.
+pub fn clear_fault_region() {
+    FAULT_REGION_END.store(FAULT_REGION_START.load(Ordering::SeqCst), Ordering::SeqCst);
+}
+
+/// Returns and clears the details of the last page fault recovered from.
+pub fn take_recovered_fault() -> Option<RecoveredFault> {
+    without_interrupts(|| RECOVERED_FAULT.lock().take())
+}
+
+/// Reads the `u64` at `addr`. If the read page faults inside the region registered with
+/// `set_fault_region`, the fault is recorded (see `take_recovered_fault`) and 0 is returned.
+///
+/// # Safety
+/// Outside of the fault region this is a plain read of a raw pointer, so the caller must
+/// guarantee that `addr` is valid to read.
+pub unsafe fn probe_read(addr: *const u64) -> u64 {
+    let value: u64;
+    // the instruction has to match `PROBE_INSTRUCTION` so the handler can skip it. rax keeps its
+    // initial 0 when the read is skipped.
+    asm!("mov rax, [rdi]", in("rdi") addr, inout("rax") 0u64 => value, options(nostack));
+    value
+}
+
+/// Writes `value` to the `u64` at `addr`. If the write page faults inside the region registered
+/// with `set_fault_region`, the fault is recorded (see `take_recovered_fault`) and the write is
+/// skipped.
+///
+/// # Safety
+/// Outside of the fault region this is a plain write to a raw pointer, so the caller must
+/// guarantee that `addr` is valid to write.
+pub unsafe fn probe_write(addr: *mut u64, value: u64) {
+    // the instruction has to match `PROBE_WRITE_INSTRUCTION` so the handler can skip it
+    asm!("mov [rdi], rax", in("rdi") addr, in("rax") value, options(nostack));
+}
+
+/// Skips the faulting instruction if it is the access of `probe_read` or `probe_write` and
+/// `address` lies in the fault region. Returns whether the fault was recovered from.
+fn recover_probe_fault(
+    stack_frame: &mut InterruptStackFrame,
+    address: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> bool {
+    let start = FAULT_REGION_START.load(Ordering::SeqCst);
+    let end = FAULT_REGION_END.load(Ordering::SeqCst);
+    if !(start..end).contains(&address.as_u64()) {
+        return false;
+    }
+
+    let instruction_pointer = stack_frame.instruction_pointer;
+    // the instruction was just executed, so its bytes are readable
+    let instruction: [u8; 3] = unsafe { *instruction_pointer.as_ptr() };
+    if instruction != PROBE_INSTRUCTION && instruction != PROBE_WRITE_INSTRUCTION {
+        return false;
+    }
+
+    *RECOVERED_FAULT.lock() = Some(RecoveredFault {
+        address,
+        error_code,
+        instruction_pointer,
+    });
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer += PROBE_INSTRUCTION.len() as u64;
+        });
+    }
+    true
+}
+
+#[test_case]
+fn test_page_fault_recovery() {
+    // nothing is mapped here
+    let region = VirtAddr::new(0x_6666_0000_0000);
+    set_fault_region(region, 4096);
+
+    let value = unsafe { probe_read((region + 8u64).as_ptr()) };
+    clear_fault_region();
+
+    assert_eq!(value, 0);
+    let fault = take_recovered_fault().expect("no page fault recorded");
+    assert_eq!(fault.address, region + 8u64);
+    assert!(!fault
+        .error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    assert!(!fault
+        .error_code
+        .contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+    // execution continued after the fault
+    assert!(take_recovered_fault().is_none());
+}
+
+#[test_case]
+fn test_page_fault_description() {
+    use crate::util::FixedString;
+    use core::fmt::Write;
+
+    let describe = |error_code| {
+        let mut s = FixedString::<96>::new();
+        write!(
+            s,
+            "{}",
+            PageFaultDescription::new(VirtAddr::new(0x1000), error_code)
+        )
+        .unwrap();
+        s
+    };
+
+    assert_eq!(
+        describe(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+            .as_str(),
+        "write to read-only page at 0x1000 from kernel mode"
+    );
+    assert_eq!(
+        describe(PageFaultErrorCode::USER_MODE).as_str(),
+        "read from non-present page at 0x1000 from user mode"
+    );
+    assert_eq!(
+        describe(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::INSTRUCTION_FETCH)
+            .as_str(),
+        "instruction fetch from non-executable page at 0x1000 from kernel mode"
+    );
+}
+
+/// Vector of the software interrupt raised by `trace_point!`.
+pub const TRACE_POINT_VECTOR: u8 = vectors::TRACE_POINT;
+
+/// Raises the trace point interrupt, which logs the running task to the serial port and returns.
+/// This gives a lightweight tracepoint for stepping through the executor without a debugger.
+///
+/// Must not be used while the serial port is locked.
+#[macro_export]
+macro_rules! trace_point {
+    () => {
+        unsafe { $crate::idt::trigger_software_interrupt($crate::idt::TRACE_POINT_VECTOR) }
+    };
+}
+
+/// Handler for the trace point software interrupt.
+extern "x86-interrupt" fn trace_point_handler(stack_frame: InterruptStackFrame) {
+    counters::inc("trace_point");
+    match crate::task::current_task_id() {
+        Some(id) => serial_println!(
+            "TRACE POINT: task {} at {:?}",
+            id,
+            stack_frame.instruction_pointer
+        ),
+        None => serial_println!(
+            "TRACE POINT: no task at {:?}",
+            stack_frame.instruction_pointer
+        ),
+    }
+}
+
+#[test_case]
+fn test_trace_point() {
+    let before = counters::get("trace_point");
+    trace_point!();
+    assert_eq!(counters::get("trace_point"), before + 1);
+}
+
 // ----------------------------------------------------------------
 // Implementation of the PIC8259 hardware interrupts follows below:
 // ----------------------------------------------------------------
 
-pub const PIC_1_OFFSET: u8 = 32;
-pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+pub use crate::vectors::{PIC_1_OFFSET, PIC_2_OFFSET};
 
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
@@ -144,6 +673,8 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + 4,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -173,11 +704,13 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 
     // print!("\r{}", core::str::from_utf8(&s).unwrap());
 
+    crate::metrics::record_timer_irq_entry();
+    counters::inc("timer");
+    time::tick();
+    crate::task::timer::wake_due();
+
     // send EOI after successful handling
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    interrupts::end_of_interrupt(InterruptIndex::Timer.as_u8());
 }
 
 /// Interrupt handler for the PS/2 Keyboard interrupt.
@@ -187,11 +720,50 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     // read the scancode from the PS/2 port (0x60)
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
+    counters::inc("keyboard");
     crate::task::keyboard::add_scancode(scancode);
 
     // send EOI after successful handling
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    interrupts::end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+}
+
+/// Interrupt handler for the PS/2 mouse, raised for every byte of a movement packet.
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    // the mouse shares the data port (0x60) with the keyboard
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    counters::inc("mouse");
+    crate::task::mouse::add_byte(byte);
+
+    // send EOI after successful handling
+    interrupts::end_of_interrupt(InterruptIndex::Mouse.as_u8());
+}
+
+/// Interrupt handler for the COM1 receive interrupt.
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // the interrupt is raised once for all bytes in the receive FIFO
+    while let Some(byte) = crate::serial::try_read_byte() {
+        crate::task::serial::add_byte(byte);
     }
+    counters::inc("serial");
+
+    // send EOI after successful handling
+    interrupts::end_of_interrupt(InterruptIndex::Serial.as_u8());
+}
+
+/// Interrupt handler for the periodic local APIC timer, see `apic::start_timer`.
+pub(crate) extern "x86-interrupt" fn apic_timer_handler(_stack_frame: InterruptStackFrame) {
+    counters::inc("apic_timer");
+    time::tick();
+    crate::task::timer::wake_due();
+
+    crate::apic::send_eoi();
+}
+
+/// Interrupt handler for spurious interrupts of the local APIC. These must not be acknowledged
+/// with an EOI.
+extern "x86-interrupt" fn apic_spurious_handler(_stack_frame: InterruptStackFrame) {
+    counters::inc("apic_spurious");
 }
@@ -0,0 +1,238 @@
+use crate::{idt::PICS, metrics, vectors};
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+
+/// A guard that keeps interrupts disabled while it is alive. When dropped the interrupt flag is
+/// restored to the state it had when the guard was created, so guards can be nested safely.
+#[must_use = "interrupts are re-enabled as soon as the guard is dropped"]
+pub struct InterruptGuard {
+    // whether interrupts were enabled before the guard disabled them
+    were_enabled: bool,
+    // time stamp at which the guard disabled interrupts
+    start: u64,
+}
+
+/// Disables interrupts until the returned guard is dropped.
+///
+/// This is the RAII counterpart of `without_interrupts` for longer critical sections. The time
+/// interrupts stay disabled is recorded, see `metrics::max_irq_disabled_ticks`.
+pub fn guard() -> InterruptGuard {
+    let were_enabled = interrupts::are_enabled();
+    if were_enabled {
+        interrupts::disable();
+    }
+    InterruptGuard {
+        were_enabled,
+        start: metrics::timestamp(),
+    }
+}
+
+/// Runs `f` with interrupts disabled and restores the previous interrupt flag afterwards. Like
+/// `x86_64::instructions::interrupts::without_interrupts`, but the time interrupts stay
+/// disabled is recorded, see `metrics::max_irq_disabled_ticks`.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = guard();
+    f()
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            metrics::record_irq_disabled(metrics::timestamp() - self.start);
+            interrupts::enable();
+        }
+    }
+}
+
+/// The interrupt controller that delivers external interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptController {
+    /// The legacy chained 8259 PICs.
+    Pic,
+    /// The local APIC.
+    Apic,
+}
+
+static CONTROLLER: AtomicU8 = AtomicU8::new(InterruptController::Pic as u8);
+
+/// Returns the interrupt controller that is currently in use.
+pub fn controller() -> InterruptController {
+    match CONTROLLER.load(Ordering::Acquire) {
+        0 => InterruptController::Pic,
+        _ => InterruptController::Apic,
+    }
+}
+
+/// Selects the interrupt controller used for end-of-interrupt signalling and IRQ masking.
+/// This is done once during initialization after the controller has been set up.
+pub fn set_controller(controller: InterruptController) {
+    CONTROLLER.store(controller as u8, Ordering::Release);
+}
+
+/// An operation that is dispatched to the active interrupt controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerOp {
+    EndOfInterrupt(u8),
+    Mask(u8),
+    Unmask(u8),
+}
+
+/// When set, operations are passed to this hook instead of the interrupt controller hardware.
+#[cfg(test)]
+static TEST_HOOK: spin::Mutex<Option<fn(InterruptController, ControllerOp)>> =
+    spin::Mutex::new(None);
+
+/// Performs `op` on the active interrupt controller.
+fn dispatch(op: ControllerOp) {
+    let controller = controller();
+
+    #[cfg(test)]
+    if let Some(hook) = *TEST_HOOK.lock() {
+        return hook(controller, op);
+    }
+
+    match (controller, op) {
+        (InterruptController::Apic, ControllerOp::EndOfInterrupt(vector))
+            if !vectors::is_pic_irq(vector) =>
+        {
+            crate::apic::send_eoi()
+        }
+        // legacy IRQs come from the PIC also with the local APIC in charge, see `apic::init`
+        (_, ControllerOp::EndOfInterrupt(vector)) => unsafe {
+            PICS.lock().notify_end_of_interrupt(vector)
+        },
+        (_, ControllerOp::Mask(irq)) => set_pic_mask(irq, true),
+        (_, ControllerOp::Unmask(irq)) => set_pic_mask(irq, false),
+    }
+}
+
+/// Sets or clears the mask bit of the PIC 8259 line `irq` (0-15).
+fn set_pic_mask(irq: u8, masked: bool) {
+    // the master PIC handles IRQs 0-7, the slave PIC handles IRQs 8-15
+    let (port, line) = if irq < 8 {
+        (0x21, irq)
+    } else {
+        (0xa1, irq - 8)
+    };
+    let mut port: Port<u8> = Port::new(port);
+
+    let _guard = guard();
+    unsafe {
+        let mask = port.read();
+        if masked {
+            port.write(mask | (1 << line));
+        } else {
+            port.write(mask & !(1 << line));
+        }
+    }
+}
+
+/// Signals the end of the interrupt with the given vector to the active interrupt controller.
+/// Must be called at the end of every external interrupt handler.
+pub fn end_of_interrupt(vector: u8) {
+    dispatch(ControllerOp::EndOfInterrupt(vector));
+}
+
+/// Masks the hardware interrupt line `irq` so that it is no longer delivered.
+///
+/// Legacy IRQs are delivered by the PIC also with the local APIC in charge, so the line is
+/// masked on the PIC either way.
+pub fn mask_irq(irq: u8) {
+    dispatch(ControllerOp::Mask(irq));
+}
+
+/// Unmasks the hardware interrupt line `irq`.
+pub fn unmask_irq(irq: u8) {
+    dispatch(ControllerOp::Unmask(irq));
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that interrupts are disabled inside the guard and enabled again afterwards.
+#[test_case]
+fn guard_disables_and_restores() {
+    assert!(interrupts::are_enabled());
+    {
+        let _guard = guard();
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+}
+
+/// Test that a nested guard restores the disabled state of the outer guard.
+#[test_case]
+fn guard_nested_restores_prior_state() {
+    {
+        let _outer = guard();
+        {
+            let _inner = guard();
+            assert!(!interrupts::are_enabled());
+        }
+        // the inner guard must not enable interrupts the outer guard disabled
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+}
+
+/// Test that controller operations are dispatched to the selected controller.
+#[test_case]
+fn controller_dispatch_follows_selection() {
+    static LAST_OP: spin::Mutex<Option<(InterruptController, ControllerOp)>> =
+        spin::Mutex::new(None);
+    fn record(controller: InterruptController, op: ControllerOp) {
+        *LAST_OP.lock() = Some((controller, op));
+    }
+
+    // keep the timer from sending its end of interrupt into the hook
+    let _guard = guard();
+    *TEST_HOOK.lock() = Some(record);
+
+    set_controller(InterruptController::Apic);
+    end_of_interrupt(0x30);
+    assert_eq!(
+        *LAST_OP.lock(),
+        Some((
+            InterruptController::Apic,
+            ControllerOp::EndOfInterrupt(0x30)
+        ))
+    );
+    mask_irq(1);
+    assert_eq!(
+        *LAST_OP.lock(),
+        Some((InterruptController::Apic, ControllerOp::Mask(1)))
+    );
+
+    set_controller(InterruptController::Pic);
+    end_of_interrupt(0x20);
+    assert_eq!(
+        *LAST_OP.lock(),
+        Some((InterruptController::Pic, ControllerOp::EndOfInterrupt(0x20)))
+    );
+    unmask_irq(1);
+    assert_eq!(
+        *LAST_OP.lock(),
+        Some((InterruptController::Pic, ControllerOp::Unmask(1)))
+    );
+
+    *TEST_HOOK.lock() = None;
+}
+
+/// Test that legacy IRQs are masked on the PIC with the local APIC in charge.
+#[test_case]
+fn controller_apic_masks_pic_line() {
+    let mut master: Port<u8> = Port::new(0x21);
+    // no interrupt may see the APIC selected while the PIC delivers it
+    let _guard = guard();
+    let before = unsafe { master.read() };
+    set_controller(InterruptController::Apic);
+    mask_irq(1);
+    assert_ne!(unsafe { master.read() } & 0b10, 0);
+    unmask_irq(1);
+    assert_eq!(unsafe { master.read() } & 0b10, 0);
+    set_controller(InterruptController::Pic);
+    unsafe { master.write(before) };
+}
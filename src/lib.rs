@@ -8,18 +8,38 @@
 #![feature(asm_const)]
 // needed for implementing a linked list allocator
 #![feature(const_mut_refs)]
-
+// print heap diagnostics when an allocation fails
+#![feature(alloc_error_handler)]
+
+pub mod acpi;
+pub mod apic;
+pub mod boot;
+pub mod counters;
+pub mod cpu;
 pub mod gdt;
 pub mod heap;
 pub mod idt;
+pub mod interrupts;
+pub mod log;
 pub mod memory;
+pub mod metrics;
+pub mod pit;
+pub mod ps2;
+pub mod selftest;
 pub mod serial;
 pub mod task;
+pub mod time;
+pub mod usermode;
+pub mod util;
+pub mod vectors;
 pub mod vga_buffer;
 
 #[allow(unused_imports)]
 use bootloader::{entry_point, BootInfo};
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 extern crate alloc;
 
@@ -40,20 +60,81 @@ fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
 
 /// Initializes IDT and GDT.
 pub fn init() {
-    println!("Initializing IDT...");
+    status_println!("Initializing IDT...");
     idt::init_idt();
-    println!("IDT initialized.");
+    status_println!("IDT initialized.");
 
-    println!("Initializing GDT...");
+    status_println!("Initializing GDT...");
     gdt::init();
-    println!("GDT initialized.");
+    status_println!("GDT initialized.");
 
     // Initialize the PIC 8259 interrupt controller.
-    print!("Initializing 8259 PIC... ");
+    status_print!("Initializing 8259 PIC... ");
+    // every vector the PIC raises needs a handler
+    boot::require(boot::Stage::Idt);
     unsafe { idt::PICS.lock().initialize() };
-    println!("[ok]");
+    interrupts::set_controller(interrupts::InterruptController::Pic);
+    boot::complete(boot::Stage::Pic);
+    status_println!("[ok]");
+
+    // the tick length is fixed from now on
+    pit::set_frequency(pit::TIMER_FREQUENCY);
+
+    // the double fault handler runs on a stack from the TSS
+    boot::require(boot::Stage::Gdt);
+    boot::require(boot::Stage::Pic);
     x86_64::instructions::interrupts::enable();
-    println!("Enabled external interrupts.");
+    boot::complete(boot::Stage::Interrupts);
+    status_println!("Enabled external interrupts.");
+
+    if !boot::args().quiet() {
+        cpu::print_cpu_info();
+    }
+
+    status_print!("Reading real-time clock... ");
+    time::init();
+    status_println!("[ok] {}", time::now());
+}
+
+/// Set when the first panic starts being handled.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Called at the start of a panic handler. Returns true for the first panic and false if
+/// another panic is already being handled.
+///
+/// A panic raised while handling a panic is most likely caused by the handler itself (e.g. a
+/// deadlocked writer), so the handler must then stop right away with `halt_nested_panic`
+/// instead of formatting or printing anything, which would recurse.
+pub fn begin_panic() -> bool {
+    !PANICKING.swap(true, Ordering::SeqCst)
+}
+
+/// Stops the CPU after a nested panic. Interrupts are disabled so that no handler runs again.
+pub fn halt_nested_panic() -> ! {
+    x86_64::instructions::interrupts::disable();
+    hlt_forever();
+}
+
+/// Prints the panic `info` to the VGA text buffer in a high-visibility color scheme (white on
+/// red). The previous color is not restored as the kernel halts after a panic anyway.
+///
+/// The writer is used also if it is locked, e.g. by the code that panicked, see
+/// `vga_buffer::print_forced`.
+pub fn print_panic(info: &PanicInfo) {
+    use vga_buffer::Color;
+
+    vga_buffer::print_forced(format_args!("{}\n", info), Color::White, Color::Red);
+}
+
+/// Writes the panic `info` to the first serial port, also if the port was locked when the
+/// panic happened. Headless runs (`-display none`) only see this output.
+pub fn serial_print_panic(info: &PanicInfo) {
+    write_panic(&mut serial::ForcedWriter, info);
+}
+
+/// Writes the panic `info` to `out` the way `serial_print_panic` reports it.
+pub fn write_panic(out: &mut impl core::fmt::Write, info: &PanicInfo) {
+    let _ = writeln!(out, "{}", info);
 }
 
 pub fn hlt_forever() -> ! {
@@ -101,6 +182,28 @@ where
     }
 }
 
+/// Spins until `cond` is true and fails with a panic if that does not happen within
+/// `timeout_ticks` timer ticks. Useful for tests that wait for hardware to change state.
+///
+/// The timeout is measured with the tick counter, so external interrupts must be enabled.
+#[macro_export]
+macro_rules! ktest_eventually {
+    ($cond:expr, $timeout_ticks:expr) => {{
+        let timeout: u64 = $timeout_ticks;
+        let start = $crate::time::ticks();
+        while !$cond {
+            if $crate::time::ticks() - start >= timeout {
+                panic!(
+                    "condition `{}` not true within {} ticks",
+                    stringify!($cond),
+                    timeout
+                );
+            }
+            ::core::hint::spin_loop();
+        }
+    }};
+}
+
 /// Helper function that is called by the kernel entry point when in test config
 /// to run tests.
 pub fn test_runner(tests: &[&dyn Testable]) {
@@ -113,6 +216,11 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    if !begin_panic() {
+        // panicked while reporting a failed test, report it without formatting
+        exit_qemu(QemuExitCode::Fail);
+        halt_nested_panic();
+    }
     serial_println!("[failed]\n");
     serial_println!("{}\n", info);
     exit_qemu(QemuExitCode::Fail);
@@ -126,9 +234,15 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 #[cfg(test)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
-    serial_println!("{}", info);
-    exit_qemu(QemuExitCode::Fail);
+    test_panic_handler(info)
+}
 
-    hlt_forever();
+// -- UNIT TESTS -- //
+
+/// Test that `ktest_eventually!` returns once its condition becomes true.
+#[test_case]
+fn eventually_becomes_true() {
+    let start = time::ticks();
+    ktest_eventually!(time::ticks() >= start + 3, 20);
+    assert!(time::ticks() >= start + 3);
 }
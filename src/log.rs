@@ -0,0 +1,266 @@
+use crate::{interrupts, serial};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use spin::Mutex;
+
+/// Number of lines kept in the ring buffer.
+pub const LINES: usize = 16;
+/// Maximum length of a line in bytes. Longer lines are truncated.
+pub const LINE_LEN: usize = 128;
+
+/// A single line of the ring buffer.
+#[derive(Clone, Copy)]
+struct Line {
+    bytes: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const fn empty() -> Self {
+        Line {
+            bytes: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len];
+        // truncation may have cut a multi-byte character in half
+        core::str::from_utf8(bytes).unwrap_or_else(|err| {
+            core::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default()
+        })
+    }
+}
+
+/// An allocation-free buffer holding the last `LINES` lines printed by the kernel.
+#[derive(Clone, Copy)]
+pub struct RingBuffer {
+    lines: [Line; LINES],
+    // index of the slot the next finished line is stored in
+    next: usize,
+    // number of finished lines stored, at most `LINES`
+    count: usize,
+    // the line that is currently being printed
+    current: Line,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        RingBuffer {
+            lines: [Line::empty(); LINES],
+            next: 0,
+            count: 0,
+            current: Line::empty(),
+        }
+    }
+
+    /// Appends `s`, finishing a line at every newline.
+    fn push_str(&mut self, s: &str) {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                self.finish_line();
+            } else if self.current.len < LINE_LEN {
+                self.current.bytes[self.current.len] = byte;
+                self.current.len += 1;
+            }
+        }
+    }
+
+    fn finish_line(&mut self) {
+        self.lines[self.next] = self.current;
+        self.next = (self.next + 1) % LINES;
+        self.count = (self.count + 1).min(LINES);
+        self.current.len = 0;
+    }
+
+    /// Returns the finished lines from the oldest to the most recent. A line that is still
+    /// being printed (not terminated by a newline yet) is not included.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        let start = (self.next + LINES - self.count) % LINES;
+        (0..self.count).map(move |i| self.lines[(start + i) % LINES].as_str())
+    }
+}
+
+static RING: Mutex<RingBuffer> = Mutex::new(RingBuffer::new());
+
+/// Records printed text in the ring buffer. Called by the print macros.
+pub fn record(s: &str) {
+    interrupts::without_interrupts(|| RING.lock().push_str(s));
+}
+
+/// Returns a copy of the ring buffer holding the most recently printed lines.
+pub fn ring_buffer() -> RingBuffer {
+    interrupts::without_interrupts(|| *RING.lock())
+}
+
+/// Writes the lines of the ring buffer to the serial port. Intended for the panic handler, so
+/// the buffer is skipped if it is locked instead of waiting for it, and the serial port is
+/// written even if it is locked, see `serial::print_forced`.
+pub fn dump() {
+    interrupts::without_interrupts(|| {
+        if let Some(ring) = RING.try_lock() {
+            serial::print_forced(format_args!("-- last {} lines --\n", ring.count));
+            for line in ring.lines() {
+                serial::print_forced(format_args!("{}\n", line));
+            }
+        }
+    });
+}
+
+/// Severity of a log message. Lower levels are more severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    /// Returns the level with the number `n`, as given with the `loglevel` boot option. 0 and 1
+    /// are errors only, numbers above 5 are traces.
+    pub fn from_number(n: u8) -> Self {
+        match n {
+            0 | 1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            3 => LogLevel::Info,
+            4 => LogLevel::Debug,
+            _ => LogLevel::Trace,
+        }
+    }
+
+    /// Returns the name printed in front of the messages of this level.
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// The least severe level that is still printed.
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Sets the least severe level that is still printed.
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns the least severe level that is still printed.
+pub fn level() -> LogLevel {
+    LogLevel::from_number(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Returns whether messages of `level` are printed.
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints a message of `level` from `module` to the screen and the serial port, unless the
+/// level is disabled. Called by the log macros.
+///
+/// Takes the locks of both outputs, so it must not be used in interrupt handlers.
+#[doc(hidden)]
+pub fn _log(level: LogLevel, module: &str, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    crate::println!("[{} {}] {}", level.name(), module, args);
+    // the screen output is already mirrored with the `serial_console` boot option
+    if !crate::vga_buffer::serial_mirror() {
+        crate::serial_println!("[{} {}] {}", level.name(), module, args);
+    }
+}
+
+/// Logs a message of the given level, see `log::_log`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+/// Logs an error, see `log!`.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Error, $($arg)*));
+}
+
+/// Logs a warning, see `log!`.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Warn, $($arg)*));
+}
+
+/// Logs an informational message, see `log!`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Info, $($arg)*));
+}
+
+/// Logs a debug message, see `log!`.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Debug, $($arg)*));
+}
+
+/// Logs a trace message, see `log!`.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Trace, $($arg)*));
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the ring buffer keeps the most recent lines in order.
+#[test_case]
+fn log_ring_buffer_keeps_recent_lines() {
+    for i in 0..LINES + 4 {
+        crate::println!("ring buffer line {}", i);
+    }
+
+    let ring = ring_buffer();
+    assert_eq!(ring.lines().count(), LINES);
+    let mut last = ring.lines().skip(LINES - 3);
+    assert_eq!(last.next(), Some("ring buffer line 17"));
+    assert_eq!(last.next(), Some("ring buffer line 18"));
+    assert_eq!(last.next(), Some("ring buffer line 19"));
+    assert_eq!(last.next(), None);
+}
+
+/// Test that messages below the level are suppressed.
+#[test_case]
+fn log_level_threshold() {
+    let last_line = || {
+        ring_buffer().lines().last().map(|line| {
+            let mut copy = crate::util::FixedString::<LINE_LEN>::new();
+            copy.push_str(line);
+            copy
+        })
+    };
+
+    set_level(LogLevel::Info);
+    assert_eq!(level(), LogLevel::Info);
+    crate::info!("shown {}", 1);
+    assert_eq!(last_line().as_deref(), Some("[INFO trust::log] shown 1"));
+    crate::debug!("hidden");
+    assert_eq!(last_line().as_deref(), Some("[INFO trust::log] shown 1"));
+
+    set_level(LogLevel::Debug);
+    crate::debug!("shown {}", 2);
+    assert_eq!(last_line().as_deref(), Some("[DEBUG trust::log] shown 2"));
+    crate::trace!("hidden");
+    assert_eq!(last_line().as_deref(), Some("[DEBUG trust::log] shown 2"));
+    set_level(LogLevel::Info);
+
+    assert_eq!(LogLevel::from_number(0), LogLevel::Error);
+    assert_eq!(LogLevel::from_number(3), LogLevel::Info);
+    assert_eq!(LogLevel::from_number(9), LogLevel::Trace);
+}
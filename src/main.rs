@@ -9,20 +9,47 @@
 extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
-use core::panic::PanicInfo;
+use core::{panic::PanicInfo, time::Duration};
 use trust::{
-    heap, memory, println,
-    task::{executor::Executor, keyboard, Task},
+    acpi, apic, boot, counters, heap, memory, print, println, selftest,
+    task::{
+        executor::Executor,
+        keyboard::{self, KeyAction, KeyDecoder},
+        mouse, serial,
+        stats::stats_reporter,
+        timer::sleep,
+        Task,
+    },
+    usermode, vga_buffer, QemuExitCode,
 };
 use x86_64::{structures::paging::Page, VirtAddr};
 
 entry_point!(kernel_main);
 
+/// Boot options, taken from the `TRUST_CMDLINE` environment variable at build time as the
+/// bootloader does not pass a command line. Supports `fgcolor=<color>`, `bgcolor=<color>`,
+/// `selftest`, which runs the self-test instead of the executor, and `usermode`, which runs the
+/// demo user program instead. See `boot::BootArgs` for the options handled by the kernel
+/// itself, e.g. `quiet` and `serial_console`.
+const BOOT_CMDLINE: &str = match option_env!("TRUST_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// Interval of the heap and uptime reports on the serial port.
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for ESC to open the boot options during boot.
+const BOOT_MENU_WINDOW: Duration = Duration::from_secs(1);
+
 /// This is the kernel entry point. It is called by the bootloader.
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     // kernel entry point
 
-    // print "Booting" to the screen
+    boot::set_cmdline(BOOT_CMDLINE);
+
+    // start on a fresh screen in the configured color and print "Booting" to it
+    vga_buffer::init_color(vga_buffer::cmdline_color(&boot::args()));
     println!("Booting tRust...");
 
     // initialize GDT, IDT and enable external interrupts
@@ -34,8 +61,50 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator =
         unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
+    // find the registers used by reboot and shutdown
+    if acpi::init(&mut frame_allocator).is_none() {
+        println!("No ACPI FADT found, using fallbacks for reboot and shutdown.");
+    }
+
+    // deliver interrupts through the local APIC, the legacy IRQs keep coming from the PIC, and
+    // tick from its timer instead of the PIT
+    if apic::has_local_apic()
+        && apic::init(&mut mapper, &mut frame_allocator)
+        && !apic::start_tick_timer()
+    {
+        println!("Calibrating the APIC timer failed, ticking from the PIT.");
+    }
+
     // initialize heap
-    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+    let heap_size = heap::init(&mut mapper, &mut frame_allocator)
+        .unwrap_or_else(|err| panic!("heap initialization failed: {}", err));
+    if heap_size < heap::HEAP_SIZE {
+        println!("Low memory: heap reduced to {} KiB.", heap_size / 1024);
+    }
+
+    if selftest::requested(&boot::args()) {
+        let summary = selftest::run(&mut mapper, &mut frame_allocator);
+        trust::exit_qemu(if summary.all_passed() {
+            QemuExitCode::Success
+        } else {
+            QemuExitCode::Fail
+        });
+        trust::hlt_forever();
+    }
+
+    if boot::args().flag("usermode") {
+        let (entry, stack) =
+            usermode::load_program(usermode::demo_program(), &mut mapper, &mut frame_allocator)
+                .unwrap_or_else(|err| panic!("loading the user program failed: {:?}", err));
+        unsafe { usermode::enter(entry, stack) };
+    }
+
+    // keys pressed from now on are queued, also before the executor runs
+    keyboard::init_queue();
+    println!("Press ESC for boot options...");
+    if keyboard::escape_pressed(BOOT_MENU_WINDOW) {
+        boot_menu();
+    }
 
     // map an unused page
     let page = Page::containing_address(VirtAddr::new(0xdeadbeef));
@@ -45,17 +114,57 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
     unsafe { page_ptr.offset(200).write_volatile(0xf021_f077_f065_f04e) };
 
+    // from now on the heap grows when it runs out of memory
+    heap::enable_growth(mapper, frame_allocator);
+
     // run tests when in test config
     #[cfg(test)]
     test_main();
 
     // test asynchronous tasks
     let mut executor = Executor::new();
-    executor.spawn(Task::new(print_async()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::with_name("print_async", print_async()));
+    executor.spawn(Task::with_name("print_dots", print_dots(5)));
+    executor.spawn(Task::with_name("keyboard", keyboard::print_keypresses()));
+    executor.spawn(Task::with_name("serial", serial::print_lines()));
+    executor.spawn(Task::with_name("mouse", mouse::print_events()));
+    executor.spawn(Task::with_name(
+        "stats_reporter",
+        stats_reporter(STATS_INTERVAL),
+    ));
     executor.run();
 }
 
+/// A simple menu shown before the executor starts. Returns when booting should continue.
+fn boot_menu() {
+    let mut decoder = KeyDecoder::new();
+    loop {
+        println!("Boot options:");
+        println!("  [h] show heap usage");
+        println!("  [c] show event counters");
+        println!("  [Enter] continue booting");
+
+        let key = keyboard::wait_for_key(&mut decoder, Duration::MAX).map(KeyAction::from);
+        match key {
+            Some(KeyAction::Char('h')) => println!("heap: {}", heap::stats()),
+            Some(KeyAction::Char('c')) => {
+                counters::for_each(|name, value| println!("{}: {}", name, value))
+            }
+            Some(KeyAction::Enter) => return,
+            _ => {}
+        }
+    }
+}
+
+/// Prints `count` dots, one every half second.
+async fn print_dots(count: usize) {
+    for _ in 0..count {
+        sleep(Duration::from_millis(500)).await;
+        print!(".");
+    }
+    println!();
+}
+
 async fn async_num() -> u32 {
     69420
 }
@@ -71,7 +180,17 @@ async fn print_async() {
 fn panic(info: &PanicInfo) -> ! {
     use trust::hlt_forever;
 
-    println!("{}", info);
+    if !trust::begin_panic() {
+        trust::halt_nested_panic();
+    }
+    // stop the other cores before printing
+    trust::apic::halt_other_cores();
+    // the mirror would lock the serial port, which the panicking code may hold
+    vga_buffer::set_serial_mirror(false);
+    trust::serial_print_panic(info);
+    trust::print_panic(info);
+    // the screen only shows the last lines, keep a longer history on the serial port
+    trust::log::dump();
     hlt_forever();
 }
 
@@ -1,11 +1,97 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+pub mod bitmap_frame_allocator;
+pub mod frame_ref;
+
+pub use self::bitmap_frame_allocator::BitmapFrameAllocator;
+
+use crate::boot;
+use bootloader::{
+    bootinfo::{MemoryMap, MemoryRegionType},
+    BootInfo,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        frame::PhysFrameRange,
+        mapper::{MapToError, UnmapError},
+        page::PageRange,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
 
+/// Returns whether `addr` is aligned to a 4 KiB page boundary.
+pub const fn is_page_aligned(addr: u64) -> bool {
+    addr % Size4KiB::SIZE == 0
+}
+
+/// Returns whether `addr` is aligned to a 2 MiB huge page boundary.
+pub const fn is_huge_aligned(addr: u64) -> bool {
+    addr % Size2MiB::SIZE == 0
+}
+
+/// Panics with the offending address if the `u64` address is not 4 KiB page aligned.
+#[macro_export]
+macro_rules! assert_page_aligned {
+    ($addr:expr) => {{
+        let addr: u64 = $addr;
+        assert!(
+            $crate::memory::is_page_aligned(addr),
+            "address {:#x} is not 4 KiB page aligned (offset {:#x})",
+            addr,
+            addr % 4096
+        );
+    }};
+}
+
+/// Virtual address at which the physical memory is mapped, set by `init`.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the virtual address through which the physical address `addr` can be accessed.
+///
+/// Returns None before `init` has been called.
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    if !boot::is_complete(boot::Stage::Memory) {
+        return None;
+    }
+    Some(VirtAddr::new(
+        PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64(),
+    ))
+}
+
+/// Returns the `PRESENT`, `WRITABLE` and `USER_ACCESSIBLE` flags of the mapping of `addr` in
+/// the active page table. A flag is only returned if every level of the page table sets it, as
+/// the CPU requires. Huge pages are supported.
+///
+/// Returns None if `addr` is not mapped or the physical memory is not mapped yet.
+pub fn access_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    use x86_64::registers::control::Cr3;
+
+    let mut table_addr = Cr3::read().0.start_address();
+    let mut flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    for (level, index) in indices.into_iter().enumerate() {
+        let table = unsafe { &*phys_to_virt(table_addr)?.as_ptr::<PageTable>() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        flags &= entry.flags();
+        // the level 3 and 2 entries of 1 GiB and 2 MiB pages map the frame directly
+        if (level == 1 || level == 2) && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            break;
+        }
+        table_addr = entry.addr();
+    }
+    Some(flags)
+}
+
 /// Initialize a new OffsetPageTable.
 ///
 /// # Safety
@@ -15,7 +101,38 @@ use x86_64::{
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let l4_page_table = active_l4_page_table(physical_memory_offset);
-    OffsetPageTable::new(l4_page_table, physical_memory_offset)
+    let mapper = OffsetPageTable::new(l4_page_table, physical_memory_offset);
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    boot::complete(boot::Stage::Memory);
+    mapper
+}
+
+/// Page table and frame allocator shared by the test cases of an integration test, see
+/// `init_test_memory`.
+static TEST_MEMORY: spin::Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> =
+    spin::Mutex::new(None);
+
+/// Initializes the page table and the frame allocator from `boot_info` and keeps them for the
+/// test cases of an integration test, which use them through `with_test_memory`.
+///
+/// # Safety
+/// The same as for `init` and `BootInfoFrameAllocator::init`, which this calls instead of the
+/// test.
+pub unsafe fn init_test_memory(boot_info: &'static BootInfo) {
+    let mapper = init(VirtAddr::new(boot_info.physical_memory_offset));
+    let frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_map);
+    *TEST_MEMORY.lock() = Some((mapper, frame_allocator));
+}
+
+/// Runs `f` with the page table and frame allocator set up by `init_test_memory`.
+///
+/// Panics if `init_test_memory` has not been called.
+pub fn with_test_memory<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R,
+) -> R {
+    let mut memory = TEST_MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().expect("test memory not initialized");
+    f(mapper, frame_allocator)
 }
 
 /// Returns a mutable reference to the active level 4 page table.
@@ -54,6 +171,240 @@ pub fn create_example_mapping(
     map_to_result.expect("map_to failed").flush();
 }
 
+/// Maps the physical `frames` one by one to the virtual `pages`. Unlike an identity mapping the
+/// virtual range can be placed anywhere in the address space.
+///
+/// Panics if the two ranges do not contain the same number of frames and pages.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the frames are not in use
+/// in a way that mapping them again would violate memory safety, e.g. by creating aliasing
+/// `&mut` references to the same physical memory.
+pub unsafe fn map_frames_to_pages(
+    frames: PhysFrameRange,
+    pages: PageRange,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert_eq!(
+        frames.end - frames.start,
+        pages.end - pages.start,
+        "frame and page ranges differ in length"
+    );
+
+    let flags = flags | PageTableFlags::PRESENT;
+    for (frame, page) in frames.zip(pages) {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+
+    Ok(())
+}
+
+/// Maps `page` to `frame` and counts the mapping as a reference to the frame, so that the same
+/// frame can be shared by several pages. Returns the new reference count of the frame.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that sharing the frame does not
+/// violate memory safety, e.g. by creating aliasing `&mut` references to its memory.
+pub unsafe fn map_shared(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<u64, MapToError<Size4KiB>> {
+    mapper
+        .map_to(
+            page,
+            frame,
+            flags | PageTableFlags::PRESENT,
+            frame_allocator,
+        )?
+        .flush();
+    Ok(frame_ref::inc(frame))
+}
+
+/// Unmaps a `page` mapped with `map_shared` and drops its reference to the frame. The frame is
+/// returned to `frame_deallocator` once the last reference is gone. Returns the remaining
+/// reference count of the frame.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the page is no longer
+/// accessed and that it was mapped with `map_shared`.
+pub unsafe fn unmap_shared(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<u64, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.flush();
+
+    let remaining = frame_ref::dec(frame);
+    if remaining == 0 {
+        frame_deallocator.deallocate_frame(frame);
+    }
+    Ok(remaining)
+}
+
+/// Unmaps the mapped pages of `pages` and returns their frames to `frame_deallocator`. Pages
+/// that are not mapped, e.g. because mapping the range failed halfway, are skipped. Returns the
+/// number of unmapped pages.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the pages are no longer
+/// accessed and that their frames are not in use elsewhere.
+pub unsafe fn unmap_range_lenient(
+    pages: PageRange,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> usize {
+    let mut unmapped = 0;
+    for page in pages {
+        // not mapped, or mapped by a huge page that is not ours to split
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            frame_deallocator.deallocate_frame(frame);
+            unmapped += 1;
+        }
+    }
+    unmapped
+}
+
+/// Error returned when a 2 MiB page could not be mapped or unmapped.
+#[derive(Debug)]
+pub enum HugePageError {
+    /// The virtual or physical address is not 2 MiB aligned.
+    Unaligned,
+    Map(MapToError<Size2MiB>),
+    Unmap(UnmapError),
+}
+
+/// Maps the 2 MiB page at `virt` to the 2 MiB frame at `phys` with a single level 2 entry, which
+/// has the `HUGE_PAGE` flag set.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the frame is not in use in a
+/// way that mapping it would violate memory safety, e.g. by creating aliasing `&mut`
+/// references to the same physical memory.
+pub unsafe fn map_to_2mib(
+    virt: VirtAddr,
+    phys: PhysAddr,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size2MiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), HugePageError> {
+    if !is_huge_aligned(virt.as_u64()) || !is_huge_aligned(phys.as_u64()) {
+        return Err(HugePageError::Unaligned);
+    }
+    let page = Page::<Size2MiB>::containing_address(virt);
+    let frame = PhysFrame::<Size2MiB>::containing_address(phys);
+    mapper
+        .map_to(
+            page,
+            frame,
+            flags | PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE,
+            frame_allocator,
+        )
+        .map_err(HugePageError::Map)?
+        .flush();
+    Ok(())
+}
+
+/// Unmaps the 2 MiB page at `virt` mapped with `map_to_2mib`. Returns the frame it was mapped
+/// to.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the page is no longer
+/// accessed.
+pub unsafe fn unmap_huge(
+    virt: VirtAddr,
+    mapper: &mut impl Mapper<Size2MiB>,
+) -> Result<PhysFrame<Size2MiB>, HugePageError> {
+    if !is_huge_aligned(virt.as_u64()) {
+        return Err(HugePageError::Unaligned);
+    }
+    let page = Page::<Size2MiB>::containing_address(virt);
+    let (frame, flush) = mapper.unmap(page).map_err(HugePageError::Unmap)?;
+    flush.flush();
+    Ok(frame)
+}
+
+/// Unmaps `page` and returns the page table frames that became empty to `frame_deallocator`,
+/// walking up from the level 1 table. The level 4 table is never freed. Returns the frame the
+/// page was mapped to, which is not deallocated.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the page is no longer
+/// accessed and that the freed page tables are not referenced by any other page table.
+pub unsafe fn unmap_and_free_tables(
+    page: Page,
+    mapper: &mut OffsetPageTable,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<PhysFrame, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+
+    let p4 = mapper.level_4_table();
+    // the walk succeeded for the unmap, so all tables down to level 1 exist
+    let p4_entry = &mut p4[page.p4_index()];
+    let p3_frame = p4_entry.frame().expect("level 3 table missing");
+    let p3 = table_at(p3_frame);
+    let p3_entry = &mut p3[page.p3_index()];
+    let p2_frame = p3_entry.frame().expect("level 2 table missing");
+    let p2 = table_at(p2_frame);
+    let p2_entry = &mut p2[page.p2_index()];
+    let p1_frame = p2_entry.frame().expect("level 1 table missing");
+
+    // free bottom up and stop at the first table that is still in use
+    if is_table_empty(table_at(p1_frame)) {
+        p2_entry.set_unused();
+        frame_deallocator.deallocate_frame(p1_frame);
+        if is_table_empty(p2) {
+            p3_entry.set_unused();
+            frame_deallocator.deallocate_frame(p2_frame);
+            if is_table_empty(p3) {
+                p4_entry.set_unused();
+                frame_deallocator.deallocate_frame(p3_frame);
+            }
+        }
+    }
+
+    // invlpg also drops the cached entries of the freed tables
+    flush.flush();
+    Ok(frame)
+}
+
+/// Returns whether all 512 entries of `table` are unused.
+pub fn is_table_empty(table: &PageTable) -> bool {
+    table.iter().all(|entry| entry.is_unused())
+}
+
+/// Returns a mutable reference to the page table in `frame`.
+///
+/// # Safety
+/// `frame` must hold a page table and no other reference to it may be alive.
+unsafe fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = phys_to_virt(frame.start_address()).expect("physical memory is not mapped yet");
+    &mut *virt.as_mut_ptr()
+}
+
+/// Identity maps the frame containing `addr` with caching disabled (PCD and PWT set). Memory
+/// mapped device registers and some firmware regions like ACPI tables must not be cached.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that identity mapping the frame
+/// does not violate memory safety, e.g. by aliasing memory that is already in use.
+pub unsafe fn id_map_uncached(
+    addr: PhysAddr,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = PhysFrame::containing_address(addr);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    mapper.identity_map(frame, flags, frame_allocator)?.flush();
+    Ok(())
+}
+
 /// A FrameAllocator taht always returns `None`
 pub struct EmptyFrameAllocator;
 
@@ -63,11 +414,20 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// Maximum number of frames that can be pinned.
+const MAX_PINNED: usize = 16;
+/// Maximum number of freed frames kept for reuse.
+const MAX_FREED: usize = 64;
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     // this field keeps track of the number of the next frame that the allocator should return
     next: usize,
+    // frames that must never be handed out again
+    pinned: [Option<PhysFrame>; MAX_PINNED],
+    // frames returned by `deallocate_frame` that are handed out before new ones
+    freed: [Option<PhysFrame>; MAX_FREED],
 }
 
 impl BootInfoFrameAllocator {
@@ -78,10 +438,37 @@ impl BootInfoFrameAllocator {
     /// map is valid. The main requirement is that all frames that are marked as `USABLE`
     /// in it are really unused.
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
+        let mut allocator = BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            pinned: [None; MAX_PINNED],
+            freed: [None; MAX_FREED],
+        };
+        // the VGA text buffer stays mapped for the whole lifetime of the kernel
+        allocator.pin(PhysFrame::containing_address(PhysAddr::new(0xb8000)));
+        allocator
+    }
+
+    /// Pins `frame` so that it is never handed out by this allocator, even when it is passed to
+    /// `deallocate_frame` after its mapping was torn down. Used for frames whose contents must
+    /// stay in place, like framebuffers, firmware tables, DMA buffers and MMIO regions.
+    ///
+    /// Panics if more than `MAX_PINNED` frames are pinned.
+    pub fn pin(&mut self, frame: PhysFrame) {
+        if self.is_pinned(frame) {
+            return;
         }
+        let slot = self
+            .pinned
+            .iter_mut()
+            .find(|f| f.is_none())
+            .expect("too many pinned frames");
+        *slot = Some(frame);
+    }
+
+    /// Returns whether `frame` is pinned.
+    pub fn is_pinned(&self, frame: PhysFrame) -> bool {
+        self.pinned.contains(&Some(frame))
     }
 
     /// Returns an iterator over the usable frames specified in the memory map.
@@ -103,8 +490,52 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        // reuse freed frames first. These are never pinned as `deallocate_frame` refuses them.
+        if let Some(frame) = self.freed.iter_mut().find_map(|f| f.take()) {
+            return Some(frame);
+        }
+
+        loop {
+            let frame = self.usable_frames().nth(self.next);
+            self.next += 1;
+            match frame {
+                Some(frame) if self.is_pinned(frame) => continue,
+                frame => return frame,
+            }
+        }
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    /// Returns `frame` to the allocator. Pinned frames are refused and never handed out again.
+    /// When too many frames are waiting for reuse the frame is leaked.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        if self.is_pinned(frame) {
+            return;
+        }
+        if let Some(slot) = self.freed.iter_mut().find(|f| f.is_none()) {
+            *slot = Some(frame);
+        }
     }
 }
+
+// -- UNIT TESTS -- //
+
+/// Test the page alignment helpers with aligned and misaligned addresses.
+#[test_case]
+fn memory_page_alignment() {
+    assert!(is_page_aligned(0));
+    assert!(is_page_aligned(0x1000));
+    assert!(is_page_aligned(0x20_0000));
+    assert!(!is_page_aligned(0x1001));
+    assert!(!is_page_aligned(0xfff));
+
+    assert!(is_huge_aligned(0));
+    assert!(is_huge_aligned(0x40_0000));
+    assert!(!is_huge_aligned(0x1000));
+    assert!(!is_huge_aligned(0x20_1000));
+
+    // aligned addresses pass, misaligned ones are covered by the `page_alignment` test
+    crate::assert_page_aligned!(0x5000);
+    crate::assert_page_aligned!(VirtAddr::new(0xdead_b000).as_u64());
+}
@@ -0,0 +1,128 @@
+use super::BootInfoFrameAllocator;
+use crate::{boot, util::Bitmap};
+use alloc::{vec, vec::Vec};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr,
+};
+
+/// A FrameAllocator keeping one bit per physical frame, set while the frame is in use. Unlike
+/// `BootInfoFrameAllocator` it can take back any number of frames.
+///
+/// The bitmap is allocated on the heap, so the allocator can only be created once the heap is
+/// initialized. Until then the `BootInfoFrameAllocator` is used, see `from_boot_allocator`.
+pub struct BitmapFrameAllocator {
+    words: Vec<u64>,
+}
+
+impl BitmapFrameAllocator {
+    /// Creates an allocator handing out the usable frames of `memory_map`. The bitmap covers the
+    /// frames up to the end of the highest usable region. All frames that are not usable (the
+    /// kernel, the boot information, firmware and reserved memory) are marked as used.
+    ///
+    /// # Safety
+    /// The caller must guarantee that all frames marked as `Usable` in the memory map are really
+    /// unused.
+    pub unsafe fn new(memory_map: &MemoryMap) -> Self {
+        boot::require(boot::Stage::Heap);
+
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+        let frames = usable()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+
+        let mut words = vec![u64::MAX; frames.div_ceil(64)];
+        let mut bitmap = Bitmap::new(&mut words);
+        for region in usable() {
+            for frame in region.range.start_frame_number..region.range.end_frame_number {
+                bitmap.clear(frame as usize);
+            }
+        }
+        BitmapFrameAllocator { words }
+    }
+
+    /// Takes over from the allocator used during boot. Frames it handed out or pinned stay in
+    /// use, frames it got back are free.
+    pub fn from_boot_allocator(boot_allocator: BootInfoFrameAllocator) -> Self {
+        // the boot allocator was created from a memory map that fulfills the requirements
+        let mut allocator = unsafe { Self::new(boot_allocator.memory_map) };
+        for frame in boot_allocator.usable_frames().take(boot_allocator.next) {
+            allocator.mark_used(frame);
+        }
+        for frame in boot_allocator.pinned.iter().flatten() {
+            allocator.mark_used(*frame);
+        }
+        for frame in boot_allocator.freed.iter().flatten() {
+            allocator.bitmap().clear(Self::index(*frame));
+        }
+        allocator
+    }
+
+    /// Marks `frame` as used so that it is never handed out. Frames beyond the end of the
+    /// bitmap are never handed out anyway.
+    pub fn mark_used(&mut self, frame: PhysFrame) {
+        let index = Self::index(frame);
+        let mut bitmap = self.bitmap();
+        if index < bitmap.len() {
+            bitmap.set(index);
+        }
+    }
+
+    /// Returns whether `frame` is in use or not managed by the allocator.
+    pub fn is_used(&self, frame: PhysFrame) -> bool {
+        let index = Self::index(frame);
+        self.words
+            .get(index / 64)
+            .map_or(true, |word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// Returns the number of free frames.
+    pub fn free_frames(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_zeros() as usize)
+            .sum()
+    }
+
+    fn bitmap(&mut self) -> Bitmap {
+        Bitmap::new(&mut self.words)
+    }
+
+    /// Returns the bit index of `frame`.
+    fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / frame.size()) as usize
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let mut bitmap = self.bitmap();
+        let index = bitmap.find_first_zero()?;
+        bitmap.set(index);
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            index as u64 * 4096,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// Returns `frame` to the allocator.
+    ///
+    /// Panics if the frame is not managed by the allocator or already free.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = Self::index(frame);
+        let mut bitmap = self.bitmap();
+        assert!(
+            index < bitmap.len() && bitmap.get(index),
+            "frame {:?} is not in use",
+            frame
+        );
+        bitmap.clear(index);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::interrupts;
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+/// Number of mappings of every frame with at least one mapping, indexed by frame number.
+/// Frames that are not mapped through `map_shared` have no entry.
+static COUNTS: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+/// Returns the frame number of `frame`.
+fn number(frame: PhysFrame) -> u64 {
+    frame.start_address().as_u64() / frame.size()
+}
+
+/// Increments the reference count of `frame` and returns the new count.
+pub fn inc(frame: PhysFrame) -> u64 {
+    interrupts::without_interrupts(|| {
+        let mut counts = COUNTS.lock();
+        let count = counts.entry(number(frame)).or_insert(0);
+        *count += 1;
+        *count
+    })
+}
+
+/// Decrements the reference count of `frame` and returns the new count. When it reaches 0 the
+/// frame is no longer referenced and may be freed.
+///
+/// Panics if the frame has no references.
+pub fn dec(frame: PhysFrame) -> u64 {
+    interrupts::without_interrupts(|| {
+        let mut counts = COUNTS.lock();
+        let count = counts
+            .get_mut(&number(frame))
+            .expect("reference count of an unreferenced frame decremented");
+        *count -= 1;
+
+        let count = *count;
+        if count == 0 {
+            counts.remove(&number(frame));
+        }
+        count
+    })
+}
+
+/// Returns the reference count of `frame`.
+pub fn count(frame: PhysFrame) -> u64 {
+    interrupts::without_interrupts(|| COUNTS.lock().get(&number(frame)).copied().unwrap_or(0))
+}
@@ -0,0 +1,107 @@
+use crate::pit;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Longest time interrupts were disabled by a critical section, in TSC ticks.
+static MAX_IRQ_DISABLED: AtomicU64 = AtomicU64::new(0);
+/// Longest time from the PIT raising the timer interrupt to the entry of its handler, in PIT
+/// cycles.
+static MAX_IRQ_LATENCY: AtomicU64 = AtomicU64::new(0);
+/// Time stamp at the entry of the last timer interrupt handler.
+static LAST_IRQ_ENTRY: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current value of the time stamp counter.
+pub(crate) fn timestamp() -> u64 {
+    crate::time::rdtsc()
+}
+
+/// Records that interrupts were disabled for `ticks` TSC ticks.
+pub(crate) fn record_irq_disabled(ticks: u64) {
+    MAX_IRQ_DISABLED.fetch_max(ticks, Ordering::Relaxed);
+}
+
+/// Returns the longest time in TSC ticks that interrupts were disabled by an
+/// `interrupts::guard` or `interrupts::without_interrupts`. Only the outermost of nested
+/// critical sections is measured.
+pub fn max_irq_disabled_ticks() -> u64 {
+    MAX_IRQ_DISABLED.load(Ordering::Relaxed)
+}
+
+/// Called on entry of the PIT timer interrupt handler. Records the time stamp and how far
+/// channel 0 has counted since it raised the interrupt.
+pub(crate) fn record_timer_irq_entry() {
+    LAST_IRQ_ENTRY.store(timestamp(), Ordering::Relaxed);
+    MAX_IRQ_LATENCY.fetch_max(pit::cycles_into_tick(), Ordering::Relaxed);
+}
+
+/// Returns the time stamp at the entry of the last timer interrupt handler, 0 before the first
+/// one.
+pub fn last_irq_entry() -> u64 {
+    LAST_IRQ_ENTRY.load(Ordering::Relaxed)
+}
+
+/// Returns the longest latency from the PIT raising the timer interrupt to the entry of its
+/// handler. The latency is measured with the counter of the PIT, so latencies of a whole tick or
+/// more wrap around.
+pub fn max_irq_latency() -> Duration {
+    let cycles = MAX_IRQ_LATENCY.load(Ordering::Relaxed);
+    Duration::from_nanos(cycles * 1_000_000_000 / pit::BASE_FREQUENCY)
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that a long critical section shows up as the maximum interrupt disabled time.
+#[test_case]
+fn metrics_irq_disabled_busy_loop() {
+    const BUSY_TICKS: u64 = 50_000_000;
+
+    {
+        let _guard = crate::interrupts::guard();
+        let start = timestamp();
+        while timestamp() - start < BUSY_TICKS {
+            core::hint::spin_loop();
+        }
+    }
+    assert!(max_irq_disabled_ticks() >= BUSY_TICKS);
+}
+
+/// Test that `without_interrupts` sections are measured like guards.
+#[test_case]
+fn metrics_without_interrupts_measured() {
+    // longer than the section of the previous test
+    const BUSY_TICKS: u64 = 100_000_000;
+
+    crate::interrupts::without_interrupts(|| {
+        let start = timestamp();
+        while timestamp() - start < BUSY_TICKS {
+            core::hint::spin_loop();
+        }
+    });
+    assert!(max_irq_disabled_ticks() >= BUSY_TICKS);
+}
+
+/// Test that a timer interrupt held back by a critical section shows up as its latency.
+#[test_case]
+fn metrics_timer_irq_latency() {
+    let divisor = u64::from(pit::divisor());
+    let entry = last_irq_entry();
+    {
+        let _guard = crate::interrupts::guard();
+        // wait for the PIT to raise the timer interrupt while it cannot be handled, and hold it
+        // back for another half tick
+        while pit::cycles_into_tick() < divisor * 3 / 4 {
+            core::hint::spin_loop();
+        }
+        while pit::cycles_into_tick() >= divisor / 4 {
+            core::hint::spin_loop();
+        }
+        while pit::cycles_into_tick() < divisor / 2 {
+            core::hint::spin_loop();
+        }
+    }
+    crate::ktest_eventually!(last_irq_entry() != entry, 5);
+    // the handler reads the counter a little later, leave some room for rounding
+    assert!(max_irq_latency() >= crate::time::ticks_to_duration(1) * 2 / 5);
+}
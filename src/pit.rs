@@ -0,0 +1,183 @@
+use crate::{interrupts, time};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+/// Input frequency of the Intel 8253/8254 programmable interval timer (PIT) in Hz.
+pub const BASE_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+/// Frequency of the timer interrupt set up by `init`.
+pub const TIMER_FREQUENCY: u32 = 100;
+
+/// Lowest frequency channel 0 can run at, with the largest reload value of 65536.
+pub const MIN_FREQUENCY: u32 = 19;
+/// Highest frequency `set_frequency` accepts. Faster timer interrupts would mostly keep the
+/// CPU busy handling them.
+pub const MAX_FREQUENCY: u32 = 10_000;
+
+/// Reload value of channel 0. Until the PIT is reprogrammed the BIOS default of 65536 (roughly
+/// 18.2 Hz) applies.
+static DIVISOR: AtomicU32 = AtomicU32::new(65536);
+
+/// Returns the reload value of channel 0, i.e. the number of PIT cycles per timer tick.
+pub fn divisor() -> u32 {
+    DIVISOR.load(Ordering::Relaxed)
+}
+
+/// Returns the reload value for a timer interrupt frequency of `hz`, which is clamped to
+/// `MIN_FREQUENCY..=MAX_FREQUENCY`.
+pub fn divisor_for(hz: u32) -> u32 {
+    let hz = hz.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+    // the reload value is 16 bit wide, where 0 stands for 65536
+    (BASE_FREQUENCY / u64::from(hz)).min(65536) as u32
+}
+
+/// Programs channel 0 to raise the timer interrupt `hz` times per second, see `divisor_for`.
+/// Returns the new divisor.
+///
+/// The tick based clock assumes a constant tick length, so this should only be called before
+/// interrupts are enabled, like in `init`.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = divisor_for(hz);
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL0_DATA);
+
+    interrupts::without_interrupts(|| unsafe {
+        // channel 0, low byte then high byte, square wave mode, binary counting
+        command.write(0x36);
+        let [low, high, _, _] = (divisor % 65536).to_le_bytes();
+        data.write(low);
+        data.write(high);
+        DIVISOR.store(divisor, Ordering::Relaxed);
+    });
+    divisor
+}
+
+/// Latches and reads the current count of channel 0. The counter counts down towards 0 and is
+/// then reloaded with the divisor, at which point the timer interrupt fires.
+pub fn current_count() -> u16 {
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL0_DATA);
+
+    interrupts::without_interrupts(|| unsafe {
+        // counter latch command for channel 0
+        command.write(0x00);
+        let low = data.read();
+        let high = data.read();
+        u16::from_le_bytes([low, high])
+    })
+}
+
+/// Returns the number of PIT cycles elapsed since the last reload of channel 0.
+pub(crate) fn cycles_into_tick() -> u64 {
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL0_DATA);
+
+    let (status, count) = interrupts::without_interrupts(|| unsafe {
+        // read-back command latching both the status and the count of channel 0
+        command.write(0b1100_0010);
+        let status = data.read();
+        let low = data.read();
+        let high = data.read();
+        (status, u16::from_le_bytes([low, high]))
+    });
+
+    let divisor = u64::from(divisor());
+    // a count of 0 is the reload value 65536
+    let count = if count == 0 { 65536 } else { u64::from(count) };
+    let mode = (status >> 1) & 0b111;
+
+    if mode == 3 || mode == 7 {
+        // square wave mode: the counter runs down in steps of 2 twice per period. The output is
+        // high during the first half and low during the second half.
+        let into_half = divisor.saturating_sub(count) / 2;
+        if status & 0x80 != 0 {
+            into_half
+        } else {
+            divisor / 2 + into_half
+        }
+    } else {
+        divisor.saturating_sub(count)
+    }
+}
+
+/// Largest value returned by `micros` so far.
+static LAST_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of microseconds since the PIC was enabled, with a resolution finer than
+/// a timer tick.
+///
+/// When called with interrupts disabled a tick that is pending but not yet handled is missed,
+/// so the result may stand still for up to one tick.
+pub fn micros() -> u64 {
+    let (ticks, cycles) = loop {
+        let ticks = time::ticks();
+        let cycles = cycles_into_tick();
+        // retry if a tick happened while reading the counter
+        if time::ticks() == ticks {
+            break (ticks, cycles);
+        }
+    };
+
+    let total_cycles = u128::from(ticks) * u128::from(divisor()) + u128::from(cycles);
+    let micros = (total_cycles * 1_000_000 / u128::from(BASE_FREQUENCY)) as u64;
+
+    // the counter is reloaded before its interrupt is handled, which would make the time jump
+    // back by one tick in between
+    let last = LAST_MICROS.fetch_max(micros, Ordering::Relaxed);
+    micros.max(last)
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the channel 0 counter is running.
+#[test_case]
+fn pit_counter_is_running() {
+    let first = current_count();
+    let mut second = current_count();
+    // both reads may fall into the same PIT cycle, so give the counter some time
+    for _ in 0..1000 {
+        if second != first {
+            break;
+        }
+        second = current_count();
+    }
+    // usually second < first, unless the counter was reloaded in between
+    assert_ne!(first, second);
+}
+
+/// Test that `micros` is monotonic and agrees with the tick counter.
+#[test_case]
+fn pit_micros_monotonic() {
+    let mut last = micros();
+    for _ in 0..1000 {
+        let now = micros();
+        assert!(now >= last);
+        last = now;
+    }
+
+    let tick_micros = time::uptime().as_micros() as u64;
+    let tick_length = time::ticks_to_duration(1).as_micros() as u64;
+    assert!(micros().abs_diff(tick_micros) <= tick_length);
+}
+
+/// Test the divisor computation and reprogramming the PIT.
+#[test_case]
+fn pit_set_frequency() {
+    assert_eq!(divisor_for(1000), 1193);
+    assert_eq!(divisor_for(100), 11931);
+    // clamped at both ends
+    assert_eq!(divisor_for(0), 62799);
+    assert_eq!(divisor_for(1_000_000), 119);
+
+    interrupts::without_interrupts(|| {
+        assert_eq!(set_frequency(1000), 1193);
+        assert_eq!(divisor(), 1193);
+        assert!(current_count() <= 1193);
+        // restore the tick length the clock relies on
+        set_frequency(TIMER_FREQUENCY);
+    });
+    assert_eq!(divisor(), divisor_for(TIMER_FREQUENCY));
+}
@@ -0,0 +1,190 @@
+use crate::{boot, pit, time, warn};
+use x86_64::instructions::port::Port;
+
+/// Data port of the 8042 PS/2 controller.
+const DATA_PORT: u16 = 0x60;
+/// Status register (read) and command register (write) of the 8042 PS/2 controller.
+const STATUS_PORT: u16 = 0x64;
+
+/// Status bit set while the output buffer holds a byte for the CPU.
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+/// Status bit set while the input buffer holds a byte not yet processed by the controller.
+const STATUS_INPUT_FULL: u8 = 0x02;
+
+/// Number of timer ticks to wait for the controller before giving up: 100 ms at the timer
+/// frequency set up by `init`.
+pub const DEFAULT_TIMEOUT: u64 = pit::TIMER_FREQUENCY as u64 / 10;
+
+/// Error returned when the controller did not become ready in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+/// Reads the status register of the controller.
+fn status() -> u8 {
+    let mut port: Port<u8> = Port::new(STATUS_PORT);
+    unsafe { port.read() }
+}
+
+/// Polls `read_status` until `(status & mask) == expected` or `timeout` timer ticks have passed.
+///
+/// The timeout is measured with the tick counter, so external interrupts must be enabled.
+fn wait_status(
+    mut read_status: impl FnMut() -> u8,
+    mask: u8,
+    expected: u8,
+    timeout: u64,
+) -> Result<(), Timeout> {
+    boot::require(boot::Stage::Interrupts);
+
+    let start = time::ticks();
+    loop {
+        if read_status() & mask == expected {
+            return Ok(());
+        }
+        if time::ticks() - start >= timeout {
+            return Err(Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits up to `timeout` timer ticks until the controller accepts a command or data byte.
+pub fn wait_input_empty(timeout: u64) -> Result<(), Timeout> {
+    wait_status(status, STATUS_INPUT_FULL, 0, timeout)
+}
+
+/// Waits up to `timeout` timer ticks until the controller has a byte for the CPU.
+pub fn wait_output_full(timeout: u64) -> Result<(), Timeout> {
+    wait_status(status, STATUS_OUTPUT_FULL, STATUS_OUTPUT_FULL, timeout)
+}
+
+/// Sends the command `command` to the controller. A timeout is logged and returned, the
+/// command is dropped then.
+pub fn command(command: u8) -> Result<(), Timeout> {
+    wait_input_empty(DEFAULT_TIMEOUT).map_err(|err| {
+        warn!("PS/2 controller timed out, command {:#x} dropped", command);
+        err
+    })?;
+
+    let mut port: Port<u8> = Port::new(STATUS_PORT);
+    unsafe { port.write(command) };
+    Ok(())
+}
+
+/// Writes `byte` to the data port of the controller. A timeout is logged and returned, the
+/// byte is dropped then.
+pub fn write_data(byte: u8) -> Result<(), Timeout> {
+    wait_input_empty(DEFAULT_TIMEOUT).map_err(|err| {
+        warn!("PS/2 controller timed out, data {:#x} dropped", byte);
+        err
+    })?;
+
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    unsafe { port.write(byte) };
+    Ok(())
+}
+
+/// Reads a byte from the data port of the controller. A timeout is logged and returned.
+pub fn read_data() -> Result<u8, Timeout> {
+    wait_output_full(DEFAULT_TIMEOUT).map_err(|err| {
+        warn!("PS/2 controller timed out, no data to read");
+        err
+    })?;
+
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    Ok(unsafe { port.read() })
+}
+
+/// Acknowledgement sent by a PS/2 device for a command or data byte.
+pub const ACK: u8 = 0xfa;
+/// Response of a PS/2 device asking to send the last byte again.
+pub const RESEND: u8 = 0xfe;
+
+/// Error returned when a PS/2 device could not be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The controller or the device did not respond in time.
+    Timeout,
+    /// The device answered with something else than an acknowledgement.
+    UnexpectedResponse(u8),
+}
+
+impl From<Timeout> for DeviceError {
+    fn from(_: Timeout) -> Self {
+        DeviceError::Timeout
+    }
+}
+
+/// Number of times a byte is sent again when a device asks for it.
+const MAX_RESENDS: usize = 3;
+
+/// Sends a byte to a device with `write` and waits for the acknowledgement. The byte is sent
+/// again when the device responds with `RESEND`.
+fn send_with_ack(mut write: impl FnMut() -> Result<(), Timeout>) -> Result<(), DeviceError> {
+    for _ in 0..=MAX_RESENDS {
+        write()?;
+        match read_data()? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            response => return Err(DeviceError::UnexpectedResponse(response)),
+        }
+    }
+    Err(DeviceError::UnexpectedResponse(RESEND))
+}
+
+/// Sends `byte` to the auxiliary (mouse) device and waits for its acknowledgement.
+fn write_mouse(byte: u8) -> Result<(), DeviceError> {
+    send_with_ack(|| {
+        command(0xd4)?;
+        write_data(byte)
+    })
+}
+
+/// Sends `byte` to the keyboard and waits for its acknowledgement. The keyboard interrupt
+/// must be masked, as the acknowledgement would be taken by its handler otherwise.
+pub fn write_keyboard(byte: u8) -> Result<(), DeviceError> {
+    send_with_ack(|| write_data(byte))
+}
+
+/// Enables the auxiliary port of the controller and the mouse connected to it. The mouse then
+/// sends 3 byte movement packets, each byte raising IRQ 12.
+///
+/// IRQ 12 should stay masked until this returns, so that the acknowledgements are not taken
+/// by the interrupt handler.
+pub fn enable_mouse() -> Result<(), DeviceError> {
+    // enable the auxiliary port
+    command(0xa8)?;
+
+    // enable IRQ 12 (bit 1) and the mouse clock (bit 5 disables it) in the configuration byte
+    command(0x20)?;
+    let config = read_data()?;
+    command(0x60)?;
+    write_data((config | 0x02) & !0x20)?;
+
+    // default settings, then enable data reporting
+    write_mouse(0xf6)?;
+    write_mouse(0xf4)
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that waiting on a controller that never becomes ready times out instead of hanging.
+#[test_case]
+fn ps2_wait_times_out() {
+    // input buffer always full
+    let start = time::ticks();
+    assert_eq!(
+        wait_status(|| STATUS_INPUT_FULL, STATUS_INPUT_FULL, 0, 3),
+        Err(Timeout)
+    );
+    assert!(time::ticks() - start >= 3);
+
+    // output buffer never full
+    assert_eq!(
+        wait_status(|| 0, STATUS_OUTPUT_FULL, STATUS_OUTPUT_FULL, 3),
+        Err(Timeout)
+    );
+
+    // a ready controller does not wait
+    assert_eq!(wait_status(|| 0, STATUS_INPUT_FULL, 0, 0), Ok(()));
+}
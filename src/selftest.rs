@@ -0,0 +1,205 @@
+use crate::{boot::BootArgs, idt, memory, println, serial_println, time};
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::{
+    structures::{
+        idt::InterruptStackFrame,
+        paging::{
+            mapper::{Translate, TranslateResult},
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags,
+            Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+/// Boot option that runs the self-test instead of the executor.
+pub const CMDLINE_OPTION: &str = "selftest";
+
+/// Unused page the paging check maps and unmaps again.
+const SCRATCH_PAGE: u64 = 0x_5e1f_7e57_0000;
+
+/// Returns whether the boot options `args` ask for the self-test.
+pub fn requested(args: &BootArgs) -> bool {
+    args.flag(CMDLINE_OPTION)
+}
+
+/// Number of passed and failed checks of a self-test run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Returns whether no check failed.
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Runs the sanity checks of the kernel subsystems and prints the result of every check and a
+/// summary to the screen and the serial port. Unlike the `#[test_case]` tests this runs in
+/// normal builds, e.g. to diagnose a machine the kernel misbehaves on.
+///
+/// Requires the heap to be initialized.
+pub fn run<A>(mapper: &mut OffsetPageTable, frame_allocator: &mut A) -> Summary
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    crate::boot::require(crate::boot::Stage::Heap);
+
+    let results = [
+        ("frame allocation", check_frames(frame_allocator)),
+        ("page mapping", check_paging(mapper, frame_allocator)),
+        ("heap allocation", check_heap()),
+        ("breakpoint", check_breakpoint()),
+        ("real-time clock", check_rtc()),
+    ];
+
+    let mut summary = Summary {
+        passed: 0,
+        failed: 0,
+    };
+    for (name, result) in results {
+        match result {
+            Ok(()) => {
+                summary.passed += 1;
+                println!("[selftest] {}... [ok]", name);
+                serial_println!("[selftest] {}... [ok]", name);
+            }
+            Err(reason) => {
+                summary.failed += 1;
+                println!("[selftest] {}... [failed] {}", name, reason);
+                serial_println!("[selftest] {}... [failed] {}", name, reason);
+            }
+        }
+    }
+    println!(
+        "[selftest] {} passed, {} failed",
+        summary.passed, summary.failed
+    );
+    serial_println!(
+        "[selftest] {} passed, {} failed",
+        summary.passed,
+        summary.failed
+    );
+    summary
+}
+
+/// Allocates a frame, frees it and checks that it is handed out again.
+fn check_frames<A>(frame_allocator: &mut A) -> Result<(), &'static str>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    let frame = frame_allocator.allocate_frame().ok_or("out of frames")?;
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    let again = frame_allocator.allocate_frame().ok_or("out of frames")?;
+    unsafe { frame_allocator.deallocate_frame(again) };
+    if again != frame {
+        return Err("freed frame was not reused");
+    }
+    Ok(())
+}
+
+/// Maps a scratch page, writes through it and unmaps it again.
+fn check_paging<A>(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut A,
+) -> Result<(), &'static str>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    let page = Page::containing_address(VirtAddr::new(SCRATCH_PAGE));
+    let frame = frame_allocator.allocate_frame().ok_or("out of frames")?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+        .map_err(|_| "mapping the page failed")?
+        .flush();
+
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let value = unsafe {
+        ptr.write_volatile(0x5e1f_7e57);
+        ptr.read_volatile()
+    };
+
+    let unmapped = unsafe { memory::unmap_and_free_tables(page, mapper, frame_allocator) }
+        .map_err(|_| "unmapping the page failed")?;
+    unsafe { frame_allocator.deallocate_frame(unmapped) };
+
+    if value != 0x5e1f_7e57 {
+        return Err("read back a different value");
+    }
+    if unmapped != frame {
+        return Err("page was mapped to a different frame");
+    }
+    if !matches!(
+        mapper.translate(page.start_address()),
+        TranslateResult::NotMapped
+    ) {
+        return Err("page is still mapped");
+    }
+    Ok(())
+}
+
+/// Allocates and frees heap blocks and checks that no allocation is leaked.
+fn check_heap() -> Result<(), &'static str> {
+    let before = crate::heap::stats().allocations;
+    {
+        let boxed = Box::new(0x5e1f_u64);
+        let vec: Vec<u64> = (0..256).collect();
+        if *boxed != 0x5e1f || vec.iter().sum::<u64>() != 255 * 256 / 2 {
+            return Err("heap memory was corrupted");
+        }
+    }
+    if crate::heap::stats().allocations != before {
+        return Err("allocations were not freed");
+    }
+    Ok(())
+}
+
+/// Raises a breakpoint exception and checks that the kernel continues after its handler.
+fn check_breakpoint() -> Result<(), &'static str> {
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+    extern "x86-interrupt" fn counting_handler(_stack_frame: InterruptStackFrame) {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let before = HITS.load(Ordering::SeqCst);
+    idt::with_handler(3, counting_handler, x86_64::instructions::interrupts::int3);
+    if HITS.load(Ordering::SeqCst) != before + 1 {
+        return Err("breakpoint handler did not run");
+    }
+    Ok(())
+}
+
+/// Reads the real-time clock and checks that the date and time are in range.
+fn check_rtc() -> Result<(), &'static str> {
+    let now = time::read_rtc();
+    let plausible = (1..=12).contains(&now.month)
+        && (1..=31).contains(&now.day)
+        && now.hour < 24
+        && now.minute < 60
+        && now.second < 60;
+    if !plausible {
+        return Err("date or time out of range");
+    }
+    Ok(())
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that the self-test is only requested by its own boot option.
+#[test_case]
+fn selftest_requested() {
+    use crate::boot::parse_cmdline;
+
+    assert!(requested(&parse_cmdline("selftest")));
+    assert!(requested(&parse_cmdline(
+        "fgcolor=white selftest bgcolor=blue"
+    )));
+    assert!(!requested(&parse_cmdline("")));
+    assert!(!requested(&parse_cmdline("selftests fgcolor=selftest")));
+    assert!(!requested(&parse_cmdline(r#"title="x selftest""#)));
+    assert!(!requested(&parse_cmdline("selftest=no")));
+}
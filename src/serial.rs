@@ -1,28 +1,117 @@
+use crate::interrupts;
+use core::fmt::{self, Write};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+/// I/O port base of the first serial port.
+const COM1: u16 = 0x3f8;
+/// Offset of the interrupt enable register from the port base.
+const INTERRUPT_ENABLE: u16 = 1;
+/// Offset of the modem control register from the port base.
+const MODEM_CONTROL: u16 = 4;
+/// Offset of the line status register from the port base.
+const LINE_STATUS: u16 = 5;
+/// Line status bit set when a received byte is ready.
+const DATA_READY: u8 = 0x01;
 
 lazy_static! {
     /// Static reference to first serial port
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial = unsafe { SerialPort::new(0x3f8) };
+        let mut serial = unsafe { SerialPort::new(COM1) };
+        serial.init();
+        // raise an interrupt when a byte is received, see `task::serial`
+        let mut interrupt_enable: Port<u8> = Port::new(COM1 + INTERRUPT_ENABLE);
+        unsafe { interrupt_enable.write(interrupt_enable.read() | 0x01) };
+        Mutex::new(serial)
+    };
+
+    /// Static reference to second serial port
+    pub static ref SERIAL2: Mutex<SerialPort> = {
+        let mut serial = unsafe { SerialPort::new(0x2f8) };
         serial.init();
         Mutex::new(serial)
     };
 }
 
+/// Prints a formatted string to the serial port `port`.
+fn write_port(port: &Mutex<SerialPort>, args: ::core::fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        port.lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed.");
+    });
+}
+
 /// Prints a formatted string to the first serial port using the global `SERIAL1`.
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
-    use core::fmt::Write;
-    use x86_64::instructions::interrupts;
+    write_port(&SERIAL1, args);
+}
+
+/// Writer for the first serial port that also writes if `SERIAL1` is locked, e.g. by the code
+/// that panicked. The port is then written directly, bypassing the lock.
+///
+/// Only meant for panics, as the output may interleave with that of the lock holder.
+pub struct ForcedWriter;
+
+impl fmt::Write for ForcedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        interrupts::without_interrupts(|| match SERIAL1.try_lock() {
+            Some(mut serial) => serial.write_str(s),
+            None => {
+                // the port has been initialized by whoever holds the lock
+                let mut serial = unsafe { SerialPort::new(COM1) };
+                serial.write_str(s)
+            }
+        })
+    }
+}
+
+/// Prints a formatted string to the first serial port, also if `SERIAL1` is locked, see
+/// `ForcedWriter`.
+pub fn print_forced(args: fmt::Arguments) {
+    let _ = ForcedWriter.write_fmt(args);
+}
+
+/// Prints a formatted string to the second serial port using the global `SERIAL2`.
+#[doc(hidden)]
+pub fn _print2(args: ::core::fmt::Arguments) {
+    write_port(&SERIAL2, args);
+}
+
+/// Returns the byte received by the serial port at `base`, if there is one.
+fn receive(base: u16) -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(base + LINE_STATUS);
+    let mut data: Port<u8> = Port::new(base);
+    unsafe {
+        if line_status.read() & DATA_READY != 0 {
+            Some(data.read())
+        } else {
+            None
+        }
+    }
+}
 
+/// Reads a byte from the first serial port without waiting. Returns None if no byte has been
+/// received.
+pub fn try_read_byte() -> Option<u8> {
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed.");
-    });
+        // the port must not be accessed while it is written
+        let _serial = SERIAL1.lock();
+        receive(COM1)
+    })
+}
+
+/// Reads a byte from the first serial port, spinning until one is received.
+pub fn read_byte() -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
 }
 
 /// This macro is used to print to the first serial port interface. Useful for testing purposes where the serial connection
@@ -39,3 +128,57 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+/// This macro is used to print to the second serial port interface, e.g. to keep debug logging
+/// apart from the test output on the first one.
+#[macro_export]
+macro_rules! serial2_print {
+    ($($arg:tt)*) => ($crate::serial::_print2(format_args!($($arg)*)));
+}
+
+/// This macro is used to print to the second serial port interface. Newlines are added after. Usage is analogous to serial2_print!().
+#[macro_export]
+macro_rules! serial2_println {
+    () => ($crate::serial2_print!("\n"));
+    ($fmt:expr) => ($crate::serial2_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial2_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+// -- UNIT TESTS -- //
+
+/// Test printing to both serial ports. With two `-serial` outputs configured in QEMU the
+/// streams show up separately on the host.
+#[test_case]
+fn serial_print_both_ports() {
+    crate::serial2_println!("testing serial2_println! macro");
+    crate::serial_print!("testing serial_print! macro... ");
+    crate::serial2_print!("testing serial2_print! macro\n");
+}
+
+/// Test reading back a byte sent with the first serial port in loopback mode.
+#[test_case]
+fn serial_loopback_read_byte() {
+    let mut modem_control: Port<u8> = Port::new(COM1 + MODEM_CONTROL);
+
+    let received = interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        let previous = unsafe { modem_control.read() };
+        // loopback mode: sent bytes are received instead of being transmitted
+        unsafe { modem_control.write(previous | 0x10) };
+
+        // drop input received before
+        while receive(COM1).is_some() {}
+        serial.send(0xae);
+        let mut received = None;
+        for _ in 0..100_000 {
+            received = receive(COM1);
+            if received.is_some() {
+                break;
+            }
+        }
+
+        unsafe { modem_control.write(previous) };
+        received
+    });
+    assert_eq!(received, Some(0xae));
+}
@@ -1,22 +1,30 @@
-use core::task::{Context, Poll, Waker};
+use core::{
+    sync::atomic::Ordering,
+    task::{Context, Poll, Waker},
+};
 
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
 use crossbeam_queue::ArrayQueue;
 
-use super::{Task, TaskId};
+use super::{timer, Task, TaskId, TaskInfo, TASK_COUNT};
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
     task_queue: Arc<ArrayQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    // number of tasks that finished on this executor
+    completed: usize,
 }
 
 impl Executor {
     pub fn new() -> Self {
+        // sleeping tasks are woken through the executor
+        timer::init_queue();
         Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
+            completed: 0,
         }
     }
 
@@ -28,9 +36,16 @@ impl Executor {
         self.task_queue
             .push(task_id)
             .expect("the task queue is full");
+        TASK_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the tasks that did not finish yet, ordered by id.
+    pub fn list_tasks(&self) -> Vec<TaskInfo> {
+        self.tasks.values().map(Task::info).collect()
     }
 
     fn run_ready(&mut self) {
+        timer::wake_expired();
         while let Ok(task_id) = self.task_queue.pop() {
             let task = match self.tasks.get_mut(&task_id) {
                 Some(task) => task,
@@ -46,6 +61,8 @@ impl Executor {
                     // task finished
                     self.tasks.remove(&task_id);
                     self.waker_cache.remove(&task_id);
+                    self.completed += 1;
+                    TASK_COUNT.fetch_sub(1, Ordering::Relaxed);
                 }
                 Poll::Pending => {}
             }
@@ -56,13 +73,26 @@ impl Executor {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
         interrupts::disable(); // disable interrupts to avoid race conditions
-        if self.task_queue.is_empty() {
+        if self.task_queue.is_empty() && !timer::has_expired() {
             enable_and_hlt();
         } else {
             interrupts::enable();
         }
     }
 
+    /// Runs the tasks until `n` of them have finished. Tasks that are still pending stay
+    /// spawned and continue on the next call.
+    pub fn run_n(&mut self, n: usize) {
+        let target = self.completed + n;
+        loop {
+            self.run_ready();
+            if self.completed >= target {
+                return;
+            }
+            self.sleep_on_idle();
+        }
+    }
+
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready();
@@ -77,6 +107,13 @@ impl Default for Executor {
     }
 }
 
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // the unfinished tasks are dropped with the executor
+        TASK_COUNT.fetch_sub(self.tasks.len(), Ordering::Relaxed);
+    }
+}
+
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<ArrayQueue<TaskId>>,
@@ -1,15 +1,41 @@
-use core::task::{Context, Poll};
+// Key combinations handled by the keyboard task (`print_keypresses`):
+//
+// | keys               | action                                   |
+// |--------------------|------------------------------------------|
+// | Ctrl + Alt + Del   | reboot, see `acpi::reboot`               |
+// | Ctrl + Alt + End   | power off, see `acpi::shutdown`          |
+// | Ctrl + Alt + L     | switch to the next keyboard layout       |
+// | Page Up/Page Down  | scroll the screen                        |
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
 use futures_util::{task::AtomicWaker, Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, Keyboard, ScancodeSet1};
+use pc_keyboard::{
+    layouts, DecodedKey, Error, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet1,
+};
+use spin::Mutex;
 
-use crate::{print, println};
+use crate::{acpi, boot, interrupts, print, println, ps2, time, warn};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+/// Allocates the scancode queue. Scancodes received before are dropped.
+///
+/// Called by `ScancodeStream::new`, but may be called earlier to read keys before the executor
+/// runs. Calling it again has no effect.
+pub fn init_queue() {
+    boot::require(boot::Stage::Heap);
+    // an already initialized queue is kept
+    let _ = SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(100));
+}
+
 /// Called by the keyboard interrupt handler.
 ///
 /// Must not block or allocate.
@@ -26,27 +52,463 @@ pub(crate) fn add_scancode(scancode: u8) {
     }
 }
 
+/// Pops the next raw scancode from the scancode queue without waiting.
+///
+/// Returns None when no scancode is queued or the queue is not initialized yet.
+pub fn try_next_scancode() -> Option<u8> {
+    SCANCODE_QUEUE.try_get().ok()?.pop().ok()
+}
+
+/// Decodes queued scancodes with `decoder` until a complete key has been read.
+///
+/// Returns None when the queue runs empty first. A partially received multi-byte sequence is
+/// kept in `decoder` and completed by a later call.
+pub fn try_next_key(decoder: &mut KeyDecoder) -> Option<DecodedKey> {
+    while let Some(scancode) = try_next_scancode() {
+        if let Some(key) = decoder.decode(scancode) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Waits up to `timeout` for a key press without the executor, e.g. during boot. Halts the CPU
+/// between keyboard interrupts.
+///
+/// Returns None if no key was pressed in time.
+pub fn wait_for_key(decoder: &mut KeyDecoder, timeout: Duration) -> Option<DecodedKey> {
+    // the timeout is measured in timer ticks
+    boot::require(boot::Stage::Interrupts);
+
+    let start = time::ticks();
+    loop {
+        if let Some(key) = try_next_key(decoder) {
+            return Some(key);
+        }
+        if time::ticks_to_duration(time::ticks() - start) >= timeout {
+            return None;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Returns whether ESC is pressed within `timeout`, see `wait_for_key`. Other keys are
+/// ignored.
+pub fn escape_pressed(timeout: Duration) -> bool {
+    let mut decoder = KeyDecoder::new();
+    let start = time::ticks();
+    while let Some(key) = wait_for_key(
+        &mut decoder,
+        timeout.saturating_sub(time::ticks_to_duration(time::ticks() - start)),
+    ) {
+        if KeyAction::from(key) == KeyAction::Escape {
+            return true;
+        }
+    }
+    false
+}
+
+/// What a decoded key asks a line editor to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    /// Insert a printable character.
+    Char(char),
+    Enter,
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    Tab,
+    Escape,
+    /// Any other key, e.g. function keys or unprintable control characters.
+    Other,
+}
+
+impl From<DecodedKey> for KeyAction {
+    fn from(key: DecodedKey) -> Self {
+        // the layout reports some control keys as unicode and others as raw keys
+        match key {
+            DecodedKey::Unicode('\n') | DecodedKey::RawKey(KeyCode::Enter) => KeyAction::Enter,
+            DecodedKey::Unicode('\x08') | DecodedKey::RawKey(KeyCode::Backspace) => {
+                KeyAction::Backspace
+            }
+            DecodedKey::Unicode('\t') | DecodedKey::RawKey(KeyCode::Tab) => KeyAction::Tab,
+            DecodedKey::Unicode('\x1b') | DecodedKey::RawKey(KeyCode::Escape) => KeyAction::Escape,
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => KeyAction::Left,
+            DecodedKey::RawKey(KeyCode::ArrowRight) => KeyAction::Right,
+            DecodedKey::RawKey(KeyCode::ArrowUp) => KeyAction::Up,
+            DecodedKey::RawKey(KeyCode::ArrowDown) => KeyAction::Down,
+            DecodedKey::Unicode(char) if !char.is_control() => KeyAction::Char(char),
+            _ => KeyAction::Other,
+        }
+    }
+}
+
+/// State of the modifier and lock keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    /// Returns the bitmask of the keyboard LEDs for the lock states, as sent with the set LEDs
+    /// command: scroll lock (bit 0), num lock (bit 1) and caps lock (bit 2).
+    pub fn led_mask(&self) -> u8 {
+        u8::from(self.scroll_lock) | u8::from(self.num_lock) << 1 | u8::from(self.caps_lock) << 2
+    }
+}
+
+/// Modifier state of the keyboard task, see `modifiers`.
+static MODIFIERS: Mutex<Modifiers> = Mutex::new(Modifiers {
+    shift: false,
+    ctrl: false,
+    alt: false,
+    caps_lock: false,
+    num_lock: false,
+    scroll_lock: false,
+});
+
+/// Returns the modifier and lock state as tracked by the keyboard task.
+pub fn modifiers() -> Modifiers {
+    *MODIFIERS.lock()
+}
+
+/// Switches the keyboard LEDs to the lock states of `modifiers`.
+///
+/// The keyboard interrupt is masked meanwhile, so that the keyboard's acknowledgements are not
+/// taken for scancodes.
+pub fn set_leds(modifiers: Modifiers) -> Result<(), ps2::DeviceError> {
+    interrupts::mask_irq(1);
+    let result = ps2::write_keyboard(0xed).and_then(|()| ps2::write_keyboard(modifiers.led_mask()));
+    interrupts::unmask_irq(1);
+    result
+}
+
+/// Keyboard layouts the decoder can switch between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// US layout with 104 keys.
+    Us104,
+    /// German layout with 105 keys.
+    De105,
+}
+
+impl Layout {
+    /// Returns the layout following this one, cycling through all layouts.
+    pub fn next(self) -> Layout {
+        match self {
+            Layout::Us104 => Layout::De105,
+            Layout::De105 => Layout::Us104,
+        }
+    }
+}
+
+/// The active layout, used by new decoders and the keyboard task.
+static LAYOUT: Mutex<Layout> = Mutex::new(Layout::Us104);
+
+/// Returns the active keyboard layout.
+pub fn layout() -> Layout {
+    *LAYOUT.lock()
+}
+
+/// Switches the active keyboard layout. The keyboard task picks it up with the next scancode.
+pub fn set_layout(layout: Layout) {
+    *LAYOUT.lock() = layout;
+}
+
+/// A `pc_keyboard` decoder for one of the layouts. `Keyboard` is generic over the layout, so
+/// each layout needs its own variant.
+enum LayoutKeyboard {
+    Us104(Keyboard<layouts::Us104Key, ScancodeSet1>),
+    De105(Keyboard<layouts::De105Key, ScancodeSet1>),
+}
+
+impl LayoutKeyboard {
+    fn new(layout: Layout) -> Self {
+        match layout {
+            Layout::Us104 => LayoutKeyboard::Us104(Keyboard::new(
+                layouts::Us104Key,
+                ScancodeSet1,
+                HandleControl::Ignore,
+            )),
+            Layout::De105 => LayoutKeyboard::De105(Keyboard::new(
+                layouts::De105Key,
+                ScancodeSet1,
+                HandleControl::Ignore,
+            )),
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        match self {
+            LayoutKeyboard::Us104(keyboard) => keyboard.add_byte(byte),
+            LayoutKeyboard::De105(keyboard) => keyboard.add_byte(byte),
+        }
+    }
+
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        match self {
+            LayoutKeyboard::Us104(keyboard) => keyboard.process_keyevent(event),
+            LayoutKeyboard::De105(keyboard) => keyboard.process_keyevent(event),
+        }
+    }
+}
+
+/// Decoder turning scancode set 1 bytes into keys one byte at a time.
+///
+/// Some keys are sent as multi-byte sequences, so the decoder has to keep state between bytes:
+/// - `0xE0` prefixes the extended keys (arrow keys, right ctrl/alt, home, end, ...). The prefix
+///   is remembered by the `pc_keyboard` decoder until the next byte completes the key.
+/// - `0xE1` starts the pause key sequence (`E1 1D 45` on press, `E1 9D C5` on release).
+///   `ScancodeSet1` does not know this prefix, so the two bytes following it are dropped here
+///   instead of being decoded as left ctrl and num lock.
+pub struct KeyDecoder {
+    keyboard: LayoutKeyboard,
+    layout: Layout,
+    // number of bytes of a `0xE1` sequence that still have to be dropped
+    e1_remaining: u8,
+    modifiers: Modifiers,
+    // lock keys that are held down, so that typematic repeats do not toggle them again
+    held_locks: Modifiers,
+    // set when a lock state changed since the last `take_lock_change`
+    lock_changed: bool,
+}
+
+impl KeyDecoder {
+    /// Creates a decoder for the active layout, see `layout`.
+    pub fn new() -> Self {
+        KeyDecoder::with_layout(layout())
+    }
+
+    /// Creates a decoder for `layout`.
+    pub fn with_layout(layout: Layout) -> Self {
+        KeyDecoder {
+            keyboard: LayoutKeyboard::new(layout),
+            layout,
+            e1_remaining: 0,
+            modifiers: Modifiers::default(),
+            held_locks: Modifiers::default(),
+            lock_changed: false,
+        }
+    }
+
+    /// Returns the layout the decoder uses.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Switches the decoder to `layout`. A partially received key is dropped if the layout
+    /// changes.
+    pub fn set_layout(&mut self, layout: Layout) {
+        if layout != self.layout {
+            self.keyboard = LayoutKeyboard::new(layout);
+            self.layout = layout;
+        }
+    }
+
+    /// Returns the modifier and lock state after the keys decoded so far.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Returns whether a lock key toggled since the last call.
+    pub fn take_lock_change(&mut self) -> bool {
+        core::mem::replace(&mut self.lock_changed, false)
+    }
+
+    /// Updates the modifier state with a key press or release.
+    fn track_modifiers(&mut self, event: &KeyEvent) {
+        let down = match event.state {
+            KeyState::Down => true,
+            KeyState::Up => false,
+        };
+        let (modifiers, held_locks) = (&mut self.modifiers, &mut self.held_locks);
+        let (lock, held) = match event.code {
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => {
+                modifiers.shift = down;
+                return;
+            }
+            KeyCode::ControlLeft | KeyCode::ControlRight => {
+                modifiers.ctrl = down;
+                return;
+            }
+            KeyCode::AltLeft | KeyCode::AltRight => {
+                modifiers.alt = down;
+                return;
+            }
+            KeyCode::CapsLock => (&mut modifiers.caps_lock, &mut held_locks.caps_lock),
+            KeyCode::NumpadLock => (&mut modifiers.num_lock, &mut held_locks.num_lock),
+            KeyCode::ScrollLock => (&mut modifiers.scroll_lock, &mut held_locks.scroll_lock),
+            _ => return,
+        };
+        // a lock toggles when pressed, not on the repeats while it is held
+        if down && !*held {
+            *lock = !*lock;
+            self.lock_changed = true;
+        }
+        *held = down;
+    }
+
+    /// Feeds a single scancode byte to the decoder.
+    ///
+    /// Returns the decoded key once a key press completes. Prefix bytes, key releases and
+    /// unknown scancodes return None.
+    pub fn decode(&mut self, scancode: u8) -> Option<DecodedKey> {
+        if self.e1_remaining > 0 {
+            self.e1_remaining -= 1;
+            return None;
+        }
+        if scancode == 0xE1 {
+            self.e1_remaining = 2;
+            return None;
+        }
+
+        match self.keyboard.add_byte(scancode) {
+            Ok(Some(key_event)) => {
+                self.track_modifiers(&key_event);
+                self.keyboard.process_keyevent(key_event)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for KeyDecoder {
+    fn default() -> Self {
+        KeyDecoder::new()
+    }
+}
+
+/// An action bound to a key combination, see the table at the top of the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortcut {
+    Reboot,
+    Shutdown,
+    CycleLayout,
+}
+
+/// Returns the shortcut bound to `key` pressed with `modifiers`, if any.
+pub fn shortcut(key: DecodedKey, modifiers: Modifiers) -> Option<Shortcut> {
+    if !(modifiers.ctrl && modifiers.alt) {
+        return None;
+    }
+    // the layouts report delete as a character
+    match key {
+        DecodedKey::Unicode('\x7f') | DecodedKey::RawKey(KeyCode::Delete) => Some(Shortcut::Reboot),
+        DecodedKey::RawKey(KeyCode::End) => Some(Shortcut::Shutdown),
+        DecodedKey::Unicode('l' | 'L') => Some(Shortcut::CycleLayout),
+        _ => None,
+    }
+}
+
+/// The functions called by the power shortcuts.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerActions {
+    pub reboot: fn(),
+    pub shutdown: fn(),
+}
+
+impl PowerActions {
+    /// Reboots and powers off through ACPI.
+    pub const ACPI: PowerActions = PowerActions {
+        reboot: acpi_reboot,
+        shutdown: acpi_shutdown,
+    };
+}
+
+fn acpi_reboot() {
+    acpi::reboot();
+}
+
+fn acpi_shutdown() {
+    acpi::shutdown();
+}
+
+/// The power actions of `run_shortcut`, replaceable so that tests can check they are invoked.
+static POWER_ACTIONS: Mutex<PowerActions> = Mutex::new(PowerActions::ACPI);
+
+/// Replaces the functions called by the power shortcuts. Meant for tests, the default are
+/// `PowerActions::ACPI`.
+pub fn set_power_actions(actions: PowerActions) {
+    *POWER_ACTIONS.lock() = actions;
+}
+
+/// Runs the action of `shortcut`.
+pub fn run_shortcut(shortcut: Shortcut) {
+    match shortcut {
+        Shortcut::Reboot => {
+            println!("\nrebooting...");
+            let reboot = POWER_ACTIONS.lock().reboot;
+            reboot();
+        }
+        Shortcut::Shutdown => {
+            println!("\npowering off...");
+            let shutdown = POWER_ACTIONS.lock().shutdown;
+            shutdown();
+        }
+        Shortcut::CycleLayout => {
+            let next = layout().next();
+            set_layout(next);
+            println!("\nkeyboard layout: {:?}", next);
+        }
+    }
+}
+
+/// Scrolls the screen half a page up or down.
+fn scroll(up: bool) {
+    use crate::vga_buffer::WRITER;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        if up {
+            writer.scroll_up(12);
+        } else {
+            writer.scroll_down(12);
+        }
+        writer.flush();
+    });
+}
+
 pub async fn print_keypresses() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        layouts::Us104Key,
-        ScancodeSet1,
-        pc_keyboard::HandleControl::Ignore,
-    );
+    let mut decoder = KeyDecoder::new();
 
     // ScancodeStream::poll_next() never returns None so this will be an endless loop
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(char) => print!("{}", char),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
+        decoder.set_layout(layout());
+        let key = decoder.decode(scancode);
+        *MODIFIERS.lock() = decoder.modifiers();
+        if decoder.take_lock_change() {
+            if let Err(err) = set_leds(decoder.modifiers()) {
+                warn!("setting the keyboard LEDs failed: {:?}", err);
+            }
+        }
+
+        if let Some(key) = key {
+            // the shortcuts run here rather than in the interrupt handler, so they may lock
+            if let Some(shortcut) = shortcut(key, decoder.modifiers()) {
+                run_shortcut(shortcut);
+                continue;
+            }
+            match key {
+                DecodedKey::Unicode(char) => print!("{}", char),
+                DecodedKey::RawKey(KeyCode::PageUp) => scroll(true),
+                DecodedKey::RawKey(KeyCode::PageDown) => scroll(false),
+                DecodedKey::RawKey(key) => print!("{:?}", key),
             }
         }
     }
 }
 
+/// Set once a ScancodeStream has been created.
+static STREAM_CREATED: AtomicBool = AtomicBool::new(false);
+
 pub struct ScancodeStream {
     /// prevents the contruction of the struct outside of the module.
     /// ScancodeStream::new() has to be used!
@@ -55,9 +517,11 @@ pub struct ScancodeStream {
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new() should only be called once");
+        assert!(
+            !STREAM_CREATED.swap(true, Ordering::Relaxed),
+            "ScancodeStream::new() should only be called once"
+        );
+        init_queue();
         ScancodeStream { _private: () }
     }
 }
@@ -91,3 +555,170 @@ impl Stream for ScancodeStream {
         }
     }
 }
+
+// -- UNIT TESTS -- //
+
+/// Test that an `0xE0` prefixed sequence is decoded as a single extended key.
+#[test_case]
+fn key_decoder_extended_scancode() {
+    let mut decoder = KeyDecoder::new();
+    // up arrow: 0xE0 0x48 on press
+    assert_eq!(decoder.decode(0xE0), None);
+    assert_eq!(
+        decoder.decode(0x48),
+        Some(DecodedKey::RawKey(KeyCode::ArrowUp))
+    );
+    // release: 0xE0 0xC8
+    assert_eq!(decoder.decode(0xE0), None);
+    assert_eq!(decoder.decode(0xC8), None);
+}
+
+/// Test the mapping of decoded keys to line editor actions.
+#[test_case]
+fn key_action_from_decoded_key() {
+    let cases = [
+        (DecodedKey::Unicode('a'), KeyAction::Char('a')),
+        (DecodedKey::Unicode('\n'), KeyAction::Enter),
+        (DecodedKey::RawKey(KeyCode::Enter), KeyAction::Enter),
+        (DecodedKey::Unicode('\x08'), KeyAction::Backspace),
+        (DecodedKey::Unicode('\t'), KeyAction::Tab),
+        (DecodedKey::Unicode('\x1b'), KeyAction::Escape),
+        (DecodedKey::RawKey(KeyCode::ArrowLeft), KeyAction::Left),
+        (DecodedKey::RawKey(KeyCode::ArrowRight), KeyAction::Right),
+        (DecodedKey::RawKey(KeyCode::ArrowUp), KeyAction::Up),
+        (DecodedKey::RawKey(KeyCode::ArrowDown), KeyAction::Down),
+        (DecodedKey::RawKey(KeyCode::F1), KeyAction::Other),
+        (DecodedKey::Unicode('\x7f'), KeyAction::Other),
+    ];
+    for (key, action) in cases {
+        assert_eq!(KeyAction::from(key), action);
+    }
+
+    // decoded from scancodes: up arrow and backspace
+    let mut decoder = KeyDecoder::new();
+    assert_eq!(decoder.decode(0xE0), None);
+    assert_eq!(
+        decoder.decode(0x48).map(KeyAction::from),
+        Some(KeyAction::Up)
+    );
+    assert_eq!(
+        decoder.decode(0x0E).map(KeyAction::from),
+        Some(KeyAction::Backspace)
+    );
+}
+
+/// Test the LED bitmask of the lock states.
+#[test_case]
+fn modifiers_led_mask() {
+    let locks = |caps_lock, num_lock, scroll_lock| Modifiers {
+        caps_lock,
+        num_lock,
+        scroll_lock,
+        ..Modifiers::default()
+    };
+    assert_eq!(locks(false, false, false).led_mask(), 0b000);
+    assert_eq!(locks(false, false, true).led_mask(), 0b001);
+    assert_eq!(locks(false, true, false).led_mask(), 0b010);
+    assert_eq!(locks(true, false, false).led_mask(), 0b100);
+    assert_eq!(locks(true, true, true).led_mask(), 0b111);
+    // other modifiers have no LED
+    let shifted = Modifiers {
+        shift: true,
+        ctrl: true,
+        alt: true,
+        ..Modifiers::default()
+    };
+    assert_eq!(shifted.led_mask(), 0);
+}
+
+/// Test that lock keys toggle once per press and modifiers follow press and release.
+#[test_case]
+fn key_decoder_tracks_modifiers() {
+    let mut decoder = KeyDecoder::new();
+    // caps lock press, typematic repeat and release
+    for scancode in [0x3A, 0x3A, 0xBA] {
+        decoder.decode(scancode);
+    }
+    assert!(decoder.modifiers().caps_lock);
+    assert!(decoder.take_lock_change());
+    assert!(!decoder.take_lock_change());
+
+    // the second press switches it off again
+    decoder.decode(0x3A);
+    decoder.decode(0xBA);
+    assert!(!decoder.modifiers().caps_lock);
+    assert!(decoder.take_lock_change());
+
+    // left shift is only set while held
+    decoder.decode(0x2A);
+    assert!(decoder.modifiers().shift);
+    decoder.decode(0xAA);
+    assert!(!decoder.modifiers().shift);
+    assert!(!decoder.take_lock_change());
+}
+
+/// Test that the same scancode decodes to the character of the decoder's layout.
+#[test_case]
+fn key_decoder_layouts() {
+    // the key right of T is Y on US and Z on German keyboards
+    let mut decoder = KeyDecoder::with_layout(Layout::Us104);
+    assert_eq!(decoder.decode(0x15), Some(DecodedKey::Unicode('y')));
+    assert_eq!(decoder.decode(0x95), None);
+
+    decoder.set_layout(Layout::De105);
+    assert_eq!(decoder.layout(), Layout::De105);
+    assert_eq!(decoder.decode(0x15), Some(DecodedKey::Unicode('z')));
+    assert_eq!(decoder.decode(0x95), None);
+
+    let de = KeyDecoder::with_layout(Layout::De105);
+    assert_eq!(de.layout().next(), Layout::Us104);
+    assert_eq!(Layout::Us104.next(), Layout::De105);
+}
+
+/// Test that ctrl + alt + end and ctrl + alt + del invoke the power actions.
+#[test_case]
+fn keyboard_power_shortcuts() {
+    static REBOOTS: AtomicBool = AtomicBool::new(false);
+    static SHUTDOWNS: AtomicBool = AtomicBool::new(false);
+    set_power_actions(PowerActions {
+        reboot: || REBOOTS.store(true, Ordering::SeqCst),
+        shutdown: || SHUTDOWNS.store(true, Ordering::SeqCst),
+    });
+
+    let mut decoder = KeyDecoder::with_layout(Layout::Us104);
+    let mut press = |scancodes: &[u8]| {
+        let mut pressed = None;
+        for &scancode in scancodes {
+            if let Some(key) = decoder.decode(scancode) {
+                pressed = shortcut(key, decoder.modifiers());
+            }
+        }
+        pressed
+    };
+    // end without modifiers is no shortcut
+    assert_eq!(press(&[0xE0, 0x4F, 0xE0, 0xCF]), None);
+
+    // hold left ctrl and left alt, press end
+    assert_eq!(press(&[0x1D, 0x38, 0xE0, 0x4F]), Some(Shortcut::Shutdown));
+    run_shortcut(Shortcut::Shutdown);
+    assert!(SHUTDOWNS.load(Ordering::SeqCst));
+    assert!(!REBOOTS.load(Ordering::SeqCst));
+
+    // release end, press delete
+    assert_eq!(press(&[0xE0, 0xCF, 0xE0, 0x53]), Some(Shortcut::Reboot));
+    run_shortcut(Shortcut::Reboot);
+    assert!(REBOOTS.load(Ordering::SeqCst));
+
+    set_power_actions(PowerActions::ACPI);
+}
+
+/// Test that the pause key sequence is dropped without decoding ctrl or num lock.
+#[test_case]
+fn key_decoder_pause_sequence() {
+    let mut decoder = KeyDecoder::new();
+    for scancode in [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5] {
+        assert_eq!(decoder.decode(scancode), None);
+    }
+    // the decoder is back in its initial state: 0x1E is 'a'
+    assert_eq!(decoder.decode(0x1E), Some(DecodedKey::Unicode('a')));
+}
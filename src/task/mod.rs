@@ -1,11 +1,15 @@
 pub mod executor;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial;
 pub mod simple_executor;
+pub mod stats;
+pub mod timer;
 
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 
@@ -16,22 +20,54 @@ use alloc::boxed::Box;
 /// are merely executed for their side-effects
 pub struct Task {
     id: TaskId,
+    name: &'static str,
+    // number of times the task has been polled
+    polls: u64,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        Task::with_name("unnamed", future)
+    }
+
+    /// Creates a task with a `name` that is shown in task listings.
+    pub fn with_name(name: &'static str, future: impl Future<Output = ()> + 'static) -> Self {
         Task {
             id: TaskId::new(),
+            name,
+            polls: 0,
             future: Box::pin(future),
         }
     }
 
+    /// Returns a snapshot of the id, name and activity of the task.
+    pub fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id.0,
+            name: self.name,
+            polls: self.polls,
+        }
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        self.polls += 1;
+        CURRENT_TASK.store(self.id.0, Ordering::Relaxed);
+        let result = self.future.as_mut().poll(context);
+        CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+        result
     }
 }
 
+/// Description of a spawned task, see `Executor::list_tasks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: &'static str,
+    /// Number of times the task has been polled, a rough measure of its activity.
+    pub polls: u64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -41,3 +77,25 @@ impl TaskId {
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
+
+/// Marker for "no task is running" in `CURRENT_TASK`.
+const NO_TASK: u64 = u64::MAX;
+
+/// Id of the task that is currently polled by an executor.
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+
+/// Returns the id of the task that is currently running, if any.
+pub fn current_task_id() -> Option<u64> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(id),
+    }
+}
+
+/// Number of tasks spawned on an `Executor` that did not finish yet.
+static TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of tasks spawned on an executor that did not finish yet.
+pub fn task_count() -> usize {
+    TASK_COUNT.load(Ordering::Relaxed)
+}
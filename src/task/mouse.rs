@@ -0,0 +1,241 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::{task::AtomicWaker, Stream, StreamExt};
+
+use crate::{
+    idt::{InterruptIndex, PIC_1_OFFSET},
+    interrupts, println, ps2, warn,
+};
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Number of bytes the interrupt handler dropped since the stream last reported them.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Called by the mouse interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if queue.push(byte).is_err() {
+            // logging takes locks, the stream reports the drop from task context
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        } else {
+            // a new byte has been pushed, therefore notify the executor
+            WAKER.wake();
+        }
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Logs the bytes `add_byte` dropped because the queue was full or not yet initialized.
+fn report_dropped() {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(
+            "mouse queue full or uninitialized; dropped {} bytes",
+            dropped
+        );
+    }
+}
+
+/// Movement and button state reported by the mouse.
+///
+/// The deltas are in mouse counts. Positive `dy` is a movement away from the user, i.e. up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Decoder assembling the 3 byte packets of a standard PS/2 mouse.
+///
+/// The first byte holds the buttons (bits 0-2), a bit that is always set (bit 3), the sign bits
+/// of the deltas (bits 4 and 5) and their overflow bits (bits 6 and 7). The second and third
+/// byte are the low 8 bits of the x and y delta.
+pub struct MouseDecoder {
+    packet: [u8; 3],
+    // number of bytes of the current packet received so far
+    received: usize,
+}
+
+impl MouseDecoder {
+    pub const fn new() -> Self {
+        MouseDecoder {
+            packet: [0; 3],
+            received: 0,
+        }
+    }
+
+    /// Feeds a single byte to the decoder. Returns the event once a packet is complete.
+    ///
+    /// A first byte without bit 3 set cannot start a packet, it is dropped to find the start of
+    /// the next packet after a lost byte.
+    pub fn decode(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.received == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.packet[self.received] = byte;
+        self.received += 1;
+        if self.received < 3 {
+            return None;
+        }
+        self.received = 0;
+
+        let [flags, x, y] = self.packet;
+        // 9 bit two's complement deltas, saturated when the counter overflowed
+        let delta = |low: u8, sign: bool, overflow: bool| match (overflow, sign) {
+            (true, true) => -256,
+            (true, false) => 255,
+            (false, true) => i16::from(low) - 256,
+            (false, false) => i16::from(low),
+        };
+        Some(MouseEvent {
+            dx: delta(x, flags & 0x10 != 0, flags & 0x40 != 0),
+            dy: delta(y, flags & 0x20 != 0, flags & 0x80 != 0),
+            left: flags & 0x01 != 0,
+            right: flags & 0x02 != 0,
+            middle: flags & 0x04 != 0,
+        })
+    }
+}
+
+impl Default for MouseDecoder {
+    fn default() -> Self {
+        MouseDecoder::new()
+    }
+}
+
+/// Prints every mouse event to the screen.
+pub async fn print_events() {
+    let mut events = match MouseStream::new() {
+        Ok(events) => events,
+        Err(err) => {
+            warn!("enabling the PS/2 mouse failed: {:?}", err);
+            return;
+        }
+    };
+
+    // MouseStream::poll_next() never returns None so this will be an endless loop
+    while let Some(event) = events.next().await {
+        println!("mouse: {:?}", event);
+    }
+}
+
+pub struct MouseStream {
+    decoder: MouseDecoder,
+}
+
+impl MouseStream {
+    /// Enables the mouse and its interrupt.
+    ///
+    /// Panics if called more than once.
+    pub fn new() -> Result<Self, ps2::DeviceError> {
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(100))
+            .expect("MouseStream::new() should only be called once");
+        ps2::enable_mouse()?;
+        // bytes can be queued now, the slave PIC is connected to line 2 of the master
+        interrupts::unmask_irq(2);
+        interrupts::unmask_irq(InterruptIndex::Mouse as u8 - PIC_1_OFFSET);
+        Ok(MouseStream {
+            decoder: MouseDecoder::new(),
+        })
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Self::Item>> {
+        let queue = BYTE_QUEUE
+            .try_get()
+            .expect("ERROR: mouse queue still uninitialized when polling mouse stream");
+        report_dropped();
+
+        // skip overhead on success
+        while let Ok(byte) = queue.pop() {
+            if let Some(event) = self.decoder.decode(byte) {
+                return Poll::Ready(Some(event));
+            }
+        }
+
+        WAKER.register(cx.waker());
+        while let Ok(byte) = queue.pop() {
+            if let Some(event) = self.decoder.decode(byte) {
+                WAKER.take();
+                return Poll::Ready(Some(event));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test decoding a canned packet with negative and overflowing deltas.
+#[test_case]
+fn mouse_decoder_packet() {
+    let mut decoder = MouseDecoder::new();
+    // left and middle button, x = +5, y = -3
+    assert_eq!(decoder.decode(0b0010_1101), None);
+    assert_eq!(decoder.decode(5), None);
+    assert_eq!(
+        decoder.decode(0xfd),
+        Some(MouseEvent {
+            dx: 5,
+            dy: -3,
+            left: true,
+            right: false,
+            middle: true,
+        })
+    );
+
+    // right button, x overflowed in negative direction
+    assert_eq!(decoder.decode(0b0101_1010), None);
+    assert_eq!(decoder.decode(0x00), None);
+    assert_eq!(
+        decoder.decode(0x10),
+        Some(MouseEvent {
+            dx: -256,
+            dy: 16,
+            left: false,
+            right: true,
+            middle: false,
+        })
+    );
+}
+
+/// Test that the decoder skips bytes that cannot start a packet.
+#[test_case]
+fn mouse_decoder_resync() {
+    let mut decoder = MouseDecoder::new();
+    // a lost first byte leaves the deltas of the packet, which have bit 3 cleared
+    assert_eq!(decoder.decode(0x01), None);
+    assert_eq!(decoder.decode(0x02), None);
+    // the next packet is decoded
+    assert_eq!(decoder.decode(0x08), None);
+    assert_eq!(decoder.decode(1), None);
+    assert_eq!(
+        decoder.decode(2),
+        Some(MouseEvent {
+            dx: 1,
+            dy: 2,
+            left: false,
+            right: false,
+            middle: false,
+        })
+    );
+}
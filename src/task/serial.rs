@@ -0,0 +1,132 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use alloc::string::String;
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::{task::AtomicWaker, Stream, StreamExt};
+
+use crate::{
+    idt::{InterruptIndex, PIC_1_OFFSET},
+    interrupts, println, serial_print, warn,
+};
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Number of bytes the interrupt handler dropped since the stream last reported them.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Called by the COM1 interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if queue.push(byte).is_err() {
+            // logging takes locks, the stream reports the drop from task context
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        } else {
+            // a new byte has been pushed, therefore notify the executor
+            WAKER.wake();
+        }
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Logs the bytes `add_byte` dropped because the queue was full or not yet initialized.
+fn report_dropped() {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(
+            "serial input queue full or uninitialized; dropped {} bytes",
+            dropped
+        );
+    }
+}
+
+/// Reads bytes from `bytes` until the end of a line (CR or LF) and returns the line without
+/// the line ending. Received characters are echoed back to COM1. Non-ASCII bytes are dropped.
+pub async fn read_line(bytes: &mut SerialStream) -> String {
+    let mut line = String::new();
+
+    // SerialStream::poll_next() never returns None
+    while let Some(byte) = bytes.next().await {
+        match byte {
+            b'\r' | b'\n' => {
+                serial_print!("\n");
+                break;
+            }
+            // backspace and delete
+            0x08 | 0x7f => {
+                if line.pop().is_some() {
+                    serial_print!("\x08 \x08");
+                }
+            }
+            0x20..=0x7e => {
+                line.push(char::from(byte));
+                serial_print!("{}", char::from(byte));
+            }
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Prints every line received over COM1 to the screen.
+pub async fn print_lines() {
+    let mut bytes = SerialStream::new();
+    loop {
+        let line = read_line(&mut bytes).await;
+        println!("serial: {}", line);
+    }
+}
+
+pub struct SerialStream {
+    /// prevents the contruction of the struct outside of the module.
+    /// SerialStream::new() has to be used!
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(100))
+            .expect("SerialStream::new() should only be called once");
+        // bytes can be queued now
+        interrupts::unmask_irq(InterruptIndex::Serial as u8 - PIC_1_OFFSET);
+        SerialStream { _private: () }
+    }
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        SerialStream::new()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: core::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let queue = BYTE_QUEUE
+            .try_get()
+            .expect("ERROR: serial input queue still uninitialized when polling serial stream");
+        report_dropped();
+
+        // skip overhead on success
+        if let Ok(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Ok(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
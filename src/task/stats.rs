@@ -0,0 +1,24 @@
+use core::time::Duration;
+
+use super::{task_count, timer::sleep};
+use crate::{counters, heap, serial_println, time};
+
+/// Prints the uptime, the heap usage and the number of tasks to the serial port every
+/// `interval`. Each report increments the counter "stats_report".
+pub async fn stats_reporter(interval: Duration) {
+    loop {
+        sleep(interval).await;
+
+        let uptime = time::uptime();
+        let free = heap::stats().free_bytes;
+        serial_println!(
+            "[stats] uptime {}.{:03}s, heap {} bytes used, {} bytes free, {} tasks",
+            uptime.as_secs(),
+            uptime.subsec_millis(),
+            heap::size().saturating_sub(free),
+            free,
+            task_count()
+        );
+        counters::inc("stats_report");
+    }
+}
@@ -0,0 +1,173 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::{ArrayQueue, PushError};
+use spin::Mutex;
+
+use crate::{boot, interrupts, time};
+
+/// Maximum number of sleeping futures that are woken by the timer interrupt. Further sleeps
+/// are polled on every round of the executor instead.
+const MAX_SLEEPERS: usize = 32;
+
+/// A sleeping future waiting for the timer interrupt.
+struct Sleeper {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+/// The registered sleeps ordered by deadline, earliest first. Free slots are at the end.
+static SLEEPERS: Mutex<[Option<Sleeper>; MAX_SLEEPERS]> =
+    Mutex::new([const { None }; MAX_SLEEPERS]);
+
+/// Wakers of the sleeps that are due, moved here by the timer interrupt and woken by the
+/// executor. Waking or dropping a waker may allocate or free memory, which the interrupt
+/// handler must not do.
+static EXPIRED: OnceCell<ArrayQueue<Waker>> = OnceCell::uninit();
+
+/// Allocates the queue of expired sleeps. Called by `Executor::new`, calling it again has no
+/// effect.
+pub fn init_queue() {
+    boot::require(boot::Stage::Heap);
+    // an already initialized queue is kept
+    let _ = EXPIRED.try_init_once(|| ArrayQueue::new(MAX_SLEEPERS));
+}
+
+/// Called by the timer interrupt handler on every tick. Moves the wakers of all sleeps that
+/// are due to the expired queue.
+///
+/// Must not block or allocate.
+pub(crate) fn wake_due() {
+    let now = time::ticks();
+    let expired = match EXPIRED.try_get() {
+        Ok(expired) => expired,
+        Err(_) => return,
+    };
+    // a sleep registering itself right now is woken on the next tick
+    if let Some(mut sleepers) = SLEEPERS.try_lock() {
+        // the sleeps are ordered by deadline, so the due ones are at the front
+        let mut due = 0;
+        while due < MAX_SLEEPERS {
+            match sleepers[due].take() {
+                Some(sleeper) if sleeper.deadline <= now => {
+                    if let Err(PushError(waker)) = expired.push(sleeper.waker) {
+                        // the queue is full, retry on the next tick
+                        sleepers[due] = Some(Sleeper { waker, ..sleeper });
+                        break;
+                    }
+                    due += 1;
+                }
+                sleeper => {
+                    sleepers[due] = sleeper;
+                    break;
+                }
+            }
+        }
+        sleepers.rotate_left(due);
+    }
+}
+
+/// Wakes the sleeps the timer interrupt found due. Called by the executor before it runs the
+/// ready tasks.
+pub fn wake_expired() {
+    if let Ok(expired) = EXPIRED.try_get() {
+        while let Ok(waker) = expired.pop() {
+            waker.wake();
+        }
+    }
+}
+
+/// Returns whether sleeps are waiting to be woken by `wake_expired`.
+pub fn has_expired() -> bool {
+    EXPIRED
+        .try_get()
+        .map_or(false, |expired| !expired.is_empty())
+}
+
+/// A future that completes once a number of timer ticks have passed, see `sleep`.
+pub struct Sleep {
+    id: u64,
+    deadline: u64,
+}
+
+/// Returns a future that completes after `duration`, rounded up to whole timer ticks.
+pub fn sleep(duration: Duration) -> Sleep {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    Sleep {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        deadline: time::ticks() + time::duration_to_ticks(duration),
+    }
+}
+
+/// Removes the sleep `id` from `sleepers`, keeping the order and the free slots at the end.
+fn remove(sleepers: &mut [Option<Sleeper>; MAX_SLEEPERS], id: u64) {
+    if let Some(i) = sleepers
+        .iter()
+        .position(|slot| matches!(slot, Some(sleeper) if sleeper.id == id))
+    {
+        sleepers[i] = None;
+        sleepers[i..].rotate_left(1);
+    }
+}
+
+impl Sleep {
+    /// Registers `waker` to be woken at the deadline. Returns false if all slots are in use.
+    fn register(&self, waker: &Waker) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut sleepers = SLEEPERS.lock();
+            remove(&mut sleepers, self.id);
+            if sleepers[MAX_SLEEPERS - 1].is_some() {
+                return false;
+            }
+
+            // insert behind the sleeps with the same or an earlier deadline
+            let i = sleepers
+                .iter()
+                .position(
+                    |slot| !matches!(slot, Some(sleeper) if sleeper.deadline <= self.deadline),
+                )
+                .unwrap_or(MAX_SLEEPERS - 1);
+            sleepers[i..].rotate_right(1);
+            sleepers[i] = Some(Sleeper {
+                id: self.id,
+                deadline: self.deadline,
+                waker: waker.clone(),
+            });
+            true
+        })
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if time::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        if !self.register(cx.waker()) {
+            // no slot left, poll again on the next round of the executor
+            cx.waker().wake_by_ref();
+        }
+        // the deadline may have passed while registering
+        if time::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // remove the waker, so that it is not woken for a finished sleep
+        interrupts::without_interrupts(|| remove(&mut SLEEPERS.lock(), self.id));
+    }
+}
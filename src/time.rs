@@ -0,0 +1,411 @@
+use crate::{boot, interrupts, pit};
+use core::{
+    arch::x86_64::{_mm_lfence, _rdtsc},
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// Number of timer interrupts since the PIC was enabled.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the timer interrupt handler on every tick.
+///
+/// Must not block or allocate.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of timer ticks since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Converts a number of timer ticks to the time they span.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = u128::from(ticks) * u128::from(pit::divisor()) * 1_000_000_000
+        / u128::from(pit::BASE_FREQUENCY);
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Converts a duration to the number of timer ticks it spans, rounded up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let cycles = duration.as_nanos() * u128::from(pit::BASE_FREQUENCY);
+    let ticks = cycles.div_ceil(u128::from(pit::divisor()) * 1_000_000_000);
+    ticks.try_into().unwrap_or(u64::MAX)
+}
+
+/// Returns the time since boot as measured by the timer interrupt.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Waits for at least `duration`, rounded up to whole timer ticks, by spinning on the tick
+/// counter. Prefer `task::timer::sleep` in tasks, which lets other tasks run meanwhile.
+///
+/// Never returns if interrupts are disabled.
+pub fn sleep_busy(duration: Duration) {
+    boot::require(boot::Stage::Interrupts);
+
+    let start = ticks();
+    // the first tick may come right away, so wait for one more
+    let end = start + duration_to_ticks(duration) + 1;
+    while ticks() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the current value of the time stamp counter.
+///
+/// The read may be reordered with the surrounding instructions, use `rdtsc_ordered` where
+/// precision matters.
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Returns the current value of the time stamp counter once all earlier instructions have
+/// completed locally.
+pub fn rdtsc_ordered() -> u64 {
+    unsafe {
+        _mm_lfence();
+        _rdtsc()
+    }
+}
+
+/// Measures elapsed time in TSC cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    start: u64,
+}
+
+impl Stopwatch {
+    /// Starts a new measurement.
+    pub fn start() -> Self {
+        Stopwatch {
+            start: rdtsc_ordered(),
+        }
+    }
+
+    /// Returns the number of TSC cycles since the stopwatch was started.
+    pub fn elapsed_cycles(&self) -> u64 {
+        rdtsc_ordered().wrapping_sub(self.start)
+    }
+}
+
+/// Number of timer ticks the TSC is measured over by `calibrate_tsc_hz`.
+const CALIBRATION_TICKS: u64 = 10;
+
+/// Estimates the TSC frequency in Hz by counting its cycles over a few timer ticks.
+///
+/// Never returns if interrupts are disabled.
+pub fn calibrate_tsc_hz() -> u64 {
+    boot::require(boot::Stage::Interrupts);
+
+    // start right after a tick, so that whole ticks are measured
+    let first = ticks();
+    while ticks() == first {
+        core::hint::spin_loop();
+    }
+    let start_ticks = ticks();
+    let stopwatch = Stopwatch::start();
+    while ticks() < start_ticks + CALIBRATION_TICKS {
+        core::hint::spin_loop();
+    }
+    let cycles = stopwatch.elapsed_cycles();
+
+    let nanos = ticks_to_duration(CALIBRATION_TICKS).as_nanos();
+    (u128::from(cycles) * 1_000_000_000 / nanos) as u64
+}
+
+/// Converts `cycles` of a TSC running at `tsc_hz` to the time they span.
+pub fn cycles_to_duration(cycles: u64, tsc_hz: u64) -> Duration {
+    Duration::from_nanos((u128::from(cycles) * 1_000_000_000 / u128::from(tsc_hz)) as u64)
+}
+
+/// A calendar date and time of day (UTC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Creates a DateTime from the number of seconds since the unix epoch (1970-01-01 00:00:00).
+    pub fn from_unix(secs: u64) -> Self {
+        // days to civil date conversion after Howard Hinnant's `civil_from_days`
+        let z = secs / 86400 + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        // month starting from march, so that the leap day is the last day of the year
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+
+        let secs_of_day = secs % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    /// Returns the number of seconds since the unix epoch (1970-01-01 00:00:00).
+    pub fn to_unix(&self) -> u64 {
+        // civil date to days conversion after Howard Hinnant's `days_from_civil`
+        let month = u64::from(self.month);
+        let year = u64::from(self.year) - u64::from(month <= 2);
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + u64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * 86400
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// CMOS registers of the real-time clock
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+
+/// Reads the CMOS register `reg`.
+fn read_cmos(reg: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(0x70);
+    let mut data: Port<u8> = Port::new(0x71);
+    unsafe {
+        address.write(reg);
+        data.read()
+    }
+}
+
+/// Reads the raw date and time registers of the RTC as (second, minute, hour, day, month, year).
+fn read_rtc_registers() -> [u8; 6] {
+    // bit 7 of status register A is set while the RTC updates its registers
+    while read_cmos(RTC_STATUS_A) & 0x80 != 0 {}
+
+    [
+        read_cmos(RTC_SECONDS),
+        read_cmos(RTC_MINUTES),
+        read_cmos(RTC_HOURS),
+        read_cmos(RTC_DAY),
+        read_cmos(RTC_MONTH),
+        read_cmos(RTC_YEAR),
+    ]
+}
+
+/// Reads the current date and time from the CMOS real-time clock. The RTC is assumed to run
+/// in UTC and in the 21st century.
+///
+/// This is slow (several port accesses and possibly waiting for an RTC update to finish), use
+/// `now()` instead.
+pub fn read_rtc() -> DateTime {
+    let (regs, status_b) = interrupts::without_interrupts(|| {
+        // read until two consecutive reads agree, so that no update happened in between
+        let mut regs = read_rtc_registers();
+        loop {
+            let again = read_rtc_registers();
+            if again == regs {
+                break;
+            }
+            regs = again;
+        }
+        (regs, read_cmos(RTC_STATUS_B))
+    });
+
+    let [second, minute, hour, day, month, year] = regs;
+    // bit 2 of status register B is set for binary values, else the values are BCD encoded
+    let decode = |value: u8| {
+        if status_b & 0x04 != 0 {
+            value
+        } else {
+            (value >> 4) * 10 + (value & 0x0f)
+        }
+    };
+
+    // bit 1 of status register B is set in 24 hour mode. In 12 hour mode bit 7 of the hour
+    // marks PM and 12 o'clock is stored as 12.
+    let hour = if status_b & 0x02 == 0 {
+        let pm = hour & 0x80 != 0;
+        decode(hour & 0x7f) % 12 + if pm { 12 } else { 0 }
+    } else {
+        decode(hour)
+    };
+
+    DateTime {
+        year: 2000 + u16::from(decode(year)),
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    }
+}
+
+/// Number of seconds of uptime after which `now()` reads the RTC again to correct drift of the
+/// tick based clock.
+const RESYNC_INTERVAL_SECS: u64 = 60;
+
+/// Wall-clock base: the RTC time and the tick count at which it was read.
+struct Clock {
+    base_unix: u64,
+    base_ticks: u64,
+}
+
+impl Clock {
+    /// Creates a clock based on the current RTC time.
+    fn sync() -> Self {
+        Clock {
+            base_unix: read_rtc().to_unix(),
+            base_ticks: ticks(),
+        }
+    }
+}
+
+static CLOCK: Mutex<Option<Clock>> = Mutex::new(None);
+
+/// Reads the RTC once to establish the base of the wall clock returned by `now()`.
+pub fn init() {
+    // the clock is advanced by the timer interrupt
+    boot::require(boot::Stage::Interrupts);
+    *CLOCK.lock() = Some(Clock::sync());
+    boot::complete(boot::Stage::Clock);
+}
+
+/// Returns the current wall-clock time.
+///
+/// The time is advanced from the last RTC reading using the timer ticks and re-synchronized
+/// with the RTC every `RESYNC_INTERVAL_SECS` seconds. The resolution is one second and a
+/// re-synchronization may move the clock backwards by a fraction of a second.
+pub fn now() -> DateTime {
+    let mut clock = CLOCK.lock();
+    let mut base = clock.get_or_insert_with(Clock::sync);
+
+    let mut elapsed = ticks_to_duration(ticks() - base.base_ticks).as_secs();
+    if elapsed >= RESYNC_INTERVAL_SECS {
+        base = clock.insert(Clock::sync());
+        elapsed = 0;
+    }
+
+    DateTime::from_unix(base.base_unix + elapsed)
+}
+
+// -- UNIT TESTS -- //
+
+/// Test conversions between DateTime and unix time.
+#[test_case]
+fn date_time_unix_conversion() {
+    let date_time = |year, month, day, hour, minute, second| DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    let cases = [
+        (946_684_799, date_time(1999, 12, 31, 23, 59, 59)),
+        (951_868_800, date_time(2000, 3, 1, 0, 0, 0)),
+        (1_709_210_096, date_time(2024, 2, 29, 12, 34, 56)),
+    ];
+    for (secs, expected) in cases {
+        assert_eq!(DateTime::from_unix(secs), expected);
+        assert_eq!(expected.to_unix(), secs);
+    }
+}
+
+/// Test that converting ticks to a duration and back is lossless.
+#[test_case]
+fn time_duration_to_ticks() {
+    for ticks in [0, 1, 18, 1000] {
+        assert_eq!(duration_to_ticks(ticks_to_duration(ticks)), ticks);
+    }
+    // partial ticks are rounded up
+    assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+}
+
+/// Test that a busy sleep lasts the requested number of ticks.
+#[test_case]
+fn time_sleep_busy() {
+    let duration = Duration::from_millis(30);
+    let expected = duration_to_ticks(duration);
+
+    let start = ticks();
+    sleep_busy(duration);
+    let elapsed = ticks() - start;
+    assert!(elapsed > expected, "slept {} ticks", elapsed);
+    assert!(elapsed <= expected + 2, "slept {} ticks", elapsed);
+}
+
+/// Test that the wall clock advances with the timer ticks.
+#[test_case]
+fn time_now_advances() {
+    let start = now();
+    let start_ticks = ticks();
+    // wait for two seconds worth of ticks
+    while ticks_to_duration(ticks() - start_ticks) < Duration::from_secs(2) {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = now().to_unix() - start.to_unix();
+    // the clock has a resolution of one second
+    assert!((1..=3).contains(&elapsed));
+}
+
+/// Test that the wall clock stays close to the RTC.
+#[test_case]
+fn time_now_matches_rtc() {
+    assert!(now().to_unix().abs_diff(read_rtc().to_unix()) <= 2);
+}
+
+/// Test that successive TSC reads increase.
+#[test_case]
+fn time_rdtsc_monotonic() {
+    let first = rdtsc();
+    let second = rdtsc();
+    let third = rdtsc_ordered();
+    assert!(second > first);
+    assert!(third > second);
+
+    let stopwatch = Stopwatch::start();
+    assert!(stopwatch.elapsed_cycles() > 0);
+}
+
+/// Test that the calibrated TSC frequency is plausible and converts cycles back to time.
+#[test_case]
+fn time_calibrate_tsc() {
+    let hz = calibrate_tsc_hz();
+    // any CPU qemu runs on is faster than 100 MHz
+    assert!(hz > 100_000_000, "TSC at {} Hz", hz);
+    assert_eq!(cycles_to_duration(hz, hz), Duration::from_secs(1));
+    assert!((499..=500).contains(&cycles_to_duration(hz / 2, hz).as_millis()));
+}
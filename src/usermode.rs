@@ -0,0 +1,357 @@
+use crate::{counters, gdt, hlt_forever, interrupts, memory, print, println, vectors};
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+/// Start of the address range for user programs. It has its own level 4 entry, so that all page
+/// tables mapping it can be user accessible without exposing the kernel.
+pub const USER_START: u64 = 0x_1000_0000_0000;
+/// End of the address range for user programs (exclusive).
+pub const USER_END: u64 = USER_START + 0x4000_0000;
+/// Number of pages of the user stack, which ends at `USER_END`.
+pub const USER_STACK_PAGES: u64 = 4;
+
+/// System call printing the UTF-8 string at `rdi` with length `rsi`. Returns the length.
+pub const SYS_WRITE: u64 = 0;
+/// System call ending the user program with the exit code in `rdi`. Does not return.
+pub const SYS_EXIT: u64 = 1;
+/// Returned in `rax` by failed or unknown system calls.
+pub const SYSCALL_ERROR: u64 = u64::MAX;
+
+/// The registers of the caller of a system call, as saved by the entry stub.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    // pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// Entry stub of the system call interrupt. It saves all general purpose registers, so that the
+// user program only sees `rax` change, and passes them to `syscall_dispatch`. The CPU aligns
+// the stack to 16 bytes before pushing the 5 words of the interrupt frame, the 15 registers
+// restore the alignment for the call.
+global_asm!(
+    ".global trust_syscall_entry",
+    "trust_syscall_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "cld",
+    "call {dispatch}",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+    dispatch = sym syscall_dispatch,
+);
+
+// A tiny position independent user program that writes a greeting and exits with code 0.
+// Its bytes are copied to user pages by `load_program`, see `demo_program`.
+global_asm!(
+    ".global trust_user_demo_start",
+    ".global trust_user_demo_end",
+    "trust_user_demo_start:",
+    "mov eax, {write}",
+    "lea rdi, [rip + trust_user_demo_message]",
+    "lea rsi, [rip + trust_user_demo_end]",
+    "sub rsi, rdi",
+    "int {syscall}",
+    "mov eax, {exit}",
+    "xor edi, edi",
+    "int {syscall}",
+    "ud2",
+    "trust_user_demo_message:",
+    ".ascii \"Hello from ring 3!\\n\"",
+    "trust_user_demo_end:",
+    write = const SYS_WRITE,
+    exit = const SYS_EXIT,
+    syscall = const vectors::SYSCALL,
+);
+
+extern "C" {
+    fn trust_syscall_entry();
+    static trust_user_demo_start: u8;
+    static trust_user_demo_end: u8;
+}
+
+/// Returns the address of the system call entry stub, which is installed in the IDT.
+pub fn syscall_entry() -> VirtAddr {
+    VirtAddr::new(trust_syscall_entry as usize as u64)
+}
+
+/// Returns the machine code of the demo user program, which prints a greeting and exits.
+pub fn demo_program() -> &'static [u8] {
+    unsafe {
+        let start = core::ptr::addr_of!(trust_user_demo_start);
+        let end = core::ptr::addr_of!(trust_user_demo_end);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Maps pages for `program` at `USER_START` and for the user stack below `USER_END`, and copies
+/// `program` to its pages.
+///
+/// Returns the entry point and the top of the stack, as passed to `enter`.
+pub fn load_program(
+    program: &[u8],
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(VirtAddr, VirtAddr), MapToError<Size4KiB>> {
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let code_pages = (program.len() as u64 + 4095) / 4096;
+    let stack_start = USER_END - USER_STACK_PAGES * 4096;
+    let addresses = (0..code_pages)
+        .map(|i| USER_START + i * 4096)
+        .chain((0..USER_STACK_PAGES).map(|i| stack_start + i * 4096));
+    for addr in addresses {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // the level 4 entry of the user range is unused by the kernel, so the page tables
+        // created here inherit the user accessible flag
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    let code = USER_START as *mut u8;
+    unsafe { core::ptr::copy_nonoverlapping(program.as_ptr(), code, program.len()) };
+    Ok((VirtAddr::new(USER_START), VirtAddr::new(USER_END)))
+}
+
+/// Kernel stack pointer of the last call to `enter`, where the exit handler runs.
+static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+
+/// Called when the user program exits, see `set_exit_handler`.
+static EXIT_HANDLER: Mutex<fn(u64) -> !> = Mutex::new(default_exit_handler);
+
+/// Prints the exit code and halts.
+fn default_exit_handler(code: u64) -> ! {
+    println!("user program exited with code {}", code);
+    hlt_forever();
+}
+
+/// Sets the function called with the exit code when the user program exits. It runs on the
+/// kernel stack `enter` was called on, with interrupts enabled.
+pub fn set_exit_handler(handler: fn(u64) -> !) {
+    interrupts::without_interrupts(|| *EXIT_HANDLER.lock() = handler);
+}
+
+/// Drops to ring 3 and continues at `entry` with the stack pointer `user_stack`. Interrupts are
+/// enabled in the user program.
+///
+/// The kernel continues in the exit handler when the program exits, see `set_exit_handler`.
+///
+/// # Safety
+/// `entry` and the stack below `user_stack` must be mapped user accessible, and the stack must
+/// be 16 byte aligned.
+pub unsafe fn enter(entry: VirtAddr, user_stack: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    let data = u64::from(selectors.user_data_selector.0);
+    let code = u64::from(selectors.user_code_selector.0);
+    // interrupts enabled and the reserved bit 1
+    let rflags: u64 = 0x202;
+
+    x86_64::instructions::interrupts::disable();
+    let rsp: u64;
+    asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    KERNEL_STACK.store(rsp, Ordering::SeqCst);
+
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {user_stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        "iretq",
+        data = in(reg) data,
+        user_stack = in(reg) user_stack.as_u64(),
+        rflags = in(reg) rflags,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
+
+/// Called by the entry stub with the saved registers. Dispatches on `rax` and stores the result
+/// in it.
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    counters::inc("syscall");
+    frame.rax = match frame.rax {
+        SYS_WRITE => sys_write(frame.rdi, frame.rsi),
+        SYS_EXIT => sys_exit(frame.rdi),
+        _ => SYSCALL_ERROR,
+    };
+}
+
+/// Returns whether `len` bytes at `addr` are in the user address range.
+fn in_user_range(addr: u64, len: u64) -> bool {
+    addr >= USER_START && addr.checked_add(len).map_or(false, |end| end <= USER_END)
+}
+
+/// Returns whether `len` bytes at `addr` are in the user address range and every page of them
+/// is mapped user accessible, so that reading them cannot fault.
+fn user_readable(addr: u64, len: u64) -> bool {
+    if !in_user_range(addr, len) {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(addr + len - 1));
+    Page::range_inclusive(first, last).all(|page| {
+        memory::access_flags(page.start_address()).map_or(false, |flags| {
+            flags.contains(PageTableFlags::USER_ACCESSIBLE)
+        })
+    })
+}
+
+/// Prints the UTF-8 string of `len` bytes at `addr`.
+fn sys_write(addr: u64, len: u64) -> u64 {
+    if !user_readable(addr, len) {
+        return SYSCALL_ERROR;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+    match core::str::from_utf8(bytes) {
+        Ok(string) => {
+            print!("{}", string);
+            len
+        }
+        Err(_) => SYSCALL_ERROR,
+    }
+}
+
+/// Switches to the kernel stack of `enter` and calls the exit handler there. Fails if no user
+/// program has been entered.
+fn sys_exit(code: u64) -> u64 {
+    let stack = KERNEL_STACK.swap(0, Ordering::SeqCst);
+    if stack == 0 {
+        return SYSCALL_ERROR;
+    }
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "sti",
+            "call {exit}",
+            "ud2",
+            stack = in(reg) stack & !0xf,
+            exit = sym run_exit_handler,
+            in("rdi") code,
+            options(noreturn)
+        );
+    }
+}
+
+/// Calls the registered exit handler with `code`.
+extern "C" fn run_exit_handler(code: u64) -> ! {
+    let handler = interrupts::without_interrupts(|| *EXIT_HANDLER.lock());
+    handler(code)
+}
+
+// -- UNIT TESTS -- //
+
+/// Test that system calls from the kernel with kernel buffers or unknown numbers fail.
+#[test_case]
+fn usermode_syscall_errors() {
+    let syscall = |number: u64, rdi: u64, rsi: u64| {
+        let result: u64;
+        unsafe {
+            asm!(
+                "int {vector}",
+                vector = const vectors::SYSCALL,
+                inout("rax") number => result,
+                in("rdi") rdi,
+                in("rsi") rsi,
+            );
+        }
+        result
+    };
+
+    let message = "kernel";
+    assert_eq!(
+        syscall(SYS_WRITE, message.as_ptr() as u64, message.len() as u64),
+        SYSCALL_ERROR
+    );
+    assert_eq!(syscall(42, 0, 0), SYSCALL_ERROR);
+    // no user program is running
+    assert_eq!(syscall(SYS_EXIT, 0, 0), SYSCALL_ERROR);
+
+    assert!(in_user_range(USER_START, 16));
+    assert!(in_user_range(USER_END - 1, 1));
+    assert!(!in_user_range(USER_END - 1, 2));
+    assert!(!in_user_range(u64::MAX, 2));
+}
+
+/// Test that writing from unmapped user pages fails instead of faulting in the kernel.
+#[test_case]
+fn usermode_syscall_unmapped() {
+    let result: u64;
+    unsafe {
+        asm!(
+            "int {vector}",
+            vector = const vectors::SYSCALL,
+            inout("rax") SYS_WRITE => result,
+            in("rdi") USER_START + 0x10000,
+            in("rsi") 16u64,
+        );
+    }
+    assert_eq!(result, SYSCALL_ERROR);
+
+    assert!(!user_readable(USER_START + 0x10000, 16));
+    assert!(user_readable(USER_START, 0));
+}
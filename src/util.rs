@@ -0,0 +1,6 @@
+pub mod bitmap;
+pub mod bitmask;
+pub mod fixed_string;
+
+pub use self::bitmap::Bitmap;
+pub use self::fixed_string::FixedString;
@@ -0,0 +1,133 @@
+/// A bitmap over a borrowed slice of `u64` words. Bit `i` is bit `i % 64` of word `i / 64`.
+///
+/// Allocators use set bits for used and clear bits for free units.
+pub struct Bitmap<'a> {
+    words: &'a mut [u64],
+}
+
+impl<'a> Bitmap<'a> {
+    /// Creates a bitmap using `words` as storage. The current contents are kept.
+    pub fn new(words: &'a mut [u64]) -> Self {
+        Bitmap { words }
+    }
+
+    /// Returns the number of bits.
+    pub fn len(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    /// Returns whether the bitmap has no bits.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns bit `index`. Panics if `index` is out of range.
+    pub fn get(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Sets bit `index`. Panics if `index` is out of range.
+    pub fn set(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    /// Clears bit `index`. Panics if `index` is out of range.
+    pub fn clear(&mut self, index: usize) {
+        self.words[index / 64] &= !(1 << (index % 64));
+    }
+
+    /// Returns the index of the first clear bit.
+    pub fn find_first_zero(&self) -> Option<usize> {
+        // full words are skipped without looking at their bits
+        self.words
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)
+            .map(|(i, word)| i * 64 + word.trailing_ones() as usize)
+    }
+
+    /// Returns the index of the first run of `n` clear bits. The run may span several words.
+    ///
+    /// A run of 0 bits is found at index 0.
+    pub fn find_contiguous_zeros(&self, n: usize) -> Option<usize> {
+        if n == 0 {
+            return Some(0);
+        }
+
+        let mut start = 0;
+        let mut run = 0;
+        let mut index = 0;
+        while index < self.len() {
+            let word = self.words[index / 64];
+            if index % 64 == 0 && word == u64::MAX {
+                // a full word ends the current run
+                run = 0;
+                index += 64;
+                continue;
+            }
+
+            if word & (1 << (index % 64)) == 0 {
+                if run == 0 {
+                    start = index;
+                }
+                run += 1;
+                if run == n {
+                    return Some(start);
+                }
+            } else {
+                run = 0;
+            }
+            index += 1;
+        }
+        None
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test setting, clearing and reading bits in both words.
+#[test_case]
+fn bitmap_set_clear_get() {
+    let mut words = [0; 2];
+    let mut bitmap = Bitmap::new(&mut words);
+    assert_eq!(bitmap.len(), 128);
+
+    for index in [0, 5, 63, 64, 127] {
+        assert!(!bitmap.get(index));
+        bitmap.set(index);
+        assert!(bitmap.get(index));
+    }
+    bitmap.clear(63);
+    assert!(!bitmap.get(63));
+    assert!(bitmap.get(64));
+
+    assert_eq!(words, [1 | 1 << 5, 1 | 1 << 63]);
+}
+
+/// Test finding the first clear bit, skipping full words.
+#[test_case]
+fn bitmap_find_first_zero() {
+    let mut words = [u64::MAX, 0b0111, 0];
+    let mut bitmap = Bitmap::new(&mut words);
+    assert_eq!(bitmap.find_first_zero(), Some(67));
+
+    bitmap.set(67);
+    assert_eq!(bitmap.find_first_zero(), Some(68));
+
+    let mut full = [u64::MAX; 2];
+    assert_eq!(Bitmap::new(&mut full).find_first_zero(), None);
+}
+
+/// Test finding runs of clear bits that cross word boundaries.
+#[test_case]
+fn bitmap_find_contiguous_zeros() {
+    // bits 60-63 of the first word and 0-7 of the second word are clear
+    let mut words = [u64::MAX >> 4, !0xff, u64::MAX, 0];
+    let bitmap = Bitmap::new(&mut words);
+    assert_eq!(bitmap.find_contiguous_zeros(1), Some(60));
+    assert_eq!(bitmap.find_contiguous_zeros(12), Some(60));
+    // the run ends at bit 72, the next free bits are in the last word
+    assert_eq!(bitmap.find_contiguous_zeros(13), Some(192));
+    assert_eq!(bitmap.find_contiguous_zeros(64), Some(192));
+    assert_eq!(bitmap.find_contiguous_zeros(65), None);
+}
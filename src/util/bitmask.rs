@@ -0,0 +1,48 @@
+/// Builds a mask with the bits `high..low` (both inclusive) or the single bit `bit` set.
+///
+/// The type of the mask can be given first, e.g. `bitmask!(u32; 23..16)` or
+/// `bitmask!(u64; 63)`. Without a type the mask is a `u64`. The bit range is checked at
+/// compile time: `high` must not be below `low` and both must fit the type.
+#[macro_export]
+macro_rules! bitmask {
+    ($ty:ty; $high:literal..$low:literal) => {{
+        const _: () = assert!($high >= $low, "bitmask: high bit is below low bit");
+        const _: () = assert!($high < <$ty>::BITS, "bitmask: bit out of range of the type");
+        // shifting the all ones value never shifts a set bit out of the type
+        (<$ty>::MAX >> (<$ty>::BITS - 1 - $high)) & (<$ty>::MAX << $low)
+    }};
+    ($ty:ty; $bit:literal) => {
+        $crate::bitmask!($ty; $bit..$bit)
+    };
+    ($high:literal..$low:literal) => {
+        $crate::bitmask!(u64; $high..$low)
+    };
+    ($bit:literal) => {
+        $crate::bitmask!(u64; $bit)
+    };
+}
+
+// -- UNIT TESTS -- //
+
+// the top bits, which `1 << 31` and `1 << 63` on the default integer type cannot express
+const _: () = assert!(bitmask!(u32; 31) == 0x8000_0000);
+const _: () = assert!(bitmask!(u64; 63) == 0x8000_0000_0000_0000);
+const _: () = assert!(bitmask!(u32; 31..0) == u32::MAX);
+const _: () = assert!(bitmask!(u64; 63..0) == u64::MAX);
+const _: () = assert!(bitmask!(u32; 23..16) == 0x00ff_0000);
+const _: () = assert!(bitmask!(u8; 7..4) == 0xf0);
+// the untyped forms build u64 masks
+const _: () = assert!(bitmask!(63) == 1 << 63);
+const _: () = assert!(bitmask!(51..12) == 0x000f_ffff_ffff_f000);
+
+/// Test the masks at runtime, including their types.
+#[test_case]
+fn bitmask_types_and_ranges() {
+    let low: u16 = bitmask!(u16; 0);
+    let high: u16 = bitmask!(u16; 15);
+    assert_eq!(low | high, 0x8001);
+
+    let register: u32 = 0x1234_5678;
+    assert_eq!((register & bitmask!(u32; 15..8)) >> 8, 0x56);
+    assert_eq!(bitmask!(5..5), 1u64 << 5);
+}
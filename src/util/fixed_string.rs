@@ -0,0 +1,117 @@
+use core::{fmt, ops::Deref};
+
+/// A string with a fixed capacity of `N` bytes stored inline. It does not allocate and can be
+/// used to format messages before the heap is initialized or during a panic.
+#[derive(Clone, Copy)]
+pub struct FixedString<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedString<N> {
+    /// Creates an empty string.
+    pub const fn new() -> Self {
+        FixedString {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the contents as a string slice.
+    pub fn as_str(&self) -> &str {
+        // only whole characters are ever appended
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    /// Returns the capacity in bytes.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Removes all contents.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends as much of `s` as fits. Characters are never split. Returns false if `s` was
+    /// truncated.
+    pub fn push_str(&mut self, s: &str) -> bool {
+        let free = N - self.len;
+        let mut end = s.len().min(free);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.bytes[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        end == s.len()
+    }
+}
+
+impl<const N: usize> Default for FixedString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for FixedString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedString<N> {
+    /// Appends `s`. When it does not fit, the part that fits is kept and an error is returned.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.push_str(s) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+// -- UNIT TESTS -- //
+
+/// Test formatting into a FixedString.
+#[test_case]
+fn fixed_string_format() {
+    use core::fmt::Write;
+
+    let mut s = FixedString::<64>::new();
+    write!(s, "{} + {} = {}, {}", 1, 2, 1 + 2, "ok").expect("write failed");
+    assert_eq!(s.as_str(), "1 + 2 = 3, ok");
+    assert_eq!(s.capacity(), 64);
+
+    s.clear();
+    assert!(s.is_empty());
+}
+
+/// Test that writes exceeding the capacity are truncated at a character boundary.
+#[test_case]
+fn fixed_string_truncation() {
+    use core::fmt::Write;
+
+    let mut s = FixedString::<8>::new();
+    assert!(write!(s, "{}", 123_456_789).is_err());
+    assert_eq!(&*s, "12345678");
+
+    // 'ä' takes two bytes and must not be split
+    let mut s = FixedString::<4>::new();
+    assert!(!s.push_str("abcä"));
+    assert_eq!(&*s, "abc");
+}
@@ -0,0 +1,112 @@
+// Map of the 256 interrupt vectors and the vectors reserved by the kernel:
+//
+// | vectors     | use                                          |
+// |-------------|----------------------------------------------|
+// | 0x00 - 0x1f | CPU exceptions                               |
+// | 0x20 - 0x2f | IRQs 0-15 of the chained 8259 PICs           |
+// | 0x30        | local APIC timer                             |
+// | 0x80        | system calls                                 |
+// | 0x81        | trace points                                 |
+// | 0xff        | local APIC spurious interrupts               |
+
+// CPU exceptions
+pub const DIVIDE_ERROR: u8 = 0x00;
+pub const DEBUG: u8 = 0x01;
+pub const NON_MASKABLE_INTERRUPT: u8 = 0x02;
+pub const BREAKPOINT: u8 = 0x03;
+pub const OVERFLOW: u8 = 0x04;
+pub const BOUND_RANGE_EXCEEDED: u8 = 0x05;
+pub const INVALID_OPCODE: u8 = 0x06;
+pub const DEVICE_NOT_AVAILABLE: u8 = 0x07;
+pub const DOUBLE_FAULT: u8 = 0x08;
+pub const INVALID_TSS: u8 = 0x0a;
+pub const SEGMENT_NOT_PRESENT: u8 = 0x0b;
+pub const STACK_SEGMENT_FAULT: u8 = 0x0c;
+pub const GENERAL_PROTECTION_FAULT: u8 = 0x0d;
+pub const PAGE_FAULT: u8 = 0x0e;
+pub const X87_FLOATING_POINT: u8 = 0x10;
+pub const ALIGNMENT_CHECK: u8 = 0x11;
+pub const MACHINE_CHECK: u8 = 0x12;
+pub const SIMD_FLOATING_POINT: u8 = 0x13;
+pub const VIRTUALIZATION: u8 = 0x14;
+pub const CONTROL_PROTECTION: u8 = 0x15;
+pub const HYPERVISOR_INJECTION: u8 = 0x1c;
+pub const VMM_COMMUNICATION: u8 = 0x1d;
+pub const SECURITY: u8 = 0x1e;
+
+/// Number of vectors reserved for CPU exceptions, starting at 0.
+pub const EXCEPTION_COUNT: u8 = 32;
+
+/// Vector of IRQ 0 of the master PIC.
+pub const PIC_1_OFFSET: u8 = EXCEPTION_COUNT;
+/// Vector of IRQ 8, the first IRQ of the slave PIC.
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+/// First vector after the PIC IRQs.
+pub const PIC_END: u8 = PIC_2_OFFSET + 8;
+
+/// Vector of the periodic local APIC timer.
+pub const APIC_TIMER: u8 = 0x30;
+/// Vector of the system call software interrupt.
+pub const SYSCALL: u8 = 0x80;
+/// Vector of the software interrupt raised by `trace_point!`.
+pub const TRACE_POINT: u8 = 0x81;
+/// Vector of the spurious interrupts raised by the local APIC.
+pub const APIC_SPURIOUS: u8 = 0xff;
+
+/// The vectors reserved by the kernel outside of the exception and PIC ranges.
+pub const KERNEL_VECTORS: [u8; 4] = [APIC_TIMER, SYSCALL, TRACE_POINT, APIC_SPURIOUS];
+
+/// Returns whether `vector` is reserved for a CPU exception.
+pub const fn is_exception(vector: u8) -> bool {
+    vector < EXCEPTION_COUNT
+}
+
+/// Returns whether `vector` is raised by one of the PICs.
+pub const fn is_pic_irq(vector: u8) -> bool {
+    vector >= PIC_1_OFFSET && vector < PIC_END
+}
+
+/// Returns whether all vectors in `vectors` are distinct.
+const fn all_distinct(vectors: &[u8]) -> bool {
+    let mut i = 0;
+    while i < vectors.len() {
+        let mut j = i + 1;
+        while j < vectors.len() {
+            if vectors[i] == vectors[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Returns whether none of `vectors` is an exception or PIC vector.
+const fn outside_fixed_ranges(vectors: &[u8]) -> bool {
+    let mut i = 0;
+    while i < vectors.len() {
+        if is_exception(vectors[i]) || is_pic_irq(vectors[i]) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// the reserved vectors must not collide with each other or with the exceptions and PIC IRQs
+const _: () = assert!(all_distinct(&KERNEL_VECTORS));
+const _: () = assert!(outside_fixed_ranges(&KERNEL_VECTORS));
+
+// -- UNIT TESTS -- //
+
+/// Test the collision checks used by the compile time assertions.
+#[test_case]
+fn vectors_collision_checks() {
+    assert!(all_distinct(&[APIC_TIMER, SYSCALL]));
+    assert!(!all_distinct(&[SYSCALL, TRACE_POINT, SYSCALL]));
+    assert!(!outside_fixed_ranges(&[APIC_TIMER, PIC_2_OFFSET]));
+    assert!(!outside_fixed_ranges(&[PAGE_FAULT]));
+    assert!(is_pic_irq(PIC_END - 1));
+    assert!(!is_pic_irq(PIC_END));
+}
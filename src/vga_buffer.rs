@@ -1,7 +1,12 @@
-use core::fmt;
+use crate::boot::BootArgs;
+use core::{
+    fmt, mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
+use x86_64::instructions::port::Port;
 
 /// The Color enum is an abstraction for the 4-bit VGA text buffer colors.
 #[allow(dead_code)]
@@ -26,6 +31,34 @@ pub enum Color {
     White = 0xf,
 }
 
+impl Color {
+    /// Returns the color with the given name, e.g. "blue" or "lightgray". Case is ignored.
+    pub fn from_name(name: &str) -> Option<Color> {
+        const NAMES: [(&str, Color); 16] = [
+            ("black", Color::Black),
+            ("blue", Color::Blue),
+            ("green", Color::Green),
+            ("cyan", Color::Cyan),
+            ("red", Color::Red),
+            ("magenta", Color::Magenta),
+            ("brown", Color::Brown),
+            ("lightgray", Color::LightGray),
+            ("darkgray", Color::DarkGray),
+            ("lightblue", Color::LightBlue),
+            ("lightgreen", Color::LightGreen),
+            ("lightcyan", Color::LightCyan),
+            ("lightred", Color::LightRed),
+            ("pink", Color::Pink),
+            ("yellow", Color::Yellow),
+            ("white", Color::White),
+        ];
+        NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, color)| *color)
+    }
+}
+
 /// The ColorCode struct serves as an abstraction for a 8-bit VGA text buffer color code
 /// formed from the foreground and background color. The blink bit (bit 7) is included in
 /// background color.
@@ -37,12 +70,68 @@ pub enum Color {
 pub struct ColorCode(u8);
 
 impl ColorCode {
-    pub fn new(font: Color, background: Color) -> ColorCode {
+    pub const fn new(font: Color, background: Color) -> ColorCode {
         // first 4 bits are foreground, last 4 are background
         ColorCode((background as u8) << 4 | (font as u8))
     }
+
+    /// Creates a color code for `font` on a black background.
+    pub const fn on_black(font: Color) -> ColorCode {
+        ColorCode::new(font, Color::Black)
+    }
+
+    /// Color for error messages: white on red.
+    pub const fn error() -> ColorCode {
+        ColorCode::new(Color::White, Color::Red)
+    }
+
+    /// Color for success messages: green on black.
+    pub const fn success() -> ColorCode {
+        ColorCode::on_black(Color::Green)
+    }
+
+    /// Color for warnings: yellow on black.
+    pub const fn warning() -> ColorCode {
+        ColorCode::on_black(Color::Yellow)
+    }
+
+    /// Returns this color code with the font color replaced by `font`.
+    const fn with_font(self, font: Color) -> ColorCode {
+        ColorCode((self.0 & 0xf0) | font as u8)
+    }
+
+    /// Returns this color code with the background color replaced by `background`.
+    const fn with_background(self, background: Color) -> ColorCode {
+        ColorCode((self.0 & 0x0f) | (background as u8) << 4)
+    }
 }
 
+/// Color the writer starts with and returns to on an ANSI reset sequence.
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::White, Color::Black);
+
+/// The VGA colors of the ANSI color indices 0-7 (black, red, green, yellow, blue, magenta, cyan,
+/// white) and of their bright variants.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::Pink,
+    Color::LightCyan,
+    Color::White,
+];
+
 /// A ScreenChar is a C-like struct representation of an ASCII character along with an
 /// associated ColorCode that defines the appearence of the character.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -55,24 +144,289 @@ struct ScreenChar {
 const BUFFER_SIZE_X: usize = 80;
 const BUFFER_SIZE_Y: usize = 25;
 
+// ports of the CRT controller (CRTC) registers
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+
+// CRTC registers
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+
+// ports of the attribute controller
+const ATTR_INDEX_DATA: u16 = 0x3c0;
+const ATTR_DATA_READ: u16 = 0x3c1;
+// reading the input status register 1 resets the attribute controller flip-flop
+const INPUT_STATUS_1: u16 = 0x3da;
+
+// attribute controller registers
+const ATTR_MODE_CONTROL: u8 = 0x10;
+
+/// Selects the attribute controller register `index`. Afterwards the next write to port 0x3c0
+/// is a data write.
+///
+/// Must be called with interrupts disabled, as an interrupt touching the attribute controller
+/// could leave the flip-flop in the data state.
+fn attr_select(index: u8) {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS_1);
+    let mut index_port: Port<u8> = Port::new(ATTR_INDEX_DATA);
+    unsafe {
+        // put the flip-flop into the index state
+        status.read();
+        // bit 5 (palette address source) keeps the screen enabled, clearing it blanks the display
+        index_port.write((index & 0x1f) | 0x20);
+    }
+}
+
+/// Writes `value` to the attribute controller register `index`.
+pub fn attr_write(index: u8, value: u8) {
+    use crate::interrupts;
+
+    let mut data: Port<u8> = Port::new(ATTR_INDEX_DATA);
+    interrupts::without_interrupts(|| {
+        attr_select(index);
+        unsafe { data.write(value) };
+    });
+}
+
+/// Reads the attribute controller register `index`.
+pub fn attr_read(index: u8) -> u8 {
+    use crate::interrupts;
+
+    let mut data: Port<u8> = Port::new(ATTR_DATA_READ);
+    interrupts::without_interrupts(|| {
+        attr_select(index);
+        unsafe { data.read() }
+    })
+}
+
+/// Selects whether bit 7 of the background color makes characters blink (`true`) or selects
+/// a bright background color (`false`).
+pub fn set_blink(enabled: bool) {
+    let mode = attr_read(ATTR_MODE_CONTROL);
+    // bit 3 of the mode control register enables blinking
+    let mode = if enabled { mode | 0x08 } else { mode & !0x08 };
+    attr_write(ATTR_MODE_CONTROL, mode);
+}
+
+/// Writes `value` to the CRTC register `index`.
+fn crtc_write(index: u8, value: u8) {
+    let mut index_port: Port<u8> = Port::new(CRTC_INDEX);
+    let mut data_port: Port<u8> = Port::new(CRTC_DATA);
+    unsafe {
+        index_port.write(index);
+        data_port.write(value);
+    }
+}
+
+/// Reads the CRTC register `index`.
+fn crtc_read(index: u8) -> u8 {
+    let mut index_port: Port<u8> = Port::new(CRTC_INDEX);
+    let mut data_port: Port<u8> = Port::new(CRTC_DATA);
+    unsafe {
+        index_port.write(index);
+        data_port.read()
+    }
+}
+
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_SIZE_X]; BUFFER_SIZE_Y],
 }
 
+/// Maximum number of rows kept in the scrollback history.
+const HISTORY_LINES: usize = 200;
+
+type Row = [ScreenChar; BUFFER_SIZE_X];
+
+const BLANK_ROW: Row = [ScreenChar {
+    ascii: b' ',
+    color_code: ColorCode::new(Color::White, Color::Black),
+}; BUFFER_SIZE_X];
+
+/// Rows that scrolled off the top of the screen, and a copy of the live screen while the view
+/// is scrolled back.
+struct History {
+    rows: [Row; HISTORY_LINES],
+    // index the next row is stored at
+    next: usize,
+    // number of stored rows, at most `limit`
+    count: usize,
+    // maximum number of stored rows, at most `HISTORY_LINES`
+    limit: usize,
+    live: [Row; BUFFER_SIZE_Y],
+}
+
+impl History {
+    const fn new() -> Self {
+        History {
+            rows: [BLANK_ROW; HISTORY_LINES],
+            next: 0,
+            count: 0,
+            limit: HISTORY_LINES,
+            live: [BLANK_ROW; BUFFER_SIZE_Y],
+        }
+    }
+
+    /// Appends a row, dropping the oldest one when the history is full.
+    fn push(&mut self, row: Row) {
+        if self.limit == 0 {
+            return;
+        }
+        self.rows[self.next] = row;
+        self.next = (self.next + 1) % HISTORY_LINES;
+        self.count = (self.count + 1).min(self.limit);
+    }
+
+    /// Sets the maximum number of stored rows, dropping the oldest rows above it.
+    fn set_limit(&mut self, rows: usize) {
+        self.limit = rows.min(HISTORY_LINES);
+        self.count = self.count.min(self.limit);
+    }
+
+    /// Returns the `i`-th stored row, counted from the oldest.
+    fn row(&self, i: usize) -> &Row {
+        &self.rows[(self.next + HISTORY_LINES - self.count + i) % HISTORY_LINES]
+    }
+}
+
+/// Maximum number of parameters of an ANSI escape sequence. Further parameters are ignored.
+const MAX_ANSI_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    /// Not inside an escape sequence.
+    Ground,
+    /// After an ESC character.
+    Escape,
+    /// Inside a control sequence (`ESC [`), collecting parameters.
+    Csi,
+}
+
+/// Parser state of the ANSI escape sequence currently written.
+struct AnsiParser {
+    state: AnsiState,
+    params: [u16; MAX_ANSI_PARAMS],
+    // index of the parameter currently collected
+    current: usize,
+}
+
+impl AnsiParser {
+    const fn new() -> Self {
+        AnsiParser {
+            state: AnsiState::Ground,
+            params: [0; MAX_ANSI_PARAMS],
+            current: 0,
+        }
+    }
+
+    /// Returns the parameters of the current control sequence. A missing parameter is 0.
+    fn params(&self) -> &[u16] {
+        &self.params[..=self.current.min(MAX_ANSI_PARAMS - 1)]
+    }
+}
+
+// kept outside of the writer to avoid building it on the stack when the writer is created
+static HISTORY: Mutex<History> = Mutex::new(History::new());
+
 /// A writer can be used to modify the VGA text buffer.
 pub struct Writer {
     // The position of the cursor in the lowest row.
     column_pos: usize,
     // The ColorCode to be used for subsequent writes.
     color_code: ColorCode,
+    // number of rows the view is scrolled back into the history, 0 shows the live screen.
+    scroll_offset: usize,
+    // state of the ANSI escape sequence parser.
+    ansi: AnsiParser,
+    // off-screen copy of the screen all writes go to, shown by `flush`.
+    shadow: [Row; BUFFER_SIZE_Y],
+    // the screen contents as of the last flush.
+    flushed: [Row; BUFFER_SIZE_Y],
     // mutable reference to the VGA text buffer (0xb8000).
     buffer: &'static mut Buffer,
 }
 
 impl Writer {
+    /// Creates a writer for `buffer`, which keeps the text that is already on the screen.
+    fn new(buffer: &'static mut Buffer) -> Self {
+        let mut shadow = [BLANK_ROW; BUFFER_SIZE_Y];
+        for (row, chars) in shadow.iter_mut().enumerate() {
+            for (col, char) in chars.iter_mut().enumerate() {
+                *char = buffer.chars[row][col].read();
+            }
+        }
+        Writer {
+            column_pos: 0,
+            color_code: DEFAULT_COLOR,
+            scroll_offset: 0,
+            ansi: AnsiParser::new(),
+            shadow,
+            flushed: shadow,
+            buffer,
+        }
+    }
+
+    /// Copies the cells of the off-screen buffer that changed since the last flush to the VGA
+    /// text buffer. Writes only become visible with a flush, so that the screen never shows a
+    /// half scrolled or half repainted state.
+    pub fn flush(&mut self) {
+        for row in 0..BUFFER_SIZE_Y {
+            if self.shadow[row] == self.flushed[row] {
+                continue;
+            }
+            for col in 0..BUFFER_SIZE_X {
+                let char = self.shadow[row][col];
+                if char != self.flushed[row][col] {
+                    self.buffer.chars[row][col].write(char);
+                }
+            }
+            self.flushed[row] = self.shadow[row];
+        }
+    }
+
+    /// Sets the color used for subsequent writes. Already written characters keep their color.
+    pub fn set_color(&mut self, font: Color, background: Color) {
+        self.color_code = ColorCode::new(font, background);
+    }
+
+    /// Returns the color used for subsequent writes.
+    pub fn color(&self) -> ColorCode {
+        self.color_code
+    }
+
+    /// Moves the blinking hardware cursor to the current position in the last row.
+    pub fn update_cursor(&self) {
+        // when the row is full the cursor stays on the last column until the next write wraps
+        let col = self.column_pos.min(BUFFER_SIZE_X - 1);
+        let pos = (BUFFER_SIZE_Y - 1) * BUFFER_SIZE_X + col;
+        crtc_write(CRTC_CURSOR_LOW, pos as u8);
+        crtc_write(CRTC_CURSOR_HIGH, (pos >> 8) as u8);
+    }
+
+    /// Shows the hardware cursor spanning the scanlines `start` to `end` (0-15) of a character
+    /// cell.
+    pub fn enable_cursor(&self, start: u8, end: u8) {
+        // keep the reserved upper bits of the shape registers, bit 5 of the start disables
+        crtc_write(
+            CRTC_CURSOR_START,
+            (crtc_read(CRTC_CURSOR_START) & 0xc0) | (start & 0x1f),
+        );
+        crtc_write(
+            CRTC_CURSOR_END,
+            (crtc_read(CRTC_CURSOR_END) & 0xe0) | (end & 0x1f),
+        );
+    }
+
+    /// Hides the hardware cursor.
+    pub fn disable_cursor(&self) {
+        crtc_write(CRTC_CURSOR_START, 0x20);
+    }
+
     /// Writes a byte to the buffer. Does not check for printable ASCII characters.
     fn write(&mut self, byte: u8) {
+        self.snap_to_bottom();
         match byte {
             b'\n' => self.newline(),
             b'\r' => self.column_pos = 0,
@@ -85,25 +439,23 @@ impl Writer {
                 let row = BUFFER_SIZE_Y - 1;
                 let col = self.column_pos;
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
+                self.shadow[row][col] = ScreenChar {
                     ascii: byte,
-                    color_code,
-                });
+                    color_code: self.color_code,
+                };
                 self.column_pos += 1;
             }
         }
+        self.update_cursor();
     }
 
     /// Performs a newline operation on the buffer by moving every row up by 1.
     fn newline(&mut self) {
-        // move every character up by one row
-        for row in 1..BUFFER_SIZE_Y {
-            for col in 0..BUFFER_SIZE_X {
-                let char = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(char);
-            }
-        }
+        // keep the row scrolling off the screen
+        HISTORY.lock().push(self.read_row(0));
+
+        // move every row up by one
+        self.shadow.copy_within(1.., 0);
         self.clear_row(BUFFER_SIZE_Y - 1);
     }
 
@@ -113,12 +465,115 @@ impl Writer {
             ascii: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_SIZE_X {
-            self.buffer.chars[row][col].write(blank);
-        }
+        self.shadow[row] = [blank; BUFFER_SIZE_X];
         self.column_pos = 0;
     }
 
+    /// Writes `byte` with `color` to the cell at `row` and `col` without moving the write
+    /// position or scrolling. Positions outside the screen are ignored.
+    pub fn write_at(&mut self, row: usize, col: usize, byte: u8, color: ColorCode) {
+        self.snap_to_bottom();
+        if row < BUFFER_SIZE_Y && col < BUFFER_SIZE_X {
+            self.shadow[row][col] = ScreenChar {
+                ascii: byte,
+                color_code: color,
+            };
+        }
+    }
+
+    /// Returns the ASCII byte and color of the cell at `row` and `col` of the visible screen.
+    ///
+    /// Panics if the position is outside the screen.
+    pub fn read_at(&self, row: usize, col: usize) -> (u8, ColorCode) {
+        assert!(
+            row < BUFFER_SIZE_Y && col < BUFFER_SIZE_X,
+            "read_at: position ({}, {}) is outside the {}x{} screen",
+            row,
+            col,
+            BUFFER_SIZE_Y,
+            BUFFER_SIZE_X
+        );
+        let char = self.shadow[row][col];
+        (char.ascii, char.color_code)
+    }
+
+    /// Writes `s` with `color` starting at `row` and `col` without moving the write position or
+    /// scrolling. The string does not wrap, characters beyond the end of the row are dropped.
+    /// Non-printable characters are shown as `~`.
+    pub fn write_str_at(&mut self, row: usize, col: usize, s: &str, color: ColorCode) {
+        for (i, byte) in s.bytes().enumerate() {
+            let byte = match byte {
+                0x20..=0x7e => byte,
+                _ => 0x7e,
+            };
+            self.write_at(row, col + i, byte, color);
+        }
+    }
+
+    /// Clears the whole screen with the current color. Writing continues at the start of the
+    /// last row as usual.
+    pub fn clear_screen(&mut self) {
+        self.snap_to_bottom();
+        for row in 0..BUFFER_SIZE_Y {
+            self.clear_row(row);
+        }
+        self.update_cursor();
+    }
+
+    /// Returns a copy of the visible `row`.
+    fn read_row(&self, row: usize) -> Row {
+        self.shadow[row]
+    }
+
+    /// Scrolls the view `lines` rows back into the history. The live screen is restored with
+    /// `scroll_down` or as soon as something is written.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let mut history = HISTORY.lock();
+        let offset = (self.scroll_offset + lines).min(history.count);
+        if offset == self.scroll_offset {
+            return;
+        }
+
+        if self.scroll_offset == 0 {
+            // save the live screen to restore it later
+            for row in 0..BUFFER_SIZE_Y {
+                history.live[row] = self.read_row(row);
+            }
+        }
+        self.scroll_offset = offset;
+        self.repaint(&history);
+    }
+
+    /// Scrolls the view `lines` rows towards the live screen.
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.scroll_offset == 0 {
+            return;
+        }
+        let history = HISTORY.lock();
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+        self.repaint(&history);
+    }
+
+    /// Returns to the live screen if the view is scrolled back.
+    fn snap_to_bottom(&mut self) {
+        self.scroll_down(self.scroll_offset);
+    }
+
+    /// Draws the rows visible at the current scroll offset.
+    fn repaint(&mut self, history: &History) {
+        // the rows form one list of the history (oldest first) followed by the live screen
+        let first = history.count - self.scroll_offset;
+        for row in 0..BUFFER_SIZE_Y {
+            let line = first + row;
+            let chars = if line < history.count {
+                history.row(line)
+            } else {
+                &history.live[line - history.count]
+            };
+            self.shadow[row] = *chars;
+        }
+    }
+
     /// Clears the last written character.
     fn backspace(&mut self) {
         // when row is empty ignore backspace characters
@@ -133,13 +588,93 @@ impl Writer {
 
         // column of the last typed char
         let col = self.column_pos - 1;
-        self.buffer.chars[BUFFER_SIZE_Y - 1][col].write(blank);
+        self.shadow[BUFFER_SIZE_Y - 1][col] = blank;
         self.column_pos = col;
     }
 
-    // Writes a string to the buffer. Checks for printable ASCII characters.
+    /// Feeds `byte` to the ANSI escape sequence parser. Returns false if the byte is not part of
+    /// an escape sequence and has to be written.
+    ///
+    /// Select Graphic Rendition sequences (`ESC [ ... m`) change the color, all other sequences
+    /// are consumed without effect.
+    fn handle_escape(&mut self, byte: u8) -> bool {
+        let ansi = &mut self.ansi;
+        match ansi.state {
+            AnsiState::Ground if byte == 0x1b => {
+                ansi.state = AnsiState::Escape;
+                true
+            }
+            AnsiState::Ground => false,
+            AnsiState::Escape if byte == b'[' => {
+                ansi.state = AnsiState::Csi;
+                ansi.params = [0; MAX_ANSI_PARAMS];
+                ansi.current = 0;
+                true
+            }
+            AnsiState::Escape => {
+                // unsupported escape sequence, drop its final character unless it is a control
+                // character
+                ansi.state = AnsiState::Ground;
+                (0x20..=0x7e).contains(&byte)
+            }
+            AnsiState::Csi => match byte {
+                b'0'..=b'9' => {
+                    if let Some(param) = ansi.params.get_mut(ansi.current) {
+                        *param = param
+                            .saturating_mul(10)
+                            .saturating_add(u16::from(byte - b'0'));
+                    }
+                    true
+                }
+                b';' => {
+                    ansi.current = (ansi.current + 1).min(MAX_ANSI_PARAMS);
+                    true
+                }
+                // final byte
+                0x40..=0x7e => {
+                    ansi.state = AnsiState::Ground;
+                    if byte == b'm' {
+                        self.apply_sgr();
+                    }
+                    true
+                }
+                // intermediate and private parameter bytes
+                0x20..=0x3f => true,
+                // malformed sequence, abort it
+                _ => {
+                    ansi.state = AnsiState::Ground;
+                    false
+                }
+            },
+        }
+    }
+
+    /// Applies the color parameters of a Select Graphic Rendition sequence.
+    fn apply_sgr(&mut self) {
+        for i in 0..self.ansi.params().len() {
+            let param = usize::from(self.ansi.params[i]);
+            self.color_code = match param {
+                0 => DEFAULT_COLOR,
+                30..=37 => self.color_code.with_font(ANSI_COLORS[param - 30]),
+                39 => self.color_code.with_font(Color::White),
+                40..=47 => self.color_code.with_background(ANSI_COLORS[param - 40]),
+                49 => self.color_code.with_background(Color::Black),
+                90..=97 => self.color_code.with_font(ANSI_BRIGHT_COLORS[param - 90]),
+                100..=107 => self
+                    .color_code
+                    .with_background(ANSI_BRIGHT_COLORS[param - 100]),
+                _ => self.color_code,
+            };
+        }
+    }
+
+    // Writes a string to the buffer. Checks for printable ASCII characters and interprets ANSI
+    // color escape sequences.
     fn write_string(&mut self, str: &str) {
         for byte in str.bytes() {
+            if self.handle_escape(byte) {
+                continue;
+            }
             match byte {
                 // check for printable ASCII
                 0x20..=0x7e | b'\n' | b'\r'
@@ -162,24 +697,256 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_pos: 0,
-        color_code: ColorCode::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref WRITER: Mutex<Writer> =
+        Mutex::new(Writer::new(unsafe { &mut *(0xb8000 as *mut Buffer) }));
+}
+
+/// Passes everything written to the VGA writer on to the log ring buffer as well.
+struct Logged<'a>(&'a mut Writer);
+
+impl fmt::Write for Logged<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::log::record(s);
+        self.0.write_string(s);
+        Ok(())
+    }
+}
+
+/// Set when everything printed to the screen is also written to the serial port.
+static SERIAL_MIRROR: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables mirroring the output of `print!` to the serial port, see the
+/// `serial_console` boot option.
+pub fn set_serial_mirror(enabled: bool) {
+    SERIAL_MIRROR.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the output of `print!` is mirrored to the serial port.
+pub fn serial_mirror() -> bool {
+    SERIAL_MIRROR.load(Ordering::Relaxed)
 }
 
 /// Prints a formatted string to the VGA text buffer using the global `WRITER`.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    use crate::interrupts;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        Logged(&mut writer).write_fmt(args).unwrap();
+        writer.flush();
+    });
+    if SERIAL_MIRROR.load(Ordering::Relaxed) {
+        crate::serial::_print(args);
+    }
+}
+
+/// Prints a formatted string to the VGA text buffer in `font` on `background`, also if `WRITER`
+/// is locked, e.g. by the code that panicked. The lock is then broken.
+///
+/// Only meant for panics, as the lock holder never gets to continue. The text is not recorded
+/// in the log ring buffer, whose lock may be held as well.
+pub fn print_forced(args: fmt::Arguments, font: Color, background: Color) {
+    use crate::interrupts;
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let mut writer = match WRITER.try_lock() {
+            Some(writer) => writer,
+            None => {
+                unsafe { WRITER.force_unlock() };
+                WRITER.lock()
+            }
+        };
+        writer.set_color(font, background);
+        let _ = writer.write_fmt(args);
+        writer.flush();
     });
 }
 
+/// Writes formatted text to a fixed position of the screen, see `Writer::write_str_at`.
+struct PositionedWriter<'a> {
+    writer: &'a mut Writer,
+    row: usize,
+    col: usize,
+}
+
+impl fmt::Write for PositionedWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let color = self.writer.color_code;
+        self.writer.write_str_at(self.row, self.col, s, color);
+        self.col += s.len();
+        Ok(())
+    }
+}
+
+/// Prints a formatted string at `row` and `col` of the screen in the current color, without
+/// moving the write position of the global `WRITER` or scrolling. Useful for status bars.
+pub fn print_at(row: usize, col: usize, args: fmt::Arguments) {
+    use crate::interrupts;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        PositionedWriter {
+            writer: &mut writer,
+            row,
+            col,
+        }
+        .write_fmt(args)
+        .unwrap();
+        writer.flush();
+    });
+}
+
+/// Clears the VGA text buffer using the global `WRITER`.
+#[doc(hidden)]
+pub fn _clear() {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.flush();
+    });
+}
+
+/// Limits the memory used by the scrollback history to `bytes`, rounded down to whole rows.
+/// The oldest rows are dropped when the limit is reached. Defaults to (and is capped at) the
+/// size of the history buffer of 200 rows.
+pub fn set_scrollback_limit(bytes: usize) {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        // the view must not point at rows that are dropped
+        writer.snap_to_bottom();
+        writer.flush();
+        HISTORY.lock().set_limit(bytes / mem::size_of::<Row>());
+    });
+}
+
+/// Returns the maximum number of bytes the scrollback history may use.
+pub fn scrollback_limit() -> usize {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| HISTORY.lock().limit * mem::size_of::<Row>())
+}
+
+/// Returns the number of bytes used by the rows currently stored in the scrollback history.
+pub fn scrollback_usage() -> usize {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| HISTORY.lock().count * mem::size_of::<Row>())
+}
+
+/// Returns the screen color selected by the `fgcolor=<name>` and `bgcolor=<name>` boot options
+/// `args`, e.g. "fgcolor=white bgcolor=blue". Missing options and unknown color names keep white
+/// on black.
+pub fn cmdline_color(args: &BootArgs) -> ColorCode {
+    let mut color = DEFAULT_COLOR;
+    if let Some(font) = args.get("fgcolor").and_then(Color::from_name) {
+        color = color.with_font(font);
+    }
+    if let Some(background) = args.get("bgcolor").and_then(Color::from_name) {
+        color = color.with_background(background);
+    }
+    color
+}
+
+/// Sets the color of the global `WRITER` and clears the screen to it. Called once at boot
+/// before anything is printed.
+pub fn init_color(color: ColorCode) {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = color;
+        writer.clear_screen();
+        writer.flush();
+    });
+}
+
+/// This macro clears the VGA text buffer.
+#[macro_export]
+macro_rules! clear {
+    () => {
+        $crate::vga_buffer::_clear()
+    };
+}
+
+/// Sets the color of the global `WRITER`.
+#[doc(hidden)]
+pub fn _set_color(font: Color, background: Color) {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| {
+        WRITER.lock().set_color(font, background);
+    });
+}
+
+/// This macro sets the font and background color used by subsequent prints.
+#[macro_export]
+macro_rules! set_color {
+    ($font:expr, $background:expr) => {
+        $crate::vga_buffer::_set_color($font, $background)
+    };
+}
+
+/// Runs `f` with the color of the global `WRITER` replaced by `change(current color)` and
+/// restores the previous color afterwards, so nested calls restore the color of the enclosing
+/// call. The writer is not locked while `f` runs.
+fn with_changed_color<R>(change: impl FnOnce(ColorCode) -> ColorCode, f: impl FnOnce() -> R) -> R {
+    use crate::interrupts;
+
+    let previous = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let previous = writer.color_code;
+        writer.color_code = change(previous);
+        previous
+    });
+    let result = f();
+    interrupts::without_interrupts(|| WRITER.lock().color_code = previous);
+    result
+}
+
+/// Runs `f` with `color` as the color of the global `WRITER` and restores the previous color
+/// afterwards.
+pub fn with_color<R>(color: ColorCode, f: impl FnOnce() -> R) -> R {
+    with_changed_color(|_| color, f)
+}
+
+/// Runs `f` with the font color of the global `WRITER` set to `font`.
+#[doc(hidden)]
+pub fn _with_fg<R>(font: Color, f: impl FnOnce() -> R) -> R {
+    with_changed_color(|color| color.with_font(font), f)
+}
+
+/// Runs `f` with the background color of the global `WRITER` set to `background`.
+#[doc(hidden)]
+pub fn _with_bg<R>(background: Color, f: impl FnOnce() -> R) -> R {
+    with_changed_color(|color| color.with_background(background), f)
+}
+
+/// This macro runs a block with the given font color and restores the previous color after it.
+/// The background color is kept.
+#[macro_export]
+macro_rules! with_fg {
+    ($font:expr, $body:block) => {
+        $crate::vga_buffer::_with_fg($font, || $body)
+    };
+}
+
+/// This macro runs a block with the given background color and restores the previous color
+/// after it. The font color is kept.
+#[macro_export]
+macro_rules! with_bg {
+    ($background:expr, $body:block) => {
+        $crate::vga_buffer::_with_bg($background, || $body)
+    };
+}
+
 /// This macro is used to print to the VGA text buffer.
 #[macro_export]
 macro_rules! print {
@@ -195,6 +962,16 @@ macro_rules! println {
 
 // -- UNIT TESTS -- //
 
+/// Test the color code constructors and presets.
+#[test_case]
+fn vga_color_code_presets() {
+    assert_eq!(ColorCode::new(Color::Yellow, Color::Blue).0, 0x1e);
+    assert_eq!(ColorCode::on_black(Color::LightGray).0, 0x07);
+    assert_eq!(ColorCode::error().0, 0x4f);
+    assert_eq!(ColorCode::success().0, 0x02);
+    assert_eq!(ColorCode::warning().0, 0x0e);
+}
+
 /// Test VGA text buffer print macro.
 #[test_case]
 fn vga_text_buffer_print() {
@@ -218,27 +995,267 @@ fn vga_text_buffer_many_print() {
 /// Test VGA text buffer functionality. Fails if content is not displayed correctly
 #[test_case]
 fn vga_text_buffer_functionality() {
+    use crate::interrupts;
     use crate::vga_buffer::WRITER;
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     let s = "Content";
     interrupts::without_interrupts(|| {
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_SIZE_Y - 2][i].read();
+            let screen_char = writer.shadow[BUFFER_SIZE_Y - 2][i];
             assert_eq!(char::from(screen_char.ascii), c);
         }
     });
 }
 
+/// Test that the hardware cursor follows the written text.
+#[test_case]
+fn vga_text_buffer_cursor() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\ncursor").expect("write failed");
+
+        let high = usize::from(crtc_read(CRTC_CURSOR_HIGH));
+        let low = usize::from(crtc_read(CRTC_CURSOR_LOW));
+        let pos = (high << 8) | low;
+        assert_eq!(pos, (BUFFER_SIZE_Y - 1) * BUFFER_SIZE_X + writer.column_pos);
+        assert_eq!(writer.column_pos, "cursor".len());
+    });
+}
+
+/// Test writing and reading back an attribute controller register.
+#[test_case]
+fn vga_attribute_controller_roundtrip() {
+    let mode = attr_read(ATTR_MODE_CONTROL);
+
+    set_blink(false);
+    assert_eq!(attr_read(ATTR_MODE_CONTROL), mode & !0x08);
+    set_blink(true);
+    assert_eq!(attr_read(ATTR_MODE_CONTROL), mode | 0x08);
+
+    attr_write(ATTR_MODE_CONTROL, mode);
+    assert_eq!(attr_read(ATTR_MODE_CONTROL), mode);
+}
+
+/// Test that clearing the screen blanks every cell.
+#[test_case]
+fn vga_text_buffer_clear_screen() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for i in 0..BUFFER_SIZE_Y {
+            writeln!(writer, "line {}", i).expect("writeln failed");
+        }
+        writer.clear_screen();
+
+        for row in writer.shadow.iter() {
+            for cell in row.iter() {
+                assert_eq!(cell.ascii, b' ');
+            }
+        }
+        assert_eq!(writer.column_pos, 0);
+    });
+}
+
+/// Test that text is written in the color set at runtime.
+#[test_case]
+fn vga_text_buffer_set_color() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    let previous = interrupts::without_interrupts(|| WRITER.lock().color());
+    crate::set_color!(Color::Red, Color::Black);
+
+    let s = "red";
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        assert_eq!(writer.color(), ColorCode::new(Color::Red, Color::Black));
+        write!(writer, "\n{}", s).expect("write failed");
+        for i in 0..s.len() {
+            let screen_char = writer.shadow[BUFFER_SIZE_Y - 1][i];
+            assert_eq!(
+                screen_char.color_code,
+                ColorCode::new(Color::Red, Color::Black)
+            );
+        }
+        // the row cleared by the newline uses the new color as well
+        let blank = writer.shadow[BUFFER_SIZE_Y - 1][s.len()];
+        assert_eq!(blank.color_code, ColorCode::new(Color::Red, Color::Black));
+
+        writer.color_code = previous;
+    });
+}
+
+/// Test that the boot command line selects the initial color and the screen is cleared to it.
+#[test_case]
+fn vga_text_buffer_cmdline_color() {
+    use crate::boot::parse_cmdline;
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+
+    let color = cmdline_color(&parse_cmdline(r#"quiet bgcolor="blue" fgcolor=White"#));
+    assert_eq!(color, ColorCode::new(Color::White, Color::Blue));
+    // the last occurrence counts, also if it names no color
+    let color = cmdline_color(&parse_cmdline("fgcolor=white fgcolor=nocolor"));
+    assert_eq!(color, DEFAULT_COLOR);
+    assert_eq!(cmdline_color(&parse_cmdline("")), DEFAULT_COLOR);
+
+    let previous = interrupts::without_interrupts(|| WRITER.lock().color());
+    init_color(color);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        assert_eq!(writer.color(), color);
+        for row in 0..BUFFER_SIZE_Y {
+            for col in 0..BUFFER_SIZE_X {
+                assert_eq!(writer.read_at(row, col), (b' ', color));
+            }
+        }
+        writer.color_code = previous;
+    });
+}
+
+/// Test that nested `with_fg!` and `with_bg!` blocks restore the color of the enclosing block.
+#[test_case]
+fn vga_text_buffer_nested_color_blocks() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+
+    let color = || interrupts::without_interrupts(|| WRITER.lock().color());
+    let outer = color();
+
+    crate::with_fg!(Color::Red, {
+        let red = outer.with_font(Color::Red);
+        assert_eq!(color(), red);
+        crate::with_fg!(Color::Green, {
+            crate::println!("nested color blocks");
+            assert_eq!(color(), outer.with_font(Color::Green));
+            crate::with_bg!(Color::Blue, {
+                assert_eq!(color(), ColorCode::new(Color::Green, Color::Blue));
+            });
+            assert_eq!(color(), outer.with_font(Color::Green));
+        });
+        assert_eq!(color(), red);
+    });
+    assert_eq!(color(), outer);
+}
+
+/// Test writing at an arbitrary position of the screen.
+#[test_case]
+fn vga_text_buffer_print_at() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+
+    let column_pos = interrupts::without_interrupts(|| WRITER.lock().column_pos);
+    print_at(3, 10, format_args!("{}", "HI"));
+
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!(writer.buffer.chars[3][10].read().ascii, b'H');
+        assert_eq!(writer.buffer.chars[3][11].read().ascii, b'I');
+        assert_eq!(writer.column_pos, column_pos);
+    });
+
+    // out of range positions are ignored
+    print_at(3, BUFFER_SIZE_X - 1, format_args!("clipped"));
+    print_at(BUFFER_SIZE_Y, 0, format_args!("ignored"));
+}
+
+/// Test reading back a cell written with `write_at`.
+#[test_case]
+fn vga_text_buffer_read_at() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color = ColorCode::new(Color::Cyan, Color::Blue);
+        writer.write_at(5, BUFFER_SIZE_X - 1, b'R', color);
+        assert_eq!(writer.read_at(5, BUFFER_SIZE_X - 1), (b'R', color));
+    });
+}
+
+/// Test scrolling back into the history and returning to the live screen.
+#[test_case]
+fn vga_text_buffer_scrollback() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    fn row_starts_with(writer: &Writer, row: usize, s: &str) -> bool {
+        s.bytes()
+            .enumerate()
+            .all(|(col, byte)| writer.shadow[row][col].ascii == byte)
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for i in 0..50 {
+            writeln!(writer, "scroll {}", i).expect("writeln failed");
+        }
+        // the last line is empty, so the top row shows line 26
+        assert!(row_starts_with(&writer, 0, "scroll 26 "));
+
+        writer.scroll_up(25);
+        assert!(row_starts_with(&writer, 0, "scroll 1 "));
+        assert!(row_starts_with(&writer, BUFFER_SIZE_Y - 1, "scroll 25 "));
+
+        writer.scroll_down(25);
+        assert!(row_starts_with(&writer, 0, "scroll 26 "));
+
+        // writing returns to the live screen
+        writer.scroll_up(10);
+        write!(writer, "x").expect("write failed");
+        assert!(row_starts_with(&writer, 0, "scroll 26 "));
+        assert_eq!(writer.scroll_offset, 0);
+    });
+}
+
+/// Test that the scrollback history drops its oldest rows to stay within its byte budget.
+#[test_case]
+fn vga_text_buffer_scrollback_limit() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    let row_size = mem::size_of::<Row>();
+    let previous = scrollback_limit();
+    // room for three and a half rows
+    set_scrollback_limit(3 * row_size + row_size / 2);
+    assert_eq!(scrollback_limit(), 3 * row_size);
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for i in 0..30 {
+            writeln!(writer, "evict {}", i).expect("writeln failed");
+        }
+        // lines 0 to 5 scrolled off the screen, only the last three of them are kept
+        writer.scroll_up(HISTORY_LINES);
+        assert_eq!(writer.scroll_offset, 3);
+        let top: [u8; 8] = core::array::from_fn(|col| writer.shadow[0][col].ascii);
+        assert_eq!(&top, b"evict 3 ");
+        writer.snap_to_bottom();
+    });
+    assert_eq!(scrollback_usage(), 3 * row_size);
+
+    set_scrollback_limit(previous);
+}
+
 /// Test VGA buffer backspace functionality
 #[test_case]
 fn vga_text_buffer_backspace() {
+    use crate::interrupts;
     use crate::vga_buffer::WRITER;
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     let s = "Content";
     interrupts::without_interrupts(|| {
@@ -249,8 +1266,98 @@ fn vga_text_buffer_backspace() {
         }
         for i in 0..s.len() {
             // check that the i-th character is ' '
-            let screen_char = writer.buffer.chars[BUFFER_SIZE_Y - 1][i].read();
+            let screen_char = writer.shadow[BUFFER_SIZE_Y - 1][i];
             assert_eq!(char::from(screen_char.ascii), ' ');
         }
     });
 }
+
+/// Test that ANSI color sequences change the color of the written cells and are not printed.
+#[test_case]
+fn vga_ansi_color_sequences() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let previous = writer.color();
+        write!(
+            writer,
+            "\n\x1b[32mgreen\x1b[0mx\x1b[91;44my\x1b[5;1H\x1b[?zq\x1bXz"
+        )
+        .unwrap();
+
+        let row = writer.read_row(BUFFER_SIZE_Y - 1);
+        let green = ColorCode::new(Color::Green, Color::Black);
+        for (i, c) in "green".bytes().enumerate() {
+            assert_eq!(row[i].ascii, c);
+            assert_eq!(row[i].color_code, green);
+        }
+        assert_eq!(row[5].ascii, b'x');
+        assert_eq!(row[5].color_code, DEFAULT_COLOR);
+        assert_eq!(row[6].ascii, b'y');
+        assert_eq!(
+            row[6].color_code,
+            ColorCode::new(Color::LightRed, Color::Blue)
+        );
+        // the cursor movement, the malformed and the unsupported sequence are consumed
+        assert_eq!(row[7].ascii, b'q');
+        assert_eq!(row[8].ascii, b'z');
+        assert_eq!(row[9].ascii, b' ');
+
+        writer.color_code = previous;
+    });
+}
+
+/// Test that writes go to the off-screen buffer and only reach the VGA text buffer on a flush.
+#[test_case]
+fn vga_text_buffer_flush() {
+    use crate::interrupts;
+    use crate::vga_buffer::WRITER;
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        fn screen_row(writer: &Writer, row: usize) -> Row {
+            core::array::from_fn(|col| writer.buffer.chars[row][col].read())
+        }
+
+        let mut writer = WRITER.lock();
+        writer.flush();
+        let last = BUFFER_SIZE_Y - 1;
+        let before = screen_row(&writer, last);
+        write!(writer, "\nflushed").expect("write failed");
+        for (col, byte) in "flushed".bytes().enumerate() {
+            assert_eq!(writer.shadow[last][col].ascii, byte);
+        }
+        // the screen is unchanged until the flush
+        assert_eq!(screen_row(&writer, last), before);
+        assert_eq!(writer.shadow[last - 1], before);
+
+        writer.flush();
+        for row in 0..BUFFER_SIZE_Y {
+            assert_eq!(screen_row(&writer, row), writer.shadow[row]);
+        }
+    });
+}
+
+/// Test that a forced print gets through while `WRITER` is locked.
+#[test_case]
+fn vga_print_forced_while_locked() {
+    use crate::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let color_code = WRITER.lock().color_code;
+        // a holder that never unlocks, like code that panicked
+        core::mem::forget(WRITER.lock());
+        print_forced(format_args!("\nforced"), Color::White, Color::Red);
+
+        let mut writer = WRITER.try_lock().expect("the lock was not released");
+        let row = &writer.shadow[BUFFER_SIZE_Y - 1];
+        for (i, c) in "forced".chars().enumerate() {
+            assert_eq!(char::from(row[i].ascii), c);
+            assert_eq!(row[i].color_code, ColorCode::error());
+        }
+        writer.color_code = color_code;
+    });
+}
@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use alloc::{vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    acpi::madt::{self, InterruptSourceOverride, IoApic, MadtError},
+    heap, hlt_forever, memory,
+};
+use x86_64::{structures::paging::PhysFrame, PhysAddr};
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    unsafe { memory::init_test_memory(boot_info) };
+
+    // initialize heap
+    memory::with_test_memory(|mapper, frame_allocator| heap::init(mapper, frame_allocator))
+        .expect("heap initialization failed.");
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// Builds a MADT with the given interrupt controller structures and a valid checksum.
+fn build_madt(entries: &[&[u8]]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend_from_slice(b"APIC");
+    table.extend_from_slice(&[0; 4]); // length, set below
+    table.push(3); // revision
+    table.push(0); // checksum, set below
+    table.extend_from_slice(b"TRUST ");
+    table.extend_from_slice(b"TESTMADT");
+    table.extend_from_slice(&[0; 12]); // OEM revision, creator id and revision
+    table.extend_from_slice(&0xfee0_0000u32.to_le_bytes()); // local APIC address
+    table.extend_from_slice(&1u32.to_le_bytes()); // flags: legacy PICs installed
+    for entry in entries {
+        table.extend_from_slice(entry);
+    }
+
+    let length = table.len() as u32;
+    table[4..8].copy_from_slice(&length.to_le_bytes());
+    let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    table[9] = 0u8.wrapping_sub(sum);
+    table
+}
+
+#[test_case]
+fn madt_parses_interrupt_controllers() {
+    let table = build_madt(&[
+        // local APICs: enabled, disabled and online capable
+        &[0, 8, 0, 0, 1, 0, 0, 0],
+        &[0, 8, 1, 2, 0, 0, 0, 0],
+        &[0, 8, 2, 4, 2, 0, 0, 0],
+        // IO APIC 5 at 0xfec00000 handling GSIs from 0
+        &[1, 12, 5, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0],
+        // IRQ 0 is connected to GSI 2
+        &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0],
+        // local APIC NMI, not collected
+        &[4, 6, 0xff, 0, 0, 1],
+    ]);
+
+    let info = madt::parse(&table).expect("parsing the MADT failed");
+    assert_eq!(info.local_apic_addr, 0xfee0_0000);
+    assert_eq!(info.cpus, vec![0, 4]);
+    assert_eq!(
+        info.ioapics,
+        vec![IoApic {
+            id: 5,
+            address: 0xfec0_0000,
+            gsi_base: 0,
+        }]
+    );
+    assert_eq!(
+        info.overrides,
+        vec![InterruptSourceOverride {
+            bus: 0,
+            source: 0,
+            gsi: 2,
+            flags: 0,
+        }]
+    );
+}
+
+#[test_case]
+fn madt_local_apic_address_override() {
+    let mut entry = vec![5, 12, 0, 0];
+    entry.extend_from_slice(&0x1_0000_0000u64.to_le_bytes());
+    let table = build_madt(&[&entry]);
+
+    let info = madt::parse(&table).expect("parsing the MADT failed");
+    assert_eq!(info.local_apic_addr, 0x1_0000_0000);
+}
+
+#[test_case]
+fn madt_rejects_malformed_tables() {
+    let mut table = build_madt(&[&[0, 8, 0, 0, 1, 0, 0, 0]]);
+    table[0] = b'X';
+    assert_eq!(madt::parse(&table), Err(MadtError::WrongSignature));
+
+    let mut table = build_madt(&[&[0, 8, 0, 0, 1, 0, 0, 0]]);
+    table[9] = table[9].wrapping_add(1);
+    assert_eq!(madt::parse(&table), Err(MadtError::InvalidChecksum));
+
+    // a structure of length 0 would never advance
+    let table = build_madt(&[&[0, 0, 0, 0]]);
+    assert_eq!(madt::parse(&table), Err(MadtError::Truncated));
+
+    // an IO APIC structure too short for its fields
+    let table = build_madt(&[&[1, 6, 5, 0, 0, 0]]);
+    assert_eq!(madt::parse(&table), Err(MadtError::Truncated));
+}
+
+#[test_case]
+fn rsdp_found_in_bios_area() {
+    // qemu's BIOS provides an ACPI 1.0 RSDP
+    let rsdp = trust::acpi::find_rsdp().expect("no RSDP found");
+    assert_ne!(rsdp.root_table_address(), 0);
+}
+
+#[test_case]
+fn fadt_registered() {
+    let rsdp = trust::acpi::find_rsdp().expect("no RSDP found");
+    memory::with_test_memory(|_, frame_allocator| {
+        let fadt = trust::acpi::init(frame_allocator).expect("no FADT found");
+        assert_eq!(trust::acpi::fadt(), Some(fadt));
+        // qemu's PIIX4 power management has its PM1a control register at port 0x604
+        assert_eq!(fadt.pm1a_control_block, 0x604);
+
+        // the tables stay where the firmware put them
+        let root = PhysFrame::containing_address(PhysAddr::new(rsdp.root_table_address()));
+        assert!(frame_allocator.is_pinned(root));
+    });
+}
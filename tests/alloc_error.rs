@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::{fmt::Write, panic::PanicInfo};
+use trust::{
+    exit_qemu, heap, memory, serial_print, serial_println, util::FixedString, QemuExitCode,
+};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+/// Size of the allocation that can never be served by the heap.
+const HUGE: usize = 1 << 30;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("alloc_error::huge_allocation...\t");
+    trust::init();
+
+    // initialize paging and the heap
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+
+    let huge: Vec<u8> = Vec::with_capacity(HUGE);
+    serial_println!("[no panic] {}", huge.capacity());
+    exit_qemu(QemuExitCode::Fail);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = FixedString::<256>::new();
+    let _ = write!(message, "{}", info);
+
+    // the panic must come from the allocation error handler
+    if message.contains("allocation error: Layout { size: 1073741824, align: 1") {
+        serial_println!("\r[ok] alloc_error::huge_allocation");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use trust::{
+    apic, counters, hlt_forever,
+    interrupts::{self, InterruptController},
+    memory, time,
+};
+use x86_64::{instructions::port::Port, VirtAddr};
+
+entry_point!(main);
+
+/// Result of `apic::init`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    ENABLED.store(
+        apic::init(&mut mapper, &mut frame_allocator),
+        Ordering::SeqCst,
+    );
+    // a second call finds the APIC enabled
+    assert_eq!(
+        apic::init(&mut mapper, &mut frame_allocator),
+        ENABLED.load(Ordering::SeqCst)
+    );
+
+    test_main();
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info);
+}
+
+/// TSC cycles `wait_ticks` waits for the ticks, several seconds at the usual TSC frequencies of
+/// a few GHz.
+const TIMEOUT_CYCLES: u64 = 10_000_000_000;
+
+/// Waits until the kernel ticks advanced by `count`. Panics after `TIMEOUT_CYCLES`.
+///
+/// The ticks may come from the timer under test, so the wait is bounded by the TSC instead, and
+/// spins as `hlt` would not return without an interrupt.
+fn wait_ticks(count: u64) {
+    let start = time::ticks();
+    let stopwatch = time::Stopwatch::start();
+    while time::ticks() - start < count {
+        if stopwatch.elapsed_cycles() > TIMEOUT_CYCLES {
+            panic!(
+                "{} of {} ticks within {} TSC cycles",
+                time::ticks() - start,
+                count,
+                TIMEOUT_CYCLES
+            );
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Test that qemu's local APIC is enabled and takes over from the PIC.
+#[test_case]
+fn apic_replaces_pic() {
+    assert!(apic::has_local_apic());
+    assert!(ENABLED.load(Ordering::SeqCst));
+    assert!(apic::is_software_enabled());
+    assert_eq!(interrupts::controller(), InterruptController::Apic);
+}
+
+/// Test that the legacy IRQs are still delivered by the PIC through the local APIC.
+#[test_case]
+fn apic_legacy_irqs_live() {
+    // the keyboard line is not masked
+    let mut master: Port<u8> = Port::new(0x21);
+    assert_eq!(unsafe { master.read() } & 0b10, 0);
+    // the PIT still advances the ticks
+    wait_ticks(3);
+}
+
+/// Test that an end of interrupt can be sent through the active controller.
+#[test_case]
+fn apic_end_of_interrupt() {
+    // no interrupt is in service, the local APIC ignores the write
+    interrupts::end_of_interrupt(0x20);
+    apic::send_eoi();
+}
+
+/// Test that the calibrated APIC timer drives the kernel ticks in place of the PIT.
+#[test_case]
+fn apic_timer_ticks() {
+    let per_10ms = apic::calibrate(10).expect("PIT channel 2 timed out");
+    assert_ne!(per_10ms, 0);
+
+    let mut master: Port<u8> = Port::new(0x21);
+    let before = counters::get("apic_timer");
+    assert!(apic::start_tick_timer());
+    // the PIT timer interrupt is masked, the ticks come from the APIC timer alone
+    assert_ne!(unsafe { master.read() } & 0b1, 0);
+    wait_ticks(3);
+    apic::stop_timer();
+    assert!(counters::get("apic_timer") - before >= 3);
+    assert_eq!(unsafe { master.read() } & 0b1, 0);
+}
+
+/// Test that sending the halt NMI completes and does not reach this core. Whether other cores
+/// receive it cannot be tested as the kernel does not start any.
+#[test_case]
+fn apic_halt_other_cores() {
+    let before = counters::get("nmi");
+    apic::halt_other_cores();
+    assert_eq!(counters::get("nmi"), before);
+}
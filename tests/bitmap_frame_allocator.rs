@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{
+    bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType},
+    entry_point, BootInfo,
+};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use trust::{
+    heap, hlt_forever,
+    memory::{self, BitmapFrameAllocator, BootInfoFrameAllocator},
+};
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+extern crate alloc;
+
+entry_point!(main);
+
+/// The frame allocator used during boot, taken over by one of the test cases.
+static BOOT_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // initialize heap, which holds the bitmap
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+    *BOOT_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+fn frame(number: u64) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(number * 4096))
+}
+
+/// Test allocating and freeing on a small synthetic memory map.
+#[test_case]
+fn bitmap_frame_allocator_synthetic_map() {
+    let mut memory_map = MemoryMap::new();
+    let mut add = |start, end, region_type| {
+        memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(start * 4096, end * 4096),
+            region_type,
+        })
+    };
+    add(0, 1, MemoryRegionType::FrameZero);
+    add(1, 3, MemoryRegionType::Usable);
+    add(3, 70, MemoryRegionType::Kernel);
+    add(70, 72, MemoryRegionType::Usable);
+
+    let mut allocator = unsafe { BitmapFrameAllocator::new(&memory_map) };
+    assert_eq!(allocator.free_frames(), 4);
+    assert!(allocator.is_used(frame(0)));
+    assert!(allocator.is_used(frame(3)));
+    assert!(!allocator.is_used(frame(71)));
+    // beyond the last usable frame
+    assert!(allocator.is_used(frame(72)));
+
+    // the first free frame is handed out first, across the word boundary at frame 64
+    for expected in [1, 2, 70, 71] {
+        assert_eq!(allocator.allocate_frame(), Some(frame(expected)));
+    }
+    assert_eq!(allocator.allocate_frame(), None);
+
+    unsafe { allocator.deallocate_frame(frame(70)) };
+    assert!(!allocator.is_used(frame(70)));
+    assert_eq!(allocator.free_frames(), 1);
+    assert_eq!(allocator.allocate_frame(), Some(frame(70)));
+}
+
+/// Test that the frames handed out during boot stay in use after taking over.
+#[test_case]
+fn bitmap_frame_allocator_takes_over_boot_allocator() {
+    let mut boot_allocator = BOOT_ALLOCATOR.lock().take().expect("boot allocator taken");
+    let used = boot_allocator
+        .allocate_frame()
+        .expect("no frames available");
+    let freed = boot_allocator
+        .allocate_frame()
+        .expect("no frames available");
+    unsafe { boot_allocator.deallocate_frame(freed) };
+
+    let mut allocator = BitmapFrameAllocator::from_boot_allocator(boot_allocator);
+    assert!(allocator.is_used(used));
+    assert!(!allocator.is_used(freed));
+
+    // the heap frames and `used` are never handed out again
+    let free = allocator.free_frames();
+    for _ in 0..free {
+        let frame = allocator
+            .allocate_frame()
+            .expect("free frame count is wrong");
+        assert_ne!(frame, used);
+    }
+    assert_eq!(allocator.allocate_frame(), None);
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{panic::PanicInfo, time::Duration};
+use trust::{
+    counters, heap, hlt_forever,
+    memory::{self, BootInfoFrameAllocator},
+    ps2,
+    task::keyboard,
+};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // initialize heap
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+
+    // the scancode queue is needed before any executor runs
+    keyboard::init_queue();
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// Makes the PS/2 controller deliver `scancode` as if it was sent by the keyboard and waits
+/// for the keyboard interrupt handler to receive it.
+fn inject_scancode(scancode: u8) {
+    let received = counters::get("keyboard");
+    // "write keyboard output buffer"
+    ps2::command(0xd2).expect("PS/2 controller not ready");
+    ps2::write_data(scancode).expect("PS/2 controller not ready");
+    while counters::get("keyboard") == received {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn boot_menu_window_times_out() {
+    assert!(!keyboard::escape_pressed(Duration::from_millis(200)));
+}
+
+#[test_case]
+fn boot_menu_entered_on_escape() {
+    // 'a' press and release, then ESC press and release
+    for scancode in [0x1e, 0x9e, 0x01, 0x81] {
+        inject_scancode(scancode);
+    }
+    assert!(keyboard::escape_pressed(Duration::from_millis(500)));
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use core::{fmt::Write, panic::PanicInfo};
+use trust::{exit_qemu, serial_print, serial_println, time, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("boot_order::clock_before_interrupts...\t");
+    // the clock requires the timer interrupt, which is only enabled by `trust::init`
+    time::init();
+    serial_println!("[no panic]");
+    exit_qemu(QemuExitCode::Fail);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
+
+/// Collects formatted output into a fixed buffer, dropping what does not fit.
+struct Buffer {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len < self.bytes.len() {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Buffer {
+        bytes: [0; 256],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+    let message = core::str::from_utf8(&message.bytes[..message.len]).unwrap_or("");
+
+    if message.contains("boot order violated: init stage Interrupts is required") {
+        serial_println!("\r[ok] boot_order::clock_before_interrupts");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+use core::{arch::asm, fmt::Write, panic::PanicInfo};
+use trust::{exit_qemu, serial_print, serial_println, util::FixedString, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("double_fault::corrupted_stack...\t");
+
+    // the kernel's own IDT and GDT, including the double fault IST stack
+    trust::init();
+
+    // point the stack to unmapped memory. The push page faults and the page fault cannot be
+    // delivered on that stack either, so the CPU raises a double fault. Without a working IST
+    // entry this would be a triple fault and QEMU would reboot.
+    unsafe {
+        asm!("mov rsp, {}", "push rax", in(reg) 0x_7777_0000_0000u64, options(noreturn));
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // the kernel's double fault handler panics
+    let mut message = FixedString::<256>::new();
+    let _ = write!(message, "{}", info);
+
+    if message.contains("CPU EXCEPTION: DOUBLE FAULT") {
+        serial_println!("\r[ok] double_fault::corrupted_stack");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use trust::{exit_qemu, ktest_eventually, serial_print, serial_println, time, QemuExitCode};
+
+const TIMEOUT_TICKS: u64 = 5;
+
+/// Tick count at which the wait started.
+static START: AtomicU64 = AtomicU64::new(0);
+/// A flag that is never set.
+static READY: AtomicBool = AtomicBool::new(false);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("eventually_timeout::never_true...\t");
+    trust::init();
+
+    START.store(time::ticks(), Ordering::SeqCst);
+    ktest_eventually!(READY.load(Ordering::SeqCst), TIMEOUT_TICKS);
+    serial_println!("[no panic]");
+    exit_qemu(QemuExitCode::Fail);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // the panic must come from the timeout and not from somewhere else
+    if time::ticks() - START.load(Ordering::SeqCst) < TIMEOUT_TICKS {
+        serial_println!("[failed]\n");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    serial_println!("\r[ok] eventually_timeout::never_true");
+    exit_qemu(QemuExitCode::Success);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
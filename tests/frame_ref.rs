@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    heap, hlt_forever,
+    memory::{self, frame_ref},
+};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    unsafe { memory::init_test_memory(boot_info) };
+
+    // initialize heap, which holds the reference counts
+    memory::with_test_memory(|mapper, frame_allocator| heap::init(mapper, frame_allocator))
+        .expect("heap initialization failed.");
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// A FrameDeallocator that remembers the frames passed to it.
+#[derive(Default)]
+struct RecordingDeallocator {
+    freed: Option<PhysFrame>,
+}
+
+impl FrameDeallocator<Size4KiB> for RecordingDeallocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.freed = Some(frame);
+    }
+}
+
+#[test_case]
+fn shared_frame_freed_after_last_unmap() {
+    memory::with_test_memory(|mapper, frame_allocator| {
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        let first = Page::containing_address(VirtAddr::new(0x_5555_2000_0000));
+        let second = first + 1;
+        let flags = PageTableFlags::WRITABLE;
+
+        let count = unsafe { memory::map_shared(first, frame, flags, mapper, frame_allocator) }
+            .expect("mapping the first page failed");
+        assert_eq!(count, 1);
+        let count = unsafe { memory::map_shared(second, frame, flags, mapper, frame_allocator) }
+            .expect("mapping the second page failed");
+        assert_eq!(count, 2);
+        assert_eq!(mapper.translate_page(second).ok(), Some(frame));
+
+        let mut deallocator = RecordingDeallocator::default();
+        let count = unsafe { memory::unmap_shared(first, mapper, &mut deallocator) }
+            .expect("unmapping the first page failed");
+        assert_eq!(count, 1);
+        assert_eq!(deallocator.freed, None);
+
+        let count = unsafe { memory::unmap_shared(second, mapper, &mut deallocator) }
+            .expect("unmapping the second page failed");
+        assert_eq!(count, 0);
+        assert_eq!(frame_ref::count(frame), 0);
+        assert_eq!(deallocator.freed, Some(frame));
+    });
+}
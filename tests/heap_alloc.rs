@@ -73,3 +73,17 @@ fn prolonged_use() {
     }
     assert_eq!(*prolonged_lifetime, 123);
 }
+
+#[test_case]
+fn free_blocks_match_stats() {
+    let blocks = heap::free_blocks();
+    let stats = heap::stats();
+    assert_eq!(blocks.len(), stats.node_count);
+    assert_eq!(
+        blocks.iter().map(|(_, size)| size).sum::<usize>(),
+        stats.free_bytes
+    );
+    for (start, size) in blocks {
+        assert!(start >= heap::HEAP_START && start + size <= heap::HEAP_START + heap::size());
+    }
+}
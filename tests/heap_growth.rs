@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    counters,
+    heap::{self, HeapPolicy},
+    hlt_forever,
+    memory::{self, BootInfoFrameAllocator},
+};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+/// A small heap that may grow to eight times its initial size.
+const POLICY: HeapPolicy = HeapPolicy {
+    initial_size: 16 * 1024,
+    max_size: 128 * 1024,
+    growth_chunk: 16 * 1024,
+};
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // initialize heap
+    heap::set_policy(POLICY);
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+    heap::enable_growth(mapper, frame_allocator);
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// Test that filling the heap grows it and the allocation succeeds afterwards.
+#[test_case]
+fn full_heap_grows() {
+    assert_eq!(heap::size(), POLICY.initial_size);
+
+    // fill the initial heap, the last blocks need a larger heap
+    let mut blocks = Vec::with_capacity(24);
+    for i in 0..24 {
+        blocks.push(Box::new([i as u8; 1024]));
+    }
+    assert!(heap::size() > POLICY.initial_size);
+    assert!(counters::get("heap_grow") > 0);
+    assert!(blocks
+        .iter()
+        .enumerate()
+        .all(|(i, block)| block[0] == i as u8));
+
+    let large = Vec::<u8>::with_capacity(12 * 1024);
+    assert!(large.capacity() >= 12 * 1024);
+    assert!(heap::size() <= POLICY.max_size);
+}
+
+/// Test that an allocation larger than a growth chunk grows the heap by several chunks at once.
+#[test_case]
+fn large_allocation_grows_heap() {
+    let size = heap::size();
+    let large = Vec::<u8>::with_capacity(3 * POLICY.growth_chunk);
+    assert!(large.capacity() >= 3 * POLICY.growth_chunk);
+    // whole chunks were added
+    assert!(heap::size() > size);
+    assert_eq!((heap::size() - size) % POLICY.growth_chunk, 0);
+    assert!(heap::size() - size <= 4 * POLICY.growth_chunk);
+}
+
+/// Test that an allocation that cannot fit below the maximum size fails without growing.
+#[test_case]
+fn oversized_allocation_does_not_grow() {
+    let size = heap::size();
+    let layout = alloc::alloc::Layout::from_size_align(POLICY.max_size, 8).unwrap();
+    assert!(unsafe { alloc::alloc::alloc(layout) }.is_null());
+    assert_eq!(heap::size(), size);
+}
+
+/// Test that allocations fail once the heap cannot grow beyond its maximum size.
+#[test_case]
+fn heap_growth_respects_max_size() {
+    let layout = alloc::alloc::Layout::from_size_align(1024, 8).unwrap();
+    // the blocks are leaked
+    while !unsafe { alloc::alloc::alloc(layout) }.is_null() {}
+    assert_eq!(heap::size(), POLICY.max_size);
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use alloc::{boxed::Box, format};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    heap::{self, HeapInitError},
+    hlt_forever,
+    memory::{self, BootInfoFrameAllocator, EmptyFrameAllocator},
+};
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging, but leave the heap to the test cases
+    unsafe { memory::init_test_memory(boot_info) };
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// A FrameAllocator that hands out at most `remaining` frames, simulating a machine with little
+/// memory.
+struct LimitedFrameAllocator<'a> {
+    inner: &'a mut BootInfoFrameAllocator,
+    remaining: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for LimitedFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        self.inner.allocate_frame()
+    }
+}
+
+#[test_case]
+fn no_memory_fails_clearly() {
+    memory::with_test_memory(|mapper, _| {
+        let err = heap::init(mapper, &mut EmptyFrameAllocator).expect_err("heap initialized");
+        assert!(matches!(
+            err,
+            HeapInitError::InsufficientMemory {
+                needed: heap::MIN_HEAP_SIZE,
+                available: 0
+            }
+        ));
+    });
+}
+
+#[test_case]
+fn low_memory_shrinks_heap() {
+    memory::with_test_memory(|mapper, frame_allocator| {
+        // enough for the page tables and about half of the heap
+        let mut limited = LimitedFrameAllocator {
+            inner: frame_allocator,
+            remaining: 16,
+        };
+        let heap_size = heap::init(mapper, &mut limited).expect("heap initialization failed");
+        assert!(heap_size >= heap::MIN_HEAP_SIZE);
+        assert!(heap_size < heap::HEAP_SIZE);
+
+        // the shrunk heap is usable
+        let value = Box::new(42);
+        assert_eq!(*value, 42);
+
+        let err = HeapInitError::InsufficientMemory {
+            needed: heap::MIN_HEAP_SIZE,
+            available: 0,
+        };
+        assert_eq!(
+            format!("{}", err),
+            format!(
+                "insufficient memory for heap (need {} bytes, have 0 bytes)",
+                heap::MIN_HEAP_SIZE
+            )
+        );
+    });
+}
@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use alloc::alloc::{alloc, Layout};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    heap::{self, HeapGrowError, HeapPolicy},
+    hlt_forever, memory,
+};
+
+extern crate alloc;
+
+entry_point!(main);
+
+/// A deliberately small heap policy.
+const POLICY: HeapPolicy = HeapPolicy {
+    initial_size: 16 * 1024,
+    max_size: 32 * 1024,
+    growth_chunk: 8 * 1024,
+};
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    unsafe { memory::init_test_memory(boot_info) };
+
+    // initialize heap
+    heap::set_policy(POLICY);
+    memory::with_test_memory(|mapper, frame_allocator| heap::init(mapper, frame_allocator))
+        .expect("heap initialization failed.");
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// Allocates 1 KiB blocks until the heap is exhausted and returns the number of blocks. The
+/// blocks are leaked.
+fn exhaust() -> usize {
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let mut blocks = 0;
+    while !unsafe { alloc(layout) }.is_null() {
+        blocks += 1;
+    }
+    blocks
+}
+
+#[test_case]
+fn heap_growth_stops_at_max_size() {
+    memory::with_test_memory(|mapper, frame_allocator| {
+        assert_eq!(heap::size(), POLICY.initial_size);
+        let initial_blocks = exhaust();
+        assert!(initial_blocks > 0);
+
+        // grow in chunks until the cap
+        assert_eq!(heap::grow(mapper, frame_allocator).ok(), Some(24 * 1024));
+        assert_eq!(heap::grow(mapper, frame_allocator).ok(), Some(32 * 1024));
+        assert!(matches!(
+            heap::grow(mapper, frame_allocator),
+            Err(HeapGrowError::LimitReached)
+        ));
+        assert_eq!(heap::size(), POLICY.max_size);
+
+        // the grown part is used and then allocation fails at the cap
+        let grown_blocks = exhaust();
+        assert!(grown_blocks > 0);
+        assert!((initial_blocks + grown_blocks) * 1024 <= POLICY.max_size);
+        assert!(matches!(
+            heap::grow(mapper, frame_allocator),
+            Err(HeapGrowError::LimitReached)
+        ));
+    });
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use trust::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+/// Number of times the panic handler was entered.
+static ENTRIES: AtomicU32 = AtomicU32::new(0);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("nested_panic::nested_panic_halts...\t");
+    panic!("first panic");
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    let entries = ENTRIES.fetch_add(1, Ordering::SeqCst) + 1;
+
+    if trust::begin_panic() {
+        if entries > 1 {
+            serial_println!("[failed]\n\nnested panic was not detected");
+            exit_qemu(QemuExitCode::Fail);
+        }
+        // simulate a fault inside the panic handler
+        panic!("nested panic");
+    }
+
+    // the nested panic takes the halting path exactly once
+    if entries == 2 {
+        serial_println!("\r[ok] nested_panic::nested_panic_halts");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nunexpected panic handler entry {}", entries);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    trust::halt_nested_panic();
+}
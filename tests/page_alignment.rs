@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+use core::{fmt::Write, panic::PanicInfo};
+use trust::{
+    assert_page_aligned, exit_qemu, serial_print, serial_println, util::FixedString, QemuExitCode,
+};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_alignment::misaligned_address...\t");
+    assert_page_aligned!(0x4444_4444_0010);
+    serial_println!("[no panic]");
+    exit_qemu(QemuExitCode::Fail);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = FixedString::<256>::new();
+    let _ = write!(message, "{}", info);
+
+    // the message names the offending address
+    if message.contains("address 0x444444440010 is not 4 KiB page aligned (offset 0x10)") {
+        serial_println!("\r[ok] page_alignment::misaligned_address");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
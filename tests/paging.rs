@@ -0,0 +1,374 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{bootinfo::MemoryRegionType, entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use trust::{
+    hlt_forever,
+    memory::{self, BootInfoFrameAllocator},
+};
+use x86_64::{
+    structures::paging::{
+        mapper::{Translate, TranslateResult},
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+entry_point!(main);
+
+/// Boot information passed by the bootloader.
+static BOOT_INFO: Mutex<Option<&'static BootInfo>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    *BOOT_INFO.lock() = Some(boot_info);
+
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    unsafe { memory::init_test_memory(boot_info) };
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+#[test_case]
+fn map_frames_to_non_identity_pages() {
+    // the VGA memory area is never handed out by the frame allocator
+    let first_frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    let frames = PhysFrame::range(first_frame, first_frame + 4);
+    let first_page = Page::containing_address(VirtAddr::new(0x_5555_0000_0000));
+    let pages = Page::range(first_page, first_page + 4);
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        unsafe {
+            memory::map_frames_to_pages(
+                frames,
+                pages,
+                PageTableFlags::WRITABLE,
+                mapper,
+                frame_allocator,
+            )
+        }
+        .expect("mapping the frame range failed");
+
+        for (frame, page) in frames.zip(pages) {
+            assert_eq!(mapper.translate_page(page).ok(), Some(frame));
+        }
+    });
+}
+
+#[test_case]
+fn map_frame_above_4gib() {
+    const FOUR_GIB: u64 = 4 << 30;
+
+    let boot_info = BOOT_INFO.lock().expect("boot info not initialized");
+    let high_region = boot_info
+        .memory_map
+        .iter()
+        .find(|r| r.region_type == MemoryRegionType::Usable && r.range.end_addr() > FOUR_GIB);
+    // the test-args give qemu 5 GiB of memory
+    let region = high_region.expect("no usable memory above 4GiB, is qemu started with `-m 5G`?");
+
+    let phys = PhysAddr::new(region.range.start_addr().max(FOUR_GIB));
+    let frame = PhysFrame::containing_address(phys);
+    let page = Page::containing_address(VirtAddr::new(0x_5555_1000_0000));
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        unsafe {
+            mapper.map_to(
+                page,
+                frame,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                frame_allocator,
+            )
+        }
+        .expect("mapping a frame above 4GiB failed")
+        .flush();
+        assert_eq!(mapper.translate_page(page).ok(), Some(frame));
+    });
+
+    // the write through the new mapping must be visible through the physical memory mapping,
+    // which would not be the case if the address got truncated to 32 bits anywhere
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let phys_ptr: *const u64 = (phys_mem_offset + frame.start_address().as_u64()).as_ptr();
+    unsafe {
+        page_ptr.write_volatile(0x_dead_beef_cafe_babe);
+        assert_eq!(phys_ptr.read_volatile(), 0x_dead_beef_cafe_babe);
+    }
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    memory::with_test_memory(|_mapper, frame_allocator| {
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+    });
+}
+
+#[test_case]
+fn pinned_frame_is_never_reused() {
+    memory::with_test_memory(|_mapper, frame_allocator| {
+        let vga_frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+        assert!(frame_allocator.is_pinned(vga_frame));
+
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        frame_allocator.pin(frame);
+        assert!(frame_allocator.is_pinned(frame));
+
+        // freeing the pinned frame is refused
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        for _ in 0..16 {
+            let next = frame_allocator
+                .allocate_frame()
+                .expect("no frames available");
+            assert_ne!(next, frame);
+        }
+    });
+}
+
+#[test_case]
+fn id_map_uncached_sets_cache_disable_bits() {
+    // the local APIC registers, which are memory mapped I/O
+    let addr = PhysAddr::new(0xfee0_0000);
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        unsafe { memory::id_map_uncached(addr, mapper, frame_allocator) }
+            .expect("identity mapping failed");
+
+        let virt = VirtAddr::new(addr.as_u64());
+        assert_eq!(mapper.translate_addr(virt), Some(addr));
+        match mapper.translate(virt) {
+            TranslateResult::Mapped { flags, .. } => assert!(flags.contains(
+                PageTableFlags::PRESENT | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+            )),
+            _ => panic!("uncached identity mapping is not mapped"),
+        }
+    });
+}
+
+#[test_case]
+fn write_to_read_only_page_is_described() {
+    use core::fmt::Write;
+    use trust::{idt, util::FixedString};
+
+    let page = Page::containing_address(VirtAddr::new(0x_5555_2000_0000));
+    memory::with_test_memory(|mapper, frame_allocator| {
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT, frame_allocator) }
+            .expect("mapping the read-only page failed")
+            .flush();
+    });
+
+    let addr = page.start_address() + 16u64;
+    idt::set_fault_region(page.start_address(), page.size());
+    unsafe { idt::probe_write(addr.as_mut_ptr(), 42) };
+    idt::clear_fault_region();
+
+    let fault = idt::take_recovered_fault().expect("no page fault recorded");
+    assert_eq!(fault.address, addr);
+    let mut description = FixedString::<96>::new();
+    write!(description, "{}", fault.describe()).unwrap();
+    assert_eq!(
+        description.as_str(),
+        "write to read-only page at 0x555520000010 from kernel mode"
+    );
+}
+
+/// A frame allocator recording the frames it hands out and gets back.
+struct Recording<'a> {
+    inner: &'a mut BootInfoFrameAllocator,
+    allocated: [Option<PhysFrame>; 4],
+    deallocated: [Option<PhysFrame>; 4],
+}
+
+impl Recording<'_> {
+    fn record(frames: &mut [Option<PhysFrame>; 4], frame: PhysFrame) {
+        let slot = frames
+            .iter_mut()
+            .find(|f| f.is_none())
+            .expect("too many frames");
+        *slot = Some(frame);
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for Recording<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.inner.allocate_frame()?;
+        Self::record(&mut self.allocated, frame);
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for Recording<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        Self::record(&mut self.deallocated, frame);
+        self.inner.deallocate_frame(frame);
+    }
+}
+
+#[test_case]
+fn unmap_frees_empty_page_tables() {
+    // nothing else is mapped in the 512 GiB around this page
+    let page = Page::containing_address(VirtAddr::new(0x_6666_0000_0000));
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        let mut recording = Recording {
+            inner: frame_allocator,
+            allocated: [None; 4],
+            deallocated: [None; 4],
+        };
+
+        unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT, &mut recording) }
+            .expect("mapping the page failed")
+            .flush();
+        // the level 3, 2 and 1 tables were created for the page
+        assert!(recording.allocated[..3].iter().all(Option::is_some));
+        assert_eq!(recording.allocated[3], None);
+
+        let unmapped = unsafe { memory::unmap_and_free_tables(page, mapper, &mut recording) }
+            .expect("unmapping the page failed");
+        assert_eq!(unmapped, frame);
+        assert!(matches!(
+            mapper.translate(page.start_address()),
+            TranslateResult::NotMapped
+        ));
+
+        // the tables are freed bottom up
+        let mut tables = recording.allocated;
+        tables[..3].reverse();
+        assert_eq!(recording.deallocated, tables);
+
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    });
+}
+
+#[test_case]
+fn map_2mib_page_translates_whole_range() {
+    use x86_64::structures::paging::mapper::MappedFrame;
+
+    let virt = VirtAddr::new(0x_5555_4000_0000);
+    // the page is only read through translation, so mapping memory that is in use is fine
+    let phys = PhysAddr::new(0x20_0000);
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        unsafe {
+            memory::map_to_2mib(virt, phys, PageTableFlags::empty(), mapper, frame_allocator)
+        }
+        .expect("mapping the 2 MiB page failed");
+
+        for offset in [0, 0x1234, 0x10_0000, 0x1f_ffff] {
+            match mapper.translate(virt + offset) {
+                TranslateResult::Mapped {
+                    frame: MappedFrame::Size2MiB(frame),
+                    offset: frame_offset,
+                    flags,
+                } => {
+                    assert_eq!(frame.start_address(), phys);
+                    assert_eq!(frame_offset, offset);
+                    assert!(flags.contains(PageTableFlags::HUGE_PAGE));
+                }
+                other => panic!("unexpected translation {:?}", other),
+            }
+        }
+        // the next 2 MiB are not mapped
+        assert!(matches!(
+            mapper.translate(virt + 0x20_0000u64),
+            TranslateResult::NotMapped
+        ));
+
+        let frame = unsafe { memory::unmap_huge(virt, mapper) }.expect("unmapping failed");
+        assert_eq!(frame.start_address(), phys);
+        assert!(matches!(mapper.translate(virt), TranslateResult::NotMapped));
+
+        // both addresses must be 2 MiB aligned
+        assert!(matches!(
+            unsafe {
+                memory::map_to_2mib(
+                    virt + 0x1000u64,
+                    phys,
+                    PageTableFlags::empty(),
+                    mapper,
+                    frame_allocator,
+                )
+            },
+            Err(memory::HugePageError::Unaligned)
+        ));
+        assert!(matches!(
+            unsafe {
+                memory::map_to_2mib(
+                    virt,
+                    phys + 0x1000u64,
+                    PageTableFlags::empty(),
+                    mapper,
+                    frame_allocator,
+                )
+            },
+            Err(memory::HugePageError::Unaligned)
+        ));
+    });
+}
+
+#[test_case]
+fn lenient_unmap_skips_unmapped_pages() {
+    let first_page = Page::containing_address(VirtAddr::new(0x_7777_0000_0000));
+    let pages = Page::range(first_page, first_page + 8);
+
+    memory::with_test_memory(|mapper, frame_allocator| {
+        // map every other page of the range
+        let mut frames = [None; 4];
+        for (page, slot) in pages.step_by(2).zip(frames.iter_mut()) {
+            let frame = frame_allocator
+                .allocate_frame()
+                .expect("no frames available");
+            unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT, frame_allocator) }
+                .expect("mapping the page failed")
+                .flush();
+            *slot = Some(frame);
+        }
+
+        let mut recording = Recording {
+            inner: frame_allocator,
+            allocated: [None; 4],
+            deallocated: [None; 4],
+        };
+        let unmapped = unsafe { memory::unmap_range_lenient(pages, mapper, &mut recording) };
+        assert_eq!(unmapped, 4);
+        // only the frames of the mapped pages were freed
+        assert_eq!(recording.deallocated, frames);
+        for page in pages {
+            assert!(matches!(
+                mapper.translate(page.start_address()),
+                TranslateResult::NotMapped
+            ));
+        }
+
+        // nothing is left to unmap
+        assert_eq!(
+            unsafe { memory::unmap_range_lenient(pages, mapper, &mut recording) },
+            0
+        );
+    });
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use trust::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+const MESSAGE: &str = "colored panic";
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_color::panic_message_colored...\t");
+    panic!("{}", MESSAGE);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::print_panic(info);
+
+    // the message is the last line of the panic output, which ends up in the second to last
+    // row after the trailing newline scrolled the screen
+    let row = 25 - 2;
+    let vga = 0xb8000 as *const u16;
+    for (col, expected) in MESSAGE.bytes().enumerate() {
+        let cell = unsafe { vga.add(row * 80 + col).read_volatile() };
+        let (ascii, color_code) = (cell as u8, (cell >> 8) as u8);
+        // white (0xf) on red (0x4)
+        if ascii != expected || color_code != 0x4f {
+            serial_println!("[failed]\n");
+            serial_println!(
+                "cell {} holds {:#x} with color {:#x}",
+                col,
+                ascii,
+                color_code
+            );
+            exit_qemu(QemuExitCode::Fail);
+            trust::hlt_forever();
+        }
+    }
+
+    serial_println!("\r[ok] panic_color::panic_message_colored");
+    exit_qemu(QemuExitCode::Success);
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+};
+use trust::{
+    exit_qemu,
+    serial::{ForcedWriter, SERIAL1},
+    serial_print, serial_println,
+    util::FixedString,
+    QemuExitCode,
+};
+
+const MESSAGE: &str = "panic while the serial port is locked";
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_serial::panic_with_serial_locked...\t");
+
+    // panic while holding the serial port, as a panic inside `serial_println!` would
+    core::mem::forget(SERIAL1.lock());
+    panic!("{}", MESSAGE);
+}
+
+/// Passes everything on to the serial port, also if it is locked, and keeps a copy.
+struct Capture(FixedString<256>);
+
+impl Write for Capture {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        ForcedWriter.write_str(s)?;
+        // a report longer than the copy is still checked for its start
+        let _ = self.0.write_str(s);
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // must not wait for the lock that is never released
+    let mut capture = Capture(FixedString::new());
+    trust::write_panic(&mut capture, info);
+    trust::log::dump();
+
+    // reaching this point means the report did not deadlock
+    unsafe { SERIAL1.force_unlock() };
+
+    // the report written to the serial port must carry the message
+    let report = capture.0;
+    if report.contains(MESSAGE) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("report without the panic message: {}", report);
+        exit_qemu(QemuExitCode::Fail);
+    }
+
+    // CPU never halts because we exit qemu before
+    trust::hlt_forever();
+}
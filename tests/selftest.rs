@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{heap, hlt_forever, memory, selftest};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    unsafe { memory::init_test_memory(boot_info) };
+
+    // initialize heap
+    memory::with_test_memory(|mapper, frame_allocator| heap::init(mapper, frame_allocator))
+        .expect("heap initialization failed.");
+
+    test_main();
+
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info)
+}
+
+/// Test that all self-test checks pass in qemu.
+#[test_case]
+fn selftest_all_checks_pass() {
+    memory::with_test_memory(|mapper, frame_allocator| {
+        let summary = selftest::run(mapper, frame_allocator);
+        assert_eq!(summary.passed, 5);
+        assert!(summary.all_passed());
+
+        // the checks clean up after themselves and can run again
+        assert!(selftest::run(mapper, frame_allocator).all_passed());
+    });
+}
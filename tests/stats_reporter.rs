@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use trust::{
+    counters, heap, hlt_forever, memory,
+    task::{self, executor::Executor, stats::stats_reporter, timer::sleep, Task},
+    time,
+};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // initialize heap
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+
+    test_main();
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info);
+}
+
+/// Test that a sleep lasts at least the requested number of ticks.
+#[test_case]
+fn sleep_waits_for_ticks() {
+    let mut executor = Executor::new();
+    let start = time::ticks();
+    executor.spawn(Task::new(sleep(time::ticks_to_duration(3))));
+    executor.run_n(1);
+    assert!(time::ticks() - start >= 2);
+}
+
+/// Test that sleeps are woken in the order of their deadlines, not of their creation.
+#[test_case]
+fn sleeps_finish_in_deadline_order() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    static ORDER: [AtomicUsize; 3] = [const { AtomicUsize::new(usize::MAX) }; 3];
+
+    let mut executor = Executor::new();
+    for (i, ticks) in [6, 2, 4].into_iter().enumerate() {
+        executor.spawn(Task::new(async move {
+            sleep(time::ticks_to_duration(ticks)).await;
+            ORDER[FINISHED.fetch_add(1, Ordering::SeqCst)].store(i, Ordering::SeqCst);
+        }));
+    }
+    executor.run_n(3);
+
+    let order = ORDER.each_ref().map(|i| i.load(Ordering::SeqCst));
+    assert_eq!(order, [1, 2, 0]);
+}
+
+/// Test that the reporter prints one report per interval.
+#[test_case]
+fn stats_reporter_reports_every_interval() {
+    const INTERVAL_TICKS: u64 = 4;
+
+    let mut executor = Executor::new();
+    let before = counters::get("stats_report");
+    executor.spawn(Task::new(stats_reporter(time::ticks_to_duration(
+        INTERVAL_TICKS,
+    ))));
+    // stop after two and a half intervals
+    executor.spawn(Task::new(sleep(time::ticks_to_duration(
+        INTERVAL_TICKS * 5 / 2,
+    ))));
+    assert_eq!(task::task_count(), 2);
+
+    executor.run_n(1);
+    assert_eq!(counters::get("stats_report") - before, 2);
+    // the reporter is still pending
+    assert_eq!(task::task_count(), 1);
+}
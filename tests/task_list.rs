@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(trust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    future::Future,
+    panic::PanicInfo,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use trust::{
+    heap, hlt_forever, memory,
+    task::{executor::Executor, timer::sleep, Task},
+    time,
+};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // initialize heap
+    heap::init(&mut mapper, &mut frame_allocator).expect("heap initialization failed.");
+
+    test_main();
+    hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info);
+}
+
+/// A future that is never woken after its first poll.
+struct Parked;
+
+impl Future for Parked {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        Poll::Pending
+    }
+}
+
+/// Test that the task listing reports the names and poll counts of the spawned tasks.
+#[test_case]
+fn list_tasks_counts_polls() {
+    let tick = time::ticks_to_duration(1);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::with_name("ticker", async move {
+        for _ in 0..3 {
+            sleep(tick).await;
+        }
+        Parked.await;
+    }));
+    executor.spawn(Task::new(Parked));
+    executor.spawn(Task::with_name("stopper", sleep(tick * 10)));
+
+    let tasks = executor.list_tasks();
+    assert_eq!(tasks.len(), 3);
+    assert!(tasks.iter().all(|task| task.polls == 0));
+    assert!(tasks[0].id < tasks[1].id && tasks[1].id < tasks[2].id);
+
+    // run until the stopper finishes, the ticker is done sleeping by then
+    executor.run_n(1);
+
+    let tasks = executor.list_tasks();
+    assert_eq!(tasks.len(), 2);
+    // once per sleep that is pending and once when each sleep is over
+    assert_eq!((tasks[0].name, tasks[0].polls), ("ticker", 4));
+    assert_eq!((tasks[1].name, tasks[1].polls), ("unnamed", 1));
+}
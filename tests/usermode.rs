@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(asm_const)]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+use trust::{
+    counters, exit_qemu, memory, serial_print, serial_println, usermode, vectors, QemuExitCode,
+};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("usermode::demo_program...\t");
+
+    // initialize IDT, GDT and enable external interrupts
+    trust::init();
+
+    // initialize paging
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    let (entry, stack) =
+        usermode::load_program(usermode::demo_program(), &mut mapper, &mut frame_allocator)
+            .expect("mapping the user program failed");
+
+    // the program is mapped user accessible, the pages behind it are not mapped
+    let flags = memory::access_flags(entry).expect("the user program is not mapped");
+    assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    let unmapped = usermode::USER_START + 0x10000;
+    assert_eq!(memory::access_flags(VirtAddr::new(unmapped)), None);
+    // writing from them fails instead of faulting in the kernel
+    let result: u64;
+    unsafe {
+        asm!(
+            "int {vector}",
+            vector = const vectors::SYSCALL,
+            inout("rax") usermode::SYS_WRITE => result,
+            in("rdi") unmapped,
+            in("rsi") 16u64,
+        );
+    }
+    assert_eq!(result, usermode::SYSCALL_ERROR);
+    usermode::set_exit_handler(exited);
+    unsafe { usermode::enter(entry, stack) };
+}
+
+/// Called on the kernel stack once the demo program exits.
+fn exited(code: u64) -> ! {
+    // the rejected write and the demo program's write and exit
+    if code == 0 && counters::get("syscall") == 3 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!(
+            "[failed]\nexit code {}, {} system calls",
+            code,
+            counters::get("syscall")
+        );
+        exit_qemu(QemuExitCode::Fail);
+    }
+    trust::hlt_forever();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    trust::test_panic_handler(info);
+}